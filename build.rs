@@ -0,0 +1,47 @@
+//! Generates the build time default configuration
+//!
+//! The settings are taken from the `TPA2016D2_CONFIG` environment variable, or
+//! from the file named by `TPA2016D2_CONFIG_FILE`, using the syntax of the
+//! `tpa2016_config!` macro, e.g. `TPA2016D2_CONFIG="gain: 12 dB, compression: 2:1"`.
+//!
+//! A relative `TPA2016D2_CONFIG_FILE` is resolved against the manifest
+//! directory of this crate, which for a dependency is in the cargo registry.
+
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=TPA2016D2_CONFIG");
+    println!("cargo:rerun-if-env-changed=TPA2016D2_CONFIG_FILE");
+
+    // The driver is built on either embedded-hal version
+    println!("cargo:rustc-check-cfg=cfg(driver)");
+    if env::var_os("CARGO_FEATURE_HAL").is_some() || env::var_os("CARGO_FEATURE_EH1").is_some() {
+        println!("cargo:rustc-cfg=driver");
+    }
+
+    let settings = match (
+        env::var("TPA2016D2_CONFIG"),
+        env::var("TPA2016D2_CONFIG_FILE"),
+    ) {
+        (Ok(_), Ok(_)) => {
+            panic!("only one of TPA2016D2_CONFIG and TPA2016D2_CONFIG_FILE can be set")
+        }
+        (Ok(settings), Err(_)) => settings,
+        (Err(_), Ok(path)) => {
+            let path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join(path);
+            println!("cargo:rerun-if-changed={}", path.display());
+            fs::read_to_string(&path).unwrap_or_else(|e| {
+                panic!(
+                    "failed to read TPA2016D2_CONFIG_FILE {}: {}",
+                    path.display(),
+                    e
+                )
+            })
+        }
+        (Err(_), Err(_)) => String::new(),
+    };
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("build_config.rs");
+    fs::write(out, format!("crate::tpa2016_config! {{ {} }}\n", settings)).unwrap();
+}
@@ -0,0 +1,23 @@
+//! Print the amplifier status on Linux, for example on a Raspberry Pi
+//!
+//! ```sh
+//! cargo run --example linux --features std -- /dev/i2c-1
+//! ```
+
+use std::error::Error;
+
+use linux_embedded_hal::I2cdev;
+use tpa2016d2::Tpa2016d2;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "/dev/i2c-1".into());
+    let mut amp = Tpa2016d2::new(I2cdev::new(path)?);
+
+    amp.probe()?;
+    amp.sync()?;
+    println!("{:?}", amp.config());
+    println!("{:?}", amp.get_faults()?);
+    Ok(())
+}
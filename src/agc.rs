@@ -0,0 +1,486 @@
+//! Automatic gain control configuration
+//!
+//! [`AgcConfig`] holds the settings of registers 2 to 7 that make up the AGC
+//! and [`design`] synthesizes one from high level targets, following the
+//! procedure from the datasheet:
+//!
+//! 1. Set the output limiter level from the maximum output level
+//! 2. Choose the compression ratio from the wanted dynamic range reduction
+//! 3. Choose max gain and fixed gain from the ambient noise level
+//! 4. Choose attack, release and hold times to match the compression
+
+use crate::limits::*;
+use crate::{hold_time_to_u6, release_time_to_u6, CompressionRatio};
+
+/// Settings of the automatic gain control, as register values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct AgcConfig {
+    /// Attack time, 6 bits
+    pub attack_time: u8,
+    /// Release time, 6 bits
+    pub release_time: u8,
+    /// Hold time, 6 bits
+    pub hold_time: u8,
+    /// Fixed gain, 6 bits two's complement dB
+    pub fixed_gain: u8,
+    /// Max gain, 18 dB + value, 0 to 12
+    pub max_gain: u8,
+    /// Output limiter level, -6.5 dBV + value * 0.5 dB, 5 bits
+    pub output_limiter_level: u8,
+    /// Output limiter enabled, it can only be disabled with a compression ratio of 1:1
+    pub output_limiter: bool,
+    pub compression_ratio: CompressionRatio,
+}
+
+/// The setting of an [`AgcConfig`] that is out of range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AgcConfigError {
+    AttackTime,
+    ReleaseTime,
+    HoldTime,
+    /// Fixed gain outside -28 to 30 dB, negative while compressing or above max gain
+    FixedGain,
+    MaxGain,
+    OutputLimiterLevel,
+    /// Output limiter disabled while compressing
+    OutputLimiter,
+}
+
+impl AgcConfig {
+    /// Check the settings against the ranges from the datasheet
+    pub const fn validate(&self) -> Result<(), AgcConfigError> {
+        if self.attack_time > TIME_CODE_MAX {
+            return Err(AgcConfigError::AttackTime);
+        }
+        if self.release_time > TIME_CODE_MAX {
+            return Err(AgcConfigError::ReleaseTime);
+        }
+        if self.hold_time > TIME_CODE_MAX {
+            return Err(AgcConfigError::HoldTime);
+        }
+        if self.max_gain > MAX_GAIN_CODE {
+            return Err(AgcConfigError::MaxGain);
+        }
+        if self.output_limiter_level > LIMITER_LEVEL_CODE_MAX {
+            return Err(AgcConfigError::OutputLimiterLevel);
+        }
+
+        let fixed_gain = match fixed_gain_db(self.fixed_gain) {
+            Some(gain) => gain,
+            None => return Err(AgcConfigError::FixedGain),
+        };
+        let compressing = !matches!(self.compression_ratio, CompressionRatio::Ratio1);
+        if compressing
+            && (fixed_gain < MIN_FIXED_GAIN_DB_COMPRESSED
+                || fixed_gain > max_gain_db(self.max_gain))
+        {
+            return Err(AgcConfigError::FixedGain);
+        }
+        if compressing && !self.output_limiter {
+            return Err(AgcConfigError::OutputLimiter);
+        }
+
+        Ok(())
+    }
+
+    /// Check the settings against the ranges from the datasheet, collecting
+    /// every violation instead of stopping at the first
+    ///
+    /// [`validate`](Self::validate) is const and allocation free for use in
+    /// const contexts and on the write path, this one is for reporting, e.g.
+    /// to show everything wrong with a configuration to a user at once. The
+    /// violations are in register order.
+    pub fn validate_all(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        let times = [
+            (Field::AttackTime, self.attack_time),
+            (Field::ReleaseTime, self.release_time),
+            (Field::HoldTime, self.hold_time),
+        ];
+        for &(field, val) in &times {
+            if val > TIME_CODE_MAX {
+                report.push(field, Reason::OutOfRange);
+            }
+        }
+
+        let compressing = !matches!(self.compression_ratio, CompressionRatio::Ratio1);
+        match fixed_gain_db(self.fixed_gain) {
+            None => report.push(Field::FixedGain, Reason::OutOfRange),
+            Some(gain) if compressing && gain < MIN_FIXED_GAIN_DB_COMPRESSED => {
+                report.push(Field::FixedGain, Reason::NegativeWithCompression)
+            }
+            Some(gain) if compressing && gain > max_gain_db(self.max_gain) => {
+                report.push(Field::FixedGain, Reason::AboveMaxGain)
+            }
+            Some(_) => (),
+        }
+        if self.output_limiter_level > LIMITER_LEVEL_CODE_MAX {
+            report.push(Field::OutputLimiterLevel, Reason::OutOfRange);
+        }
+        if compressing && !self.output_limiter {
+            report.push(Field::OutputLimiter, Reason::DisabledWithCompression);
+        }
+        if self.max_gain > MAX_GAIN_CODE {
+            report.push(Field::MaxGain, Reason::OutOfRange);
+        }
+
+        report
+    }
+}
+
+/// A user defined AGC preset, e.g. tuned for an enclosure
+///
+/// Written with [`Tpa2016d2::set_agc_custom`](crate::Tpa2016d2::set_agc_custom).
+/// [`AgcPreset::config`](crate::AgcPreset::config) gives the settings of a
+/// datasheet preset to start from.
+///
+/// ```
+/// use tpa2016d2::agc::CustomPreset;
+/// use tpa2016d2::AgcPreset;
+///
+/// let mut config = AgcPreset::Voice.config();
+/// config.hold_time = 4;
+/// let preset = CustomPreset::new("Voice, small enclosure", config);
+/// assert!(preset.config.validate().is_ok());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CustomPreset {
+    pub name: &'static str,
+    pub config: AgcConfig,
+}
+
+impl CustomPreset {
+    pub const fn new(name: &'static str, config: AgcConfig) -> Self {
+        CustomPreset { name, config }
+    }
+}
+
+/// A setting of an [`AgcConfig`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    AttackTime,
+    ReleaseTime,
+    HoldTime,
+    FixedGain,
+    MaxGain,
+    OutputLimiterLevel,
+    OutputLimiter,
+}
+
+impl Field {
+    /// Name of the setting
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::AttackTime => "attack time",
+            Field::ReleaseTime => "release time",
+            Field::HoldTime => "hold time",
+            Field::FixedGain => "fixed gain",
+            Field::MaxGain => "max gain",
+            Field::OutputLimiterLevel => "output limiter level",
+            Field::OutputLimiter => "output limiter",
+        }
+    }
+}
+
+/// Why a setting is invalid
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// Outside the range of the register field
+    OutOfRange,
+    /// The fixed gain is negative while the compression is enabled
+    NegativeWithCompression,
+    /// The fixed gain is above the max gain while the compression is enabled
+    AboveMaxGain,
+    /// The output limiter is disabled while the compression is enabled
+    DisabledWithCompression,
+}
+
+impl Reason {
+    /// Description of the problem
+    pub fn description(self) -> &'static str {
+        match self {
+            Reason::OutOfRange => "out of range",
+            Reason::NegativeWithCompression => "must not be negative with compression",
+            Reason::AboveMaxGain => "must not exceed max gain with compression",
+            Reason::DisabledWithCompression => "must not be disabled with compression",
+        }
+    }
+}
+
+/// A setting that is invalid and why
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub field: Field,
+    pub reason: Reason,
+}
+
+/// All violations found by [`AgcConfig::validate_all`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    // At most one violation per field
+    violations: [Option<Violation>; 7],
+    len: usize,
+}
+
+impl ValidationReport {
+    /// Number of violations
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True if there are no violations, the configuration is valid
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The violations, in register order
+    pub fn iter(&self) -> impl Iterator<Item = &Violation> {
+        self.violations[..self.len].iter().flatten()
+    }
+
+    fn push(&mut self, field: Field, reason: Reason) {
+        self.violations[self.len] = Some(Violation { field, reason });
+        self.len += 1;
+    }
+}
+
+/// Decode a max gain register value to dB
+pub(crate) const fn max_gain_db(val: u8) -> i8 {
+    *MAX_GAIN_DB_RANGE.start() + val as i8
+}
+
+/// Decode a fixed gain register value to dB
+pub(crate) const fn fixed_gain_db(val: u8) -> Option<i8> {
+    match val {
+        0..=30 => Some(val as i8),
+        36..=63 => Some(val as i8 - 64),
+        _ => None,
+    }
+}
+
+/// Decode a fixed gain register value to dB, out of range values are clamped
+pub(crate) const fn fixed_gain_to_db(val: u8) -> i8 {
+    let db = ((val << 2) as i8) >> 2;
+    if db < MIN_FIXED_GAIN_DB {
+        MIN_FIXED_GAIN_DB
+    } else if db > MAX_FIXED_GAIN_DB {
+        MAX_FIXED_GAIN_DB
+    } else {
+        db
+    }
+}
+
+/// Encode a fixed gain in dB, clamped to -28 to 30 dB, as register value
+pub(crate) fn fixed_gain_code(db: i8) -> u8 {
+    (db.clamp(MIN_FIXED_GAIN_DB, MAX_FIXED_GAIN_DB) as u8) & 0x3F
+}
+
+/// Encode an output limiter level in 0.1 dBV as register value
+///
+/// Returns `None` outside -6.5 to 9 dBV or between the 0.5 dB steps.
+pub(crate) const fn limiter_level_code(dbv_x10: i16) -> Option<u8> {
+    let offset = dbv_x10 - *LIMITER_LEVEL_RANGE_DBV_X10.start();
+    if dbv_x10 > *LIMITER_LEVEL_RANGE_DBV_X10.end() || offset < 0 || offset % 5 != 0 {
+        return None;
+    }
+    Some((offset / 5) as u8)
+}
+
+/// Decode an output limiter level register value to 0.1 dBV
+pub(crate) const fn limiter_level_dbv_x10(val: u8) -> i16 {
+    *LIMITER_LEVEL_RANGE_DBV_X10.start() + 5 * (val & LIMITER_LEVEL_CODE_MAX) as i16
+}
+
+/// Amount of dynamic range reduction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dynamics {
+    Light,
+    Medium,
+    Heavy,
+}
+
+/// Ambient noise level where the device is used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AmbientNoise {
+    Quiet,
+    Moderate,
+    Loud,
+}
+
+/// High level targets for [`design`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AgcTargets {
+    /// Maximum output level in 0.1 dBV, -65 to 90
+    pub max_output_dbv_x10: i16,
+    pub dynamics: Dynamics,
+    pub ambient_noise: AmbientNoise,
+}
+
+/// Synthesize an AGC configuration from high level targets
+///
+/// The limiter level is rounded down to the nearest 0.5 dB step so the
+/// maximum output level is never exceeded.
+pub fn design(targets: &AgcTargets) -> Result<AgcConfig, AgcConfigError> {
+    if !LIMITER_LEVEL_RANGE_DBV_X10.contains(&targets.max_output_dbv_x10) {
+        return Err(AgcConfigError::OutputLimiterLevel);
+    }
+    let output_limiter_level = ((targets.max_output_dbv_x10 + 65) / 5) as u8;
+
+    // Release and hold times in 0.1 ms
+    let (compression_ratio, attack_time, release_time, hold_time) = match targets.dynamics {
+        Dynamics::Light => (CompressionRatio::Ratio2, 0b00_0110, 32880, 0),
+        Dynamics::Medium => (CompressionRatio::Ratio4, 0b00_0011, 16440, 0),
+        Dynamics::Heavy => (CompressionRatio::Ratio8, 0b00_0010, 9864, 1370),
+    };
+
+    // Max gain is 18 dB + value
+    let (max_gain, fixed_gain) = match targets.ambient_noise {
+        AmbientNoise::Quiet => (6, 6),
+        AmbientNoise::Moderate => (9, 12),
+        AmbientNoise::Loud => (12, 18),
+    };
+
+    let config = AgcConfig {
+        attack_time,
+        release_time: release_time_to_u6(release_time),
+        hold_time: hold_time_to_u6(hold_time),
+        fixed_gain,
+        max_gain,
+        output_limiter_level,
+        output_limiter: true,
+        compression_ratio,
+    };
+
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    #[test]
+    fn design_limiter_level() {
+        let mut targets = AgcTargets {
+            max_output_dbv_x10: 65,
+            dynamics: Dynamics::Medium,
+            ambient_noise: AmbientNoise::Quiet,
+        };
+        assert_eq!(design(&targets).unwrap().output_limiter_level, 0b11010);
+
+        targets.max_output_dbv_x10 = -65;
+        assert_eq!(design(&targets).unwrap().output_limiter_level, 0);
+
+        targets.max_output_dbv_x10 = 89;
+        assert_eq!(design(&targets).unwrap().output_limiter_level, 30);
+
+        targets.max_output_dbv_x10 = 91;
+        assert_eq!(design(&targets), Err(AgcConfigError::OutputLimiterLevel));
+    }
+
+    #[test]
+    fn design_is_valid() {
+        let dynamics = [Dynamics::Light, Dynamics::Medium, Dynamics::Heavy];
+        let noise = [
+            AmbientNoise::Quiet,
+            AmbientNoise::Moderate,
+            AmbientNoise::Loud,
+        ];
+
+        for &dynamics in &dynamics {
+            for &ambient_noise in &noise {
+                let targets = AgcTargets {
+                    max_output_dbv_x10: 0,
+                    dynamics,
+                    ambient_noise,
+                };
+                assert!(design(&targets).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn validate_fixed_gain() {
+        let mut config = AgcConfig {
+            attack_time: 5,
+            release_time: 11,
+            hold_time: 0,
+            fixed_gain: 0b11_1100, // -4 dB
+            max_gain: 12,
+            output_limiter_level: 26,
+            output_limiter: true,
+            compression_ratio: CompressionRatio::Ratio4,
+        };
+        assert_eq!(config.validate(), Err(AgcConfigError::FixedGain));
+
+        config.compression_ratio = CompressionRatio::Ratio1;
+        assert_eq!(config.validate(), Ok(()));
+
+        config.fixed_gain = 31;
+        assert_eq!(config.validate(), Err(AgcConfigError::FixedGain));
+    }
+
+    #[test]
+    fn validate_output_limiter() {
+        let mut config = Config::DEFAULT.agc;
+        config.output_limiter = false;
+        assert_eq!(config.validate(), Err(AgcConfigError::OutputLimiter));
+        assert!(config.validate_all().iter().eq([Violation {
+            field: Field::OutputLimiter,
+            reason: Reason::DisabledWithCompression,
+        }]
+        .iter()));
+
+        config.compression_ratio = CompressionRatio::Ratio1;
+        assert_eq!(config.validate(), Ok(()));
+        assert!(config.validate_all().is_empty());
+    }
+
+    #[test]
+    fn validate_all() {
+        let mut config = AgcConfig {
+            attack_time: 0x40,
+            release_time: 11,
+            hold_time: 0,
+            fixed_gain: 0b11_1100, // -4 dB
+            max_gain: 13,
+            output_limiter_level: 32,
+            output_limiter: true,
+            compression_ratio: CompressionRatio::Ratio4,
+        };
+
+        // In register order
+        let report = config.validate_all();
+        let violations = [
+            Violation {
+                field: Field::AttackTime,
+                reason: Reason::OutOfRange,
+            },
+            Violation {
+                field: Field::FixedGain,
+                reason: Reason::NegativeWithCompression,
+            },
+            Violation {
+                field: Field::OutputLimiterLevel,
+                reason: Reason::OutOfRange,
+            },
+            Violation {
+                field: Field::MaxGain,
+                reason: Reason::OutOfRange,
+            },
+        ];
+        assert_eq!(report.len(), 4);
+        assert!(report.iter().eq(violations.iter()));
+
+        config.attack_time = 5;
+        config.max_gain = 12;
+        config.fixed_gain = 6;
+        config.output_limiter_level = 26;
+        assert!(config.validate_all().is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+//! Async driver
+//!
+//! Requires the `async` feature. A driver on an [`embedded_hal_async::i2c::I2c`]
+//! bus has the register setters and readers of the blocking driver as async
+//! functions, generated from the same code:
+//!
+//! ```ignore
+//! let mut amp = Tpa2016d2::new_async(i2c);
+//! amp.sync().await?;
+//! amp.gain(12).await?;
+//!
+//! // Get the bus back
+//! let i2c = amp.release().into_inner();
+//! ```
+//!
+//! The getters, the configuration of the driver and [`Tpa2016d2::core`] for
+//! operations without an async version are shared with the blocking driver.
+
+use embedded_hal_async::i2c::I2c;
+
+use crate::agc;
+use crate::limits;
+use crate::regmap::{self, Register};
+use crate::sansio::{Command, Commands};
+use crate::{
+    attack_time_to_u6, check, hold_time_to_u6, release_time_to_u6, AgcConfig, AgcPreset,
+    CompressionRatio, Config, ConfigPatch, Error, Faults, NoiseGateThreshold, Status, Tpa2016d2,
+};
+
+/// An embedded-hal-async [`I2c`] bus for the driver
+pub struct AsyncI2c<I2C>(pub I2C);
+
+impl<I2C> AsyncI2c<I2C> {
+    /// Return the wrapped bus
+    pub fn into_inner(self) -> I2C {
+        self.0
+    }
+}
+
+impl<I2C: I2c> Tpa2016d2<AsyncI2c<I2C>> {
+    /// Creates an async driver
+    pub fn new_async(i2c: I2C) -> Self {
+        Tpa2016d2::new(AsyncI2c(i2c))
+    }
+}
+
+impl<I2C, E> Tpa2016d2<AsyncI2c<I2C>>
+where
+    I2C: I2c<Error = E>,
+{
+    register_ops!(async await);
+
+    async fn bus_read_registers(&mut self, reg: Register, values: &mut [u8]) -> Result<(), E> {
+        let len = values.len().min(reg.remaining());
+        self.i2c
+            .0
+            .write_read(self.address, &[reg.addr()], &mut values[..len])
+            .await
+    }
+
+    async fn bus_write_registers(&mut self, reg: Register, values: &[u8]) -> Result<(), E> {
+        let values = &values[..values.len().min(reg.remaining())];
+        let mut bytes = [reg.addr(); 8];
+        bytes[1..=values.len()].copy_from_slice(values);
+        self.i2c
+            .0
+            .write(self.address, &bytes[..=values.len()])
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::blocking::i2c::{Write, WriteRead};
+    use embedded_hal_async::i2c::{ErrorKind, ErrorType, Operation};
+
+    use super::*;
+    use crate::mock::{block_on, MockI2c};
+
+    impl ErrorType for MockI2c {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for MockI2c {
+        async fn transaction(
+            &mut self,
+            addr: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), ErrorKind> {
+            match operations {
+                [Operation::Write(bytes)] => Write::write(self, addr, bytes),
+                [Operation::Write(bytes), Operation::Read(buffer)] => {
+                    WriteRead::write_read(self, addr, bytes, buffer)
+                }
+                // The driver only writes, or writes then reads
+                _ => return Err(ErrorKind::Other),
+            }
+            .map_err(|_| ErrorKind::Other)
+        }
+    }
+
+    #[test]
+    fn async_driver() {
+        let mut amp = Tpa2016d2::new_async(MockI2c::new());
+        block_on(amp.gain(12)).unwrap();
+        block_on(amp.set_agc_preset(AgcPreset::Jazz)).unwrap();
+        assert_eq!(block_on(amp.set_max_gain(16)), Err(Error::InvalidValue));
+        block_on(amp.sync()).unwrap();
+        assert_eq!(block_on(amp.get_faults()).unwrap(), Faults::default());
+        assert_eq!(amp.agc_config(), AgcPreset::Jazz.config());
+
+        let i2c = amp.release().into_inner();
+        assert_eq!(i2c.writes[0], (5, 12));
+        assert_eq!(i2c.transactions, 2);
+        assert_eq!(i2c.reads, 8);
+    }
+
+    #[test]
+    fn burst_read_past_last_register() {
+        let mut amp = Tpa2016d2::new_async(MockI2c::new());
+        let mut values = [0u8; 4];
+        block_on(amp.bus_read_registers(Register::Agc1, &mut values)).unwrap();
+
+        assert_eq!(values, [0x3A, 0xC2, 0x00, 0x00]);
+        assert_eq!(amp.release().into_inner().reads, 2);
+    }
+}
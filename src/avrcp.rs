@@ -0,0 +1,213 @@
+//! AVRCP absolute volume mapping
+//!
+//! Maps the Bluetooth AVRCP absolute volume, 0 to 127, onto the fixed gain so
+//! the integration with a Bluetooth stack is a single call per volume event:
+//!
+//! ```
+//! # use tpa2016d2::control::FakeAmp;
+//! use tpa2016d2::avrcp::{AvrcpVolume, Curve};
+//!
+//! # let mut amp = FakeAmp::new();
+//! let mut volume = AvrcpVolume::new(-10, 24, Curve::LinearDb).deadband(2);
+//!
+//! // On an AVRCP SetAbsoluteVolume command
+//! volume.set(&mut amp, 100)?;
+//! # Ok::<(), tpa2016d2::control::FakeError>(())
+//! ```
+//!
+//! With compression enabled the fixed gain must be at least 0 dB, so use a
+//! non-negative minimum gain in that case.
+
+use crate::agc::fixed_gain_code;
+use crate::Tpa2016Control;
+
+/// Highest AVRCP absolute volume
+pub const AVRCP_MAX: u8 = 127;
+
+/// Volume to gain curve
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Curve<'a> {
+    /// Gain in dB proportional to the volume
+    LinearDb,
+    /// Piecewise linear through `(volume, dB)` points, sorted on volume
+    ///
+    /// The points are given in the dB range of the mapping, volumes outside
+    /// the first and last points use the gain of that point.
+    Points(&'a [(u8, i8)]),
+}
+
+/// Mapping from AVRCP absolute volume to fixed gain
+#[derive(Clone, Copy, Debug)]
+pub struct AvrcpVolume<'a> {
+    min_db: i8,
+    max_db: i8,
+    curve: Curve<'a>,
+    deadband: u8,
+    mute_at_zero: bool,
+    last: Option<u8>,
+}
+
+impl<'a> AvrcpVolume<'a> {
+    /// Map volume 0 to `min_db` and 127 to `max_db`, clamped to -28 to 30 dB
+    pub fn new(min_db: i8, max_db: i8, curve: Curve<'a>) -> Self {
+        AvrcpVolume {
+            min_db: min_db.clamp(-28, 30),
+            max_db: max_db.clamp(-28, 30),
+            curve,
+            deadband: 0,
+            mute_at_zero: true,
+            last: None,
+        }
+    }
+
+    /// Ignore volume changes of at most `steps` from the last applied volume
+    ///
+    /// Filters out jitter from phones stepping back and forth. Volume 0 and
+    /// 127 are always applied.
+    pub fn deadband(mut self, steps: u8) -> Self {
+        self.deadband = steps;
+        self
+    }
+
+    /// Disable the speakers at volume 0 instead of using the minimum gain
+    ///
+    /// Enabled by default. Both speakers are enabled again on the next non-zero
+    /// volume.
+    pub fn mute_at_zero(mut self, mute: bool) -> Self {
+        self.mute_at_zero = mute;
+        self
+    }
+
+    /// The last applied volume
+    pub fn volume(&self) -> Option<u8> {
+        self.last
+    }
+
+    /// The gain in dB for `volume`
+    pub fn gain_db(&self, volume: u8) -> i8 {
+        let volume = volume.min(AVRCP_MAX) as i32;
+        let db = match self.curve {
+            Curve::LinearDb => {
+                let span = self.max_db as i32 - self.min_db as i32;
+                self.min_db as i32 + div_round(span * volume, AVRCP_MAX as i32)
+            }
+            Curve::Points(points) => interpolate(points, volume),
+        };
+        db.clamp(
+            self.min_db.min(self.max_db) as i32,
+            self.max_db.max(self.min_db) as i32,
+        ) as i8
+    }
+
+    /// Apply `volume` to the amplifier
+    ///
+    /// Returns false if the change was within the deadband and nothing was
+    /// written.
+    pub fn set<A: Tpa2016Control>(&mut self, amp: &mut A, volume: u8) -> Result<bool, A::Error> {
+        let volume = volume.min(AVRCP_MAX);
+
+        if let Some(last) = self.last {
+            let endpoint = volume == 0 || volume == AVRCP_MAX;
+            let diff = (volume as i16 - last as i16).unsigned_abs();
+            if volume == last || (!endpoint && diff <= self.deadband as u16) {
+                return Ok(false);
+            }
+        }
+
+        if self.mute_at_zero && volume == 0 {
+            amp.speaker_enable(false, false)?;
+        } else {
+            if self.mute_at_zero && self.last == Some(0) {
+                amp.speaker_enable(true, true)?;
+            }
+            amp.gain(fixed_gain_code(self.gain_db(volume)))?;
+        }
+
+        self.last = Some(volume);
+        Ok(true)
+    }
+}
+
+pub(crate) fn div_round(num: i32, den: i32) -> i32 {
+    if num >= 0 {
+        (num + den / 2) / den
+    } else {
+        (num - den / 2) / den
+    }
+}
+
+fn interpolate(points: &[(u8, i8)], volume: i32) -> i32 {
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return 0,
+    };
+    if volume <= first.0 as i32 {
+        return first.1 as i32;
+    }
+
+    for pair in points.windows(2) {
+        let (v0, db0) = (pair[0].0 as i32, pair[0].1 as i32);
+        let (v1, db1) = (pair[1].0 as i32, pair[1].1 as i32);
+        if volume <= v1 && v1 > v0 {
+            return db0 + div_round((db1 - db0) * (volume - v0), v1 - v0);
+        }
+    }
+
+    last.1 as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::FakeAmp;
+    use crate::ConfigChange;
+
+    #[test]
+    fn linear_curve() {
+        let map = AvrcpVolume::new(-28, 30, Curve::LinearDb);
+        assert_eq!(map.gain_db(0), -28);
+        assert_eq!(map.gain_db(64), 1);
+        assert_eq!(map.gain_db(127), 30);
+        assert_eq!(map.gain_db(200), 30);
+    }
+
+    #[test]
+    fn points_curve() {
+        const POINTS: &[(u8, i8)] = &[(0, -20), (64, 10), (127, 20)];
+        let map = AvrcpVolume::new(-20, 20, Curve::Points(POINTS));
+        assert_eq!(map.gain_db(0), -20);
+        assert_eq!(map.gain_db(32), -5);
+        assert_eq!(map.gain_db(64), 10);
+        assert_eq!(map.gain_db(127), 20);
+    }
+
+    #[test]
+    fn deadband_and_mute() {
+        let mut amp = FakeAmp::new();
+        let mut map = AvrcpVolume::new(0, 30, Curve::LinearDb).deadband(2);
+
+        assert_eq!(map.set(&mut amp, 100), Ok(true));
+        assert_eq!(map.set(&mut amp, 102), Ok(false));
+        assert_eq!(map.set(&mut amp, 98), Ok(false));
+        assert_eq!(map.set(&mut amp, 97), Ok(true));
+        assert_eq!(map.set(&mut amp, 0), Ok(true));
+        assert_eq!(map.set(&mut amp, 50), Ok(true));
+
+        assert_eq!(
+            amp.calls(),
+            [
+                ConfigChange::Gain(24),
+                ConfigChange::Gain(23),
+                ConfigChange::SpeakerEnable {
+                    left: false,
+                    right: false
+                },
+                ConfigChange::SpeakerEnable {
+                    left: true,
+                    right: true
+                },
+                ConfigChange::Gain(12),
+            ]
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Excamera I2CDriver USB adapter
+//!
+//! The adapter is a serial port on Linux, Windows and macOS, `/dev/ttyUSB0`,
+//! `COM3` or `/dev/cu.usbserial-*`. [`I2cDriver`] implements the embedded-hal
+//! 1.0 `I2c` trait on top of its serial protocol.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use embedded_hal_1::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use serialport::SerialPort;
+
+/// An I2CDriver on a serial port
+pub struct I2cDriver {
+    port: Box<dyn SerialPort>,
+}
+
+impl I2cDriver {
+    /// Open the adapter on the serial port `path`
+    pub fn open(path: &str) -> serialport::Result<Self> {
+        let port = serialport::new(path, 1_000_000)
+            .timeout(Duration::from_millis(100))
+            .open()?;
+        Ok(I2cDriver { port })
+    }
+
+    fn command(&mut self, bytes: &[u8], response: &mut [u8]) -> io::Result<()> {
+        self.port.write_all(bytes)?;
+        self.port.read_exact(response)
+    }
+
+    /// Start a transfer to `addr`, the response bit 0 is the acknowledge
+    fn start(&mut self, addr: u8, read: bool) -> Result<(), ErrorKind> {
+        let mut status = [0];
+        self.command(&[b's', addr << 1 | read as u8], &mut status)
+            .map_err(|_| ErrorKind::Bus)?;
+        if status[0] & 1 == 0 {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), ErrorKind> {
+        for chunk in bytes.chunks(64) {
+            let mut status = [0];
+            self.port
+                .write_all(&[0xC0 + (chunk.len() - 1) as u8])
+                .and_then(|()| self.command(chunk, &mut status))
+                .map_err(|_| ErrorKind::Bus)?;
+            if status[0] & 1 == 0 {
+                return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, buffer: &mut [u8]) -> Result<(), ErrorKind> {
+        for chunk in buffer.chunks_mut(64) {
+            self.command(&[0x80 + (chunk.len() - 1) as u8], chunk)
+                .map_err(|_| ErrorKind::Bus)?;
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), ErrorKind> {
+        self.port.write_all(b"p").map_err(|_| ErrorKind::Bus)
+    }
+}
+
+impl ErrorType for I2cDriver {
+    type Error = ErrorKind;
+}
+
+impl I2c for I2cDriver {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), ErrorKind> {
+        let result = operations.iter_mut().try_for_each(|op| match op {
+            Operation::Write(bytes) => {
+                self.start(address, false)?;
+                self.write_bytes(bytes)
+            }
+            Operation::Read(buffer) => {
+                self.start(address, true)?;
+                self.read_bytes(buffer)
+            }
+        });
+        self.stop()?;
+        result
+    }
+}
@@ -0,0 +1,266 @@
+//! Configure a TPA2016D2 from the command line
+//!
+//! ```sh
+//! tpa2016ctl [--bus /dev/i2c-1 | --i2cdriver PORT] [--address 0x58] [--json] <command>
+//! ```
+//!
+//! On Linux the device is reached through an i2c-dev bus, `/dev/i2c-1` by
+//! default, which includes USB adapters with a kernel driver like the
+//! i2c-tiny-usb. `--i2cdriver` uses an Excamera I2CDriver USB adapter on its
+//! serial port instead, on Linux, Windows and macOS.
+//!
+//! Commands:
+//!
+//!  - `dump`: print registers 1 to 7
+//!  - `status`: print the speakers, shutdown, faults, gain and preset
+//!  - `gain <dB>`: set the fixed gain, -28 to 30 dB
+//!  - `preset <name>`: write the AGC settings of a datasheet preset
+//!  - `monitor [ms]`: print the faults whenever they change, polling every
+//!    `ms` milliseconds, 500 by default
+//!
+//! With `--json` the output of `dump`, `status` and `monitor` is one JSON
+//! object per line, for scripts.
+
+mod i2cdriver;
+
+use std::error::Error;
+use std::fmt;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use embedded_hal_1::i2c::{ErrorKind, I2c};
+#[cfg(target_os = "linux")]
+use linux_embedded_hal::{i2cdev::linux::LinuxI2CError, I2cdev};
+use tpa2016d2::regmap::RegisterDump;
+use tpa2016d2::{AgcPreset, Faults, I2cBus, Register, Status, Tpa2016d2};
+
+use i2cdriver::I2cDriver;
+
+const USAGE: &str =
+    "usage: tpa2016ctl [--bus PATH | --i2cdriver PORT] [--address ADDR] [--json] <command>
+
+commands:
+  dump           print registers 1 to 7
+  status         print the speakers, shutdown, faults, gain and preset
+  gain <dB>      set the fixed gain, -28 to 30 dB
+  preset <name>  write an AGC preset: Pop, Classical, Jazz, Rap, Rock or Voice
+  monitor [ms]   print the faults whenever they change
+
+--bus PATH        i2c-dev bus, Linux only, /dev/i2c-1 by default
+--i2cdriver PORT  Excamera I2CDriver USB adapter on serial port PORT
+--json            print dump, status and monitor output as JSON";
+
+/// The bus the device is reached through
+enum Bus {
+    #[cfg(target_os = "linux")]
+    Dev(I2cdev),
+    I2cDriver(I2cDriver),
+}
+
+enum BusError {
+    #[cfg(target_os = "linux")]
+    Dev(LinuxI2CError),
+    I2cDriver(ErrorKind),
+}
+
+impl fmt::Debug for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(target_os = "linux")]
+            BusError::Dev(err) => write!(f, "{}", err),
+            BusError::I2cDriver(kind) => write!(f, "I2CDriver: {}", kind),
+        }
+    }
+}
+
+impl Bus {
+    fn open(bus: Option<String>, i2cdriver: Option<String>) -> Result<Bus, Box<dyn Error>> {
+        match (bus, i2cdriver) {
+            (Some(_), Some(_)) => usage(),
+            (_, Some(port)) => Ok(Bus::I2cDriver(I2cDriver::open(&port)?)),
+            #[cfg(target_os = "linux")]
+            (bus, None) => Ok(Bus::Dev(I2cdev::new(
+                bus.as_deref().unwrap_or("/dev/i2c-1"),
+            )?)),
+            #[cfg(not(target_os = "linux"))]
+            (_, None) => usage(),
+        }
+    }
+}
+
+impl I2cBus for Bus {
+    type Error = BusError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), BusError> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Bus::Dev(i2c) => Write::write(i2c, addr, bytes).map_err(BusError::Dev),
+            Bus::I2cDriver(i2c) => I2c::write(i2c, addr, bytes).map_err(BusError::I2cDriver),
+        }
+    }
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), BusError> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Bus::Dev(i2c) => WriteRead::write_read(i2c, addr, bytes, buffer).map_err(BusError::Dev),
+            Bus::I2cDriver(i2c) => {
+                I2c::write_read(i2c, addr, bytes, buffer).map_err(BusError::I2cDriver)
+            }
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2)
+}
+
+fn parse_address(arg: &str) -> Option<u8> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => arg.parse().ok(),
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut bus = None;
+    let mut i2cdriver = None;
+    let mut address = None;
+    let mut json = false;
+
+    let mut args = std::env::args().skip(1);
+    let mut command = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bus" => bus = Some(args.next().unwrap_or_else(|| usage())),
+            "--i2cdriver" => i2cdriver = Some(args.next().unwrap_or_else(|| usage())),
+            "--address" => {
+                address = args.next().and_then(|arg| parse_address(&arg));
+                if address.is_none() {
+                    usage();
+                }
+            }
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => command.push(arg),
+        }
+    }
+
+    let i2c = Bus::open(bus, i2cdriver)?;
+    let mut amp = match address {
+        Some(address) => Tpa2016d2::new_with_address(i2c, address),
+        None => Tpa2016d2::new(i2c),
+    };
+    amp.probe()?;
+    amp.sync()?;
+
+    match command.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["dump"] if json => println!("{}", dump_json(&amp.dump_registers()?)),
+        ["dump"] => print!("{}", RegisterDump(amp.dump_registers()?)),
+        ["status"] if json => println!("{}", status_json(&amp.read_status()?)),
+        ["status"] => println!("{:#?}", amp.read_status()?),
+        ["gain", db] => {
+            let db = db.parse().unwrap_or_else(|_| usage());
+            amp.gain_db(db)?;
+        }
+        ["preset", name] => {
+            let preset = AgcPreset::ALL
+                .iter()
+                .copied()
+                .find(|preset| preset.name().eq_ignore_ascii_case(name))
+                .unwrap_or_else(|| usage());
+            amp.set_agc_preset(preset)?;
+        }
+        ["monitor"] => monitor(&mut amp, 500, json)?,
+        ["monitor", ms] => monitor(&mut amp, ms.parse().unwrap_or_else(|_| usage()), json)?,
+        _ => usage(),
+    }
+    Ok(())
+}
+
+fn monitor(amp: &mut Tpa2016d2<Bus>, interval_ms: u64, json: bool) -> Result<(), Box<dyn Error>> {
+    let mut last = None;
+    loop {
+        let faults = amp.get_faults()?;
+        if last != Some(faults) {
+            if json {
+                println!("{{{}}}", faults_json(&faults));
+            } else {
+                println!("{:?}", faults);
+            }
+            last = Some(faults);
+        }
+        thread::sleep(Duration::from_millis(interval_ms));
+    }
+}
+
+fn dump_json(regs: &[u8; 7]) -> String {
+    let regs: Vec<_> = Register::ALL
+        .iter()
+        .zip(regs)
+        .map(|(reg, value)| {
+            format!(
+                r#"{{"addr":{},"name":"{}","value":{}}}"#,
+                reg.addr(),
+                reg.name(),
+                value
+            )
+        })
+        .collect();
+    format!(r#"{{"registers":[{}]}}"#, regs.join(","))
+}
+
+fn faults_json(faults: &Faults) -> String {
+    format!(
+        r#""fault_l":{},"fault_r":{},"thermal":{}"#,
+        faults.fault_l, faults.fault_r, faults.thermal
+    )
+}
+
+fn status_json(status: &Status) -> String {
+    let preset = match status.preset {
+        Some(preset) => format!(r#""{}""#, preset.name()),
+        None => "null".into(),
+    };
+    format!(
+        r#"{{"speaker_left":{},"speaker_right":{},"shutdown":{},{},"fixed_gain_db":{},"preset":{}}}"#,
+        status.speaker_left,
+        status.speaker_right,
+        status.shutdown,
+        faults_json(&status.faults),
+        status.fixed_gain_db,
+        preset
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let dump = dump_json(&[0xC3, 0x05, 0x0B, 0x00, 0x06, 0x3A, 0xC2]);
+        assert!(dump.starts_with(r#"{"registers":[{"addr":1,"name":"Control","value":195},"#));
+        let status = Status {
+            speaker_left: true,
+            speaker_right: false,
+            shutdown: false,
+            faults: Faults {
+                thermal: true,
+                ..Faults::default()
+            },
+            fixed_gain_db: 6,
+            preset: Some(AgcPreset::Jazz),
+        };
+        assert_eq!(
+            status_json(&status),
+            r#"{"speaker_left":true,"speaker_right":false,"shutdown":false,"fault_l":false,"fault_r":false,"thermal":true,"fixed_gain_db":6,"preset":"Jazz"}"#
+        );
+    }
+}
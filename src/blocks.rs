@@ -0,0 +1,263 @@
+//! Handles for the functional blocks of the device
+//!
+//! The settings are grouped the way the datasheet organizes the device. Each
+//! handle borrows the driver, and the setters return the handle so calls can
+//! be chained:
+//!
+//! ```
+//! use tpa2016d2::{CompressionRatio, NoiseGateThreshold, Tpa2016d2};
+//!
+//! # fn configure<I2C, E>(amp: &mut Tpa2016d2<I2C>) -> Result<(), tpa2016d2::Error<E>>
+//! # where
+//! #     I2C: tpa2016d2::RegisterInterface<Error = E>,
+//! # {
+//! amp.agc()
+//!     .set_compression_ratio(CompressionRatio::Ratio4)?
+//!     .set_attack_time(2)?;
+//! amp.gate().set_threshold(NoiseGateThreshold::Ngt4mV)?.enable()?;
+//! amp.speakers().enable(true, true)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::agc::{self, AgcConfig};
+use crate::{
+    AgcPreset, CompressionRatio, Error, Faults, NoiseGateThreshold, RegisterInterface, Tpa2016d2,
+};
+
+/// The noise gate, see [`Tpa2016d2::gate`]
+pub struct NoiseGate<'a, I2C> {
+    amp: &'a mut Tpa2016d2<I2C>,
+}
+
+/// The output limiter, see [`Tpa2016d2::limiter`]
+pub struct Limiter<'a, I2C> {
+    amp: &'a mut Tpa2016d2<I2C>,
+}
+
+/// The automatic gain control, see [`Tpa2016d2::agc`]
+pub struct Agc<'a, I2C> {
+    amp: &'a mut Tpa2016d2<I2C>,
+}
+
+/// The speaker outputs, see [`Tpa2016d2::speakers`]
+pub struct Speakers<'a, I2C> {
+    amp: &'a mut Tpa2016d2<I2C>,
+}
+
+impl<I2C, E> Tpa2016d2<I2C>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    /// The noise gate settings
+    pub fn gate(&mut self) -> NoiseGate<'_, I2C> {
+        NoiseGate { amp: self }
+    }
+
+    /// Enable or disable the noise gate
+    #[deprecated(note = "use `set_noise_gate`, or the `gate` handle")]
+    pub fn noise_gate(&mut self, enable: bool) -> Result<(), Error<E>> {
+        self.set_noise_gate(enable)
+    }
+
+    /// The output limiter settings
+    pub fn limiter(&mut self) -> Limiter<'_, I2C> {
+        Limiter { amp: self }
+    }
+
+    /// The AGC settings
+    pub fn agc(&mut self) -> Agc<'_, I2C> {
+        Agc { amp: self }
+    }
+
+    /// The speaker outputs
+    pub fn speakers(&mut self) -> Speakers<'_, I2C> {
+        Speakers { amp: self }
+    }
+}
+
+impl<'a, I2C, E> NoiseGate<'a, I2C>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    /// Enable the noise gate
+    pub fn enable(&mut self) -> Result<&mut Self, Error<E>> {
+        self.amp.set_noise_gate(true)?;
+        Ok(self)
+    }
+
+    /// Disable the noise gate
+    pub fn disable(&mut self) -> Result<&mut Self, Error<E>> {
+        self.amp.set_noise_gate(false)?;
+        Ok(self)
+    }
+
+    /// Set the threshold below which the output is gated
+    pub fn set_threshold(&mut self, threshold: NoiseGateThreshold) -> Result<&mut Self, Error<E>> {
+        self.amp.noise_gate_threshold(threshold)?;
+        Ok(self)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.amp.noise_gate_enabled()
+    }
+
+    pub fn threshold(&self) -> NoiseGateThreshold {
+        self.amp.get_noise_gate_threshold()
+    }
+}
+
+impl<'a, I2C, E> Limiter<'a, I2C>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    /// Set the output limiter level, -6.5 dBV + value * 0.5 dB
+    pub fn set_level(&mut self, val: u8) -> Result<&mut Self, Error<E>> {
+        self.amp.output_limiter_level(val)?;
+        Ok(self)
+    }
+
+    /// The output limiter level register value
+    pub fn level(&self) -> u8 {
+        self.amp.get_output_limiter_level()
+    }
+
+    /// Enable the output limiter
+    pub fn enable(&mut self) -> Result<&mut Self, Error<E>> {
+        self.amp.output_limiter(true)?;
+        Ok(self)
+    }
+
+    /// Disable the output limiter, only allowed with a compression ratio of 1:1
+    pub fn disable(&mut self) -> Result<&mut Self, Error<E>> {
+        self.amp.output_limiter(false)?;
+        Ok(self)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.amp.output_limiter_enabled()
+    }
+}
+
+impl<'a, I2C, E> Agc<'a, I2C>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    pub fn set_attack_time(&mut self, val: u8) -> Result<&mut Self, Error<E>> {
+        self.amp.set_attack_time(val)?;
+        Ok(self)
+    }
+
+    pub fn set_release_time(&mut self, val: u8) -> Result<&mut Self, Error<E>> {
+        self.amp.set_release_time(val)?;
+        Ok(self)
+    }
+
+    pub fn set_hold_time(&mut self, val: u8) -> Result<&mut Self, Error<E>> {
+        self.amp.set_hold_time(val)?;
+        Ok(self)
+    }
+
+    /// Set the fixed gain, see [`Tpa2016d2::gain`]
+    pub fn set_fixed_gain(&mut self, gain: u8) -> Result<&mut Self, Error<E>> {
+        self.amp.gain(gain)?;
+        Ok(self)
+    }
+
+    pub fn set_compression_ratio(
+        &mut self,
+        ratio: CompressionRatio,
+    ) -> Result<&mut Self, Error<E>> {
+        self.amp.compression_ratio(ratio)?;
+        Ok(self)
+    }
+
+    pub fn set_preset(&mut self, preset: AgcPreset) -> Result<&mut Self, Error<E>> {
+        self.amp.set_agc_preset(preset)?;
+        Ok(self)
+    }
+
+    pub fn set_config(&mut self, config: &AgcConfig) -> Result<&mut Self, Error<E>> {
+        self.amp.set_agc_config(config)?;
+        Ok(self)
+    }
+
+    /// The current AGC settings, as register values
+    pub fn config(&self) -> AgcConfig {
+        self.amp.agc_config()
+    }
+
+    /// The fixed gain in dB
+    pub fn fixed_gain_db(&self) -> i8 {
+        agc::fixed_gain_to_db(self.amp.core.regmap.fixed_gain())
+    }
+
+    pub fn compression_ratio(&self) -> CompressionRatio {
+        self.amp.get_compression_ratio()
+    }
+
+    /// The preset last applied, if the settings still match it
+    pub fn preset(&self) -> Option<AgcPreset> {
+        self.amp.core.preset
+    }
+}
+
+impl<'a, I2C, E> Speakers<'a, I2C>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    /// Enable or disable the left and right speaker
+    pub fn enable(&mut self, left: bool, right: bool) -> Result<&mut Self, Error<E>> {
+        self.amp.speaker_enable(left, right)?;
+        Ok(self)
+    }
+
+    /// Read the fault flags from the device
+    pub fn faults(&mut self) -> Result<Faults, Error<E>> {
+        self.amp.get_faults()
+    }
+
+    pub fn left_enabled(&self) -> bool {
+        self.amp.core.regmap.spk_en_l()
+    }
+
+    pub fn right_enabled(&self) -> bool {
+        self.amp.core.regmap.spk_en_r()
+    }
+}
+
+#[cfg(all(test, feature = "hal"))]
+mod tests {
+    use super::*;
+    use crate::mock::MockI2c;
+
+    #[test]
+    fn chained_setters() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+
+        amp.agc()
+            .set_compression_ratio(CompressionRatio::Ratio8)
+            .unwrap()
+            .set_attack_time(2)
+            .unwrap();
+        amp.gate().disable().unwrap();
+        amp.speakers().enable(false, true).unwrap();
+
+        assert_eq!(amp.agc().compression_ratio(), CompressionRatio::Ratio8);
+        assert!(!amp.gate().is_enabled());
+        assert!(!amp.speakers().left_enabled());
+        assert_eq!(
+            amp.release().writes,
+            [(7, 0xC3), (2, 2), (1, 0xC2), (1, 0x82)]
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_noise_gate() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        amp.noise_gate(false).unwrap();
+        assert!(!amp.gate().is_enabled());
+        assert_eq!(amp.release().writes, [(1, 0xC2)]);
+    }
+}
@@ -0,0 +1,212 @@
+//! The bus interface of the driver
+//!
+//! The driver only needs [`I2cBus`], a plain write and a write followed by a
+//! read. With the `hal` feature it is implemented for every embedded-hal 0.2
+//! bus implementing `Write` and `WriteRead` with the same error type, which
+//! includes the shared bus proxies, and with the `eh1` feature for embedded-hal
+//! 1.0 buses wrapped in `I2cCompat`. Buses that do not
+//! fit, e.g. with differing error types, implement it directly:
+//!
+//! ```
+//! use tpa2016d2::{I2cBus, Tpa2016d2};
+//!
+//! struct Bus;
+//!
+//! impl I2cBus for Bus {
+//!     type Error = ();
+//!
+//!     fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), ()> {
+//!         // Send `bytes` to `addr`
+//!         Ok(())
+//!     }
+//!
+//!     fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+//!         // Send `bytes` to `addr`, then read into `buffer` with a repeated start
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut amp = Tpa2016d2::new(Bus);
+//! amp.gain(12).unwrap();
+//! ```
+//!
+//! The driver itself accesses the device through [`RegisterInterface`],
+//! implemented for every [`I2cBus`]. Transports that are not an I2C bus, e.g.
+//! a bridge chip with register level commands or a test double, implement
+//! it instead.
+
+#[cfg(feature = "hal")]
+use embedded_hal::blocking::i2c;
+
+use crate::Register;
+
+/// Register access to the device
+pub trait RegisterInterface {
+    type Error;
+
+    /// Read register `reg` of the device at `addr`
+    ///
+    /// `addr` is the address the driver was created with, transports that do
+    /// not address the device can ignore it.
+    fn read_register(&mut self, addr: u8, reg: Register) -> Result<u8, Self::Error>;
+
+    /// Read the registers from `reg` on into `buffer`
+    ///
+    /// Reads the registers one by one, transports with a burst read override
+    /// it. Only registers up to the last one, register 7, are read.
+    fn read_registers(
+        &mut self,
+        addr: u8,
+        reg: Register,
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let first = reg.addr() as usize - 1;
+        for (&reg, val) in Register::ALL[first..].iter().zip(buffer) {
+            *val = self.read_register(addr, reg)?;
+        }
+        Ok(())
+    }
+
+    /// Write `value` to register `reg` of the device at `addr`
+    fn write_register(&mut self, addr: u8, reg: Register, value: u8) -> Result<(), Self::Error>;
+
+    /// Write `values` to the registers from `reg` on
+    ///
+    /// Writes the registers one by one, transports with a burst write
+    /// override it. Values past the last register, register 7, are not
+    /// written.
+    fn write_registers(
+        &mut self,
+        addr: u8,
+        reg: Register,
+        values: &[u8],
+    ) -> Result<(), Self::Error> {
+        let first = reg.addr() as usize - 1;
+        for (&reg, &value) in Register::ALL[first..].iter().zip(values) {
+            self.write_register(addr, reg, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The I2C transactions used by the driver
+pub trait I2cBus {
+    type Error;
+
+    /// Write `bytes` to the device at `addr`
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// Write `bytes` to the device at `addr`, then read `buffer` in the same
+    /// transaction
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "hal")]
+impl<T, E> I2cBus for T
+where
+    T: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+{
+    type Error = E;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), E> {
+        i2c::Write::write(self, addr, bytes)
+    }
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), E> {
+        i2c::WriteRead::write_read(self, addr, bytes, buffer)
+    }
+}
+
+/// Registers are accessed with the register address auto-increment of the
+/// device, a burst read or write is one transaction
+impl<T: I2cBus> RegisterInterface for T {
+    type Error = T::Error;
+
+    fn read_register(&mut self, addr: u8, reg: Register) -> Result<u8, T::Error> {
+        let mut buf = [0u8; 1];
+        I2cBus::write_read(self, addr, &[reg.addr()], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_registers(
+        &mut self,
+        addr: u8,
+        reg: Register,
+        buffer: &mut [u8],
+    ) -> Result<(), T::Error> {
+        let len = buffer.len().min(reg.remaining());
+        I2cBus::write_read(self, addr, &[reg.addr()], &mut buffer[..len])
+    }
+
+    fn write_register(&mut self, addr: u8, reg: Register, value: u8) -> Result<(), T::Error> {
+        I2cBus::write(self, addr, &[reg.addr(), value])
+    }
+
+    fn write_registers(&mut self, addr: u8, reg: Register, values: &[u8]) -> Result<(), T::Error> {
+        let values = &values[..values.len().min(reg.remaining())];
+        let mut buf = [0u8; 8];
+        buf[0] = reg.addr();
+        buf[1..=values.len()].copy_from_slice(values);
+        I2cBus::write(self, addr, &buf[..=values.len()])
+    }
+}
+
+#[cfg(all(test, feature = "hal"))]
+mod tests {
+    use super::*;
+    use crate::mock::MockI2c;
+    use crate::{AgcPreset, Tpa2016d2};
+
+    /// Register file without an I2C bus
+    struct Registers([u8; 8]);
+
+    impl RegisterInterface for Registers {
+        type Error = ();
+
+        fn read_register(&mut self, _addr: u8, reg: Register) -> Result<u8, ()> {
+            Ok(self.0[reg.addr() as usize])
+        }
+
+        fn write_register(&mut self, _addr: u8, reg: Register, value: u8) -> Result<(), ()> {
+            self.0[reg.addr() as usize] = value;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn register_interface() {
+        let mut amp = Tpa2016d2::new(Registers([0; 8]));
+        amp.set_agc_preset(AgcPreset::Jazz).unwrap();
+        assert_eq!(amp.get_agc().unwrap(), AgcPreset::Jazz.config());
+        amp.sync().unwrap();
+        assert_eq!(amp.agc_config(), AgcPreset::Jazz.config());
+        assert_eq!(amp.release().0[2..], [0x06, 0x14, 0x00, 0x06, 0x3D, 0xC1]);
+    }
+
+    #[test]
+    fn burst_write_past_last_register() {
+        let mut i2c = MockI2c::new();
+        let addr = i2c.addr;
+
+        i2c.write_registers(addr, Register::Agc1, &[0x3C, 0xC2, 0x01])
+            .unwrap();
+        i2c.write_registers(addr, Register::Control, &[0xC3; 9])
+            .unwrap();
+
+        assert_eq!(i2c.writes[..2], [(6, 0x3C), (7, 0xC2)]);
+        assert_eq!(i2c.writes.len(), 9);
+    }
+
+    #[test]
+    fn burst_read_past_last_register() {
+        let mut i2c = MockI2c::new();
+        let addr = i2c.addr;
+
+        let mut values = [0u8; 4];
+        i2c.read_registers(addr, Register::Agc1, &mut values)
+            .unwrap();
+
+        assert_eq!(values, [0x3A, 0xC2, 0x00, 0x00]);
+        assert_eq!(i2c.reads, 2);
+    }
+}
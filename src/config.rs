@@ -0,0 +1,714 @@
+//! Complete device configuration
+//!
+//! [`Config`] holds every tunable setting and is written to the device with
+//! [`Tpa2016d2::apply_config`](crate::Tpa2016d2::apply_config). Configurations
+//! can be written with physical units using [`tpa2016_config!`], which checks
+//! the values at compile time:
+//!
+//! ```
+//! use tpa2016d2::{tpa2016_config, Config};
+//!
+//! const NIGHT: Config = tpa2016_config! {
+//!     gain: 6 dB,
+//!     compression: 4:1,
+//!     attack: 2.56 ms,
+//!     limiter: 6.5 dBV,
+//!     noise_gate: 4 mV,
+//! };
+//! ```
+//!
+//! Settings not given keep their power-on default. The available settings are
+//!
+//! | Setting       | Values                            |
+//! |---------------|-----------------------------------|
+//! | `left`        | `on`, `off`                       |
+//! | `right`       | `on`, `off`                       |
+//! | `gain`        | -28 to 30 `dB`                    |
+//! | `max_gain`    | 18 to 30 `dB`                     |
+//! | `compression` | `1:1`, `2:1`, `4:1`, `8:1`        |
+//! | `attack`      | 0 to 6.72 `ms`, per 6 dB          |
+//! | `release`     | 0 to 10357 `ms`, per 6 dB         |
+//! | `hold`        | 0 to 863 `ms`, 0 disables hold    |
+//! | `limiter`     | -6.5 to 9 `dBV` in 0.5 dB steps   |
+//! | `limiter`     | `off`, only with compression 1:1  |
+//! | `noise_gate`  | `off`, 1, 4, 10 or 20 `mV`        |
+//!
+//! An invalid setting fails the build:
+//!
+//! ```compile_fail
+//! const BAD: tpa2016d2::Config = tpa2016d2::tpa2016_config! { limiter: 10 dBV };
+//! ```
+
+use crate::agc::{fixed_gain_db, AgcConfig, AgcConfigError, ValidationReport};
+use crate::limits::{
+    LIMITER_LEVEL_CODE_MAX, MAX_FIXED_GAIN_DB, MAX_GAIN_CODE, MAX_GAIN_DB_RANGE, MIN_FIXED_GAIN_DB,
+    TIME_CODE_MAX,
+};
+use crate::regmap::{Register, RegisterMap, U2, U4, U5};
+use crate::{compression_ratio_from_bits, noise_gate_threshold_from_bits};
+use crate::{CompressionRatio, NoiseGateThreshold};
+
+/// Every tunable setting of the device
+///
+/// With the `serde` feature it can be stored in settings files. The values are
+/// not checked when deserializing, see [`Config::validate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Config {
+    pub speaker_left: bool,
+    pub speaker_right: bool,
+    pub noise_gate: bool,
+    pub noise_gate_threshold: NoiseGateThreshold,
+    pub agc: AgcConfig,
+}
+
+/// The default configuration, [`Config::BUILD_DEFAULT`]
+impl Default for Config {
+    fn default() -> Self {
+        Self::BUILD_DEFAULT
+    }
+}
+
+impl Config {
+    /// The power-on configuration of the device
+    pub const DEFAULT: Config = Config {
+        speaker_left: true,
+        speaker_right: true,
+        noise_gate: true,
+        noise_gate_threshold: NoiseGateThreshold::Ngt4mV,
+        agc: AgcConfig {
+            attack_time: 0x05,
+            release_time: 0x0B,
+            hold_time: 0x00,
+            fixed_gain: 0x06,
+            max_gain: 0b1100,
+            output_limiter_level: 0b11010,
+            output_limiter: true,
+            compression_ratio: CompressionRatio::Ratio4,
+        },
+    };
+
+    /// The default configuration of this build
+    ///
+    /// Set at build time from the `TPA2016D2_CONFIG` environment variable, or
+    /// the file named by `TPA2016D2_CONFIG_FILE`, with settings in the syntax
+    /// of [`tpa2016_config!`]. This way a firmware image can have its tuned
+    /// settings built in. Without either variable it is [`Config::DEFAULT`].
+    ///
+    /// ```text
+    /// TPA2016D2_CONFIG="gain: 12 dB, compression: 2:1, limiter: 3 dBV" cargo build
+    /// ```
+    ///
+    /// A relative `TPA2016D2_CONFIG_FILE` is resolved against the manifest
+    /// directory of this crate, not of the application, so give an absolute
+    /// path, or set it with `relative = true` in the `[env]` section of
+    /// `.cargo/config.toml`.
+    pub const BUILD_DEFAULT: Config = include!(concat!(env!("OUT_DIR"), "/build_config.rs"));
+
+    /// Check the settings against the ranges from the datasheet
+    pub const fn validate(&self) -> Result<(), AgcConfigError> {
+        self.agc.validate()
+    }
+
+    /// Check the settings, reporting every violation
+    ///
+    /// See [`AgcConfig::validate_all`].
+    pub fn validate_all(&self) -> ValidationReport {
+        self.agc.validate_all()
+    }
+
+    /// The contents of registers 1 to 7 for this configuration
+    ///
+    /// Fault and shutdown bits are cleared. Values are masked to the register
+    /// fields.
+    pub fn to_registers(&self) -> [u8; 7] {
+        let mut map = RegisterMap::default();
+        map.set_spk_en_l(self.speaker_left);
+        map.set_spk_en_r(self.speaker_right);
+        map.set_ng_en(self.noise_gate);
+        map.set_noise_gate_threshold(U2::masked(self.noise_gate_threshold as u8));
+        map.set_attack_time(self.agc.attack_time);
+        map.set_release_time(self.agc.release_time);
+        map.set_hold_time(self.agc.hold_time);
+        map.set_fixed_gain(self.agc.fixed_gain);
+        map.set_output_limiter_level(U5::masked(self.agc.output_limiter_level));
+        map.set_output_limiter_disable(!self.agc.output_limiter);
+        map.set_max_gain(U4::masked(self.agc.max_gain));
+        map.set_compression_ratio(U2::masked(self.agc.compression_ratio as u8));
+
+        let mut regs = [0; 7];
+        for (val, reg) in regs.iter_mut().zip(Register::ALL) {
+            *val = map.reg_as_byte(reg);
+        }
+        regs
+    }
+
+    /// Decode the contents of registers 1 to 7
+    pub fn from_registers(regs: &[u8; 7]) -> Config {
+        let mut map = RegisterMap::default();
+        for (&val, reg) in regs.iter().zip(Register::ALL) {
+            map.update_map(reg, val);
+        }
+
+        Config {
+            speaker_left: map.spk_en_l(),
+            speaker_right: map.spk_en_r(),
+            noise_gate: map.ng_en(),
+            noise_gate_threshold: noise_gate_threshold_from_bits(
+                map.noise_gate_threshold().value(),
+            ),
+            agc: AgcConfig {
+                attack_time: regs[1] & 0x3F,
+                release_time: regs[2] & 0x3F,
+                hold_time: regs[3] & 0x3F,
+                fixed_gain: regs[4] & 0x3F,
+                max_gain: map.max_gain().value(),
+                output_limiter_level: map.output_limiter_level().value(),
+                output_limiter: !map.output_limiter_disable(),
+                compression_ratio: compression_ratio_from_bits(map.compression_ratio().value()),
+            },
+        }
+    }
+}
+
+/// A partial configuration, `None` fields are left unchanged
+///
+/// With the `serde` feature it can be deserialized from host messages, e.g.
+/// `{"output_limiter_level": 20}` to change only the limiter level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ConfigPatch {
+    pub speaker_left: Option<bool>,
+    pub speaker_right: Option<bool>,
+    pub noise_gate: Option<bool>,
+    pub noise_gate_threshold: Option<NoiseGateThreshold>,
+    pub attack_time: Option<u8>,
+    pub release_time: Option<u8>,
+    pub hold_time: Option<u8>,
+    pub fixed_gain: Option<u8>,
+    pub max_gain: Option<u8>,
+    pub output_limiter_level: Option<u8>,
+    pub output_limiter: Option<bool>,
+    pub compression_ratio: Option<CompressionRatio>,
+}
+
+impl Config {
+    /// Change the settings present in `patch`
+    pub fn apply_patch(&mut self, patch: &ConfigPatch) {
+        fn update<T: Copy>(field: &mut T, val: Option<T>) {
+            if let Some(val) = val {
+                *field = val;
+            }
+        }
+
+        update(&mut self.speaker_left, patch.speaker_left);
+        update(&mut self.speaker_right, patch.speaker_right);
+        update(&mut self.noise_gate, patch.noise_gate);
+        update(&mut self.noise_gate_threshold, patch.noise_gate_threshold);
+        update(&mut self.agc.attack_time, patch.attack_time);
+        update(&mut self.agc.release_time, patch.release_time);
+        update(&mut self.agc.hold_time, patch.hold_time);
+        update(&mut self.agc.fixed_gain, patch.fixed_gain);
+        update(&mut self.agc.max_gain, patch.max_gain);
+        update(
+            &mut self.agc.output_limiter_level,
+            patch.output_limiter_level,
+        );
+        update(&mut self.agc.output_limiter, patch.output_limiter);
+        update(&mut self.agc.compression_ratio, patch.compression_ratio);
+    }
+
+    /// The settings that differ from `base`, with their values in `self`
+    pub fn changes_from(&self, base: &Config) -> ConfigPatch {
+        fn changed<T: Copy + PartialEq>(val: T, base: T) -> Option<T> {
+            if val != base {
+                Some(val)
+            } else {
+                None
+            }
+        }
+
+        ConfigPatch {
+            speaker_left: changed(self.speaker_left, base.speaker_left),
+            speaker_right: changed(self.speaker_right, base.speaker_right),
+            noise_gate: changed(self.noise_gate, base.noise_gate),
+            noise_gate_threshold: changed(self.noise_gate_threshold, base.noise_gate_threshold),
+            attack_time: changed(self.agc.attack_time, base.agc.attack_time),
+            release_time: changed(self.agc.release_time, base.agc.release_time),
+            hold_time: changed(self.agc.hold_time, base.agc.hold_time),
+            fixed_gain: changed(self.agc.fixed_gain, base.agc.fixed_gain),
+            max_gain: changed(self.agc.max_gain, base.agc.max_gain),
+            output_limiter_level: changed(
+                self.agc.output_limiter_level,
+                base.agc.output_limiter_level,
+            ),
+            output_limiter: changed(self.agc.output_limiter, base.agc.output_limiter),
+            compression_ratio: changed(self.agc.compression_ratio, base.agc.compression_ratio),
+        }
+    }
+
+    /// The settings changed from the power-on defaults, [`Config::DEFAULT`]
+    ///
+    /// Useful in bug reports, and to confirm in factory test that a device
+    /// was provisioned.
+    pub fn compare_with_defaults(&self) -> ConfigPatch {
+        self.changes_from(&Config::DEFAULT)
+    }
+}
+
+impl ConfigPatch {
+    /// Returns true if no setting is changed
+    pub fn is_empty(&self) -> bool {
+        *self == ConfigPatch::default()
+    }
+
+    /// Returns true if every value present fits its register field
+    // `is_none_or` is only just stable at the minimum supported Rust version
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn in_range(&self) -> bool {
+        let fits = |val: Option<u8>, max: u8| val.map_or(true, |val| val <= max);
+        fits(self.attack_time, TIME_CODE_MAX)
+            && fits(self.release_time, TIME_CODE_MAX)
+            && fits(self.hold_time, TIME_CODE_MAX)
+            && fits(self.max_gain, MAX_GAIN_CODE)
+            && fits(self.output_limiter_level, LIMITER_LEVEL_CODE_MAX)
+            && self
+                .fixed_gain
+                .map_or(true, |val| fixed_gain_db(val).is_some())
+    }
+}
+
+/// Fluent construction of a [`Config`], see [`Config::builder`]
+///
+/// Starts from [`Config::DEFAULT`]. Nothing is written to the device, the
+/// combination is validated by [`ConfigBuilder::build`] and the result applied
+/// in one pass with [`Tpa2016d2::apply_config`](crate::Tpa2016d2::apply_config).
+///
+/// ```
+/// use tpa2016d2::{CompressionRatio, Config, NoiseGateThreshold};
+///
+/// let config = Config::builder()
+///     .gain(6)
+///     .compression(CompressionRatio::Ratio4)
+///     .noise_gate(NoiseGateThreshold::Ngt4mV)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ConfigBuilder {
+    config: Config,
+    // First out of range setting, reported by `build`
+    error: Option<AgcConfigError>,
+}
+
+impl Config {
+    /// A builder starting from the power-on configuration
+    pub const fn builder() -> ConfigBuilder {
+        ConfigBuilder {
+            config: Config::DEFAULT,
+            error: None,
+        }
+    }
+}
+
+impl ConfigBuilder {
+    /// Enable or disable the speakers
+    pub const fn speakers(mut self, left: bool, right: bool) -> Self {
+        self.config.speaker_left = left;
+        self.config.speaker_right = right;
+        self
+    }
+
+    /// Fixed gain in dB, -28 to 30
+    pub const fn gain(mut self, db: i8) -> Self {
+        if db < MIN_FIXED_GAIN_DB || db > MAX_FIXED_GAIN_DB {
+            return self.fail(AgcConfigError::FixedGain);
+        }
+        self.config.agc.fixed_gain = (db as u8) & 0x3F;
+        self
+    }
+
+    /// Max gain of the AGC in dB, 18 to 30
+    pub const fn max_gain(mut self, db: i8) -> Self {
+        if db < *MAX_GAIN_DB_RANGE.start() || db > *MAX_GAIN_DB_RANGE.end() {
+            return self.fail(AgcConfigError::MaxGain);
+        }
+        self.config.agc.max_gain = (db - *MAX_GAIN_DB_RANGE.start()) as u8;
+        self
+    }
+
+    pub const fn compression(mut self, ratio: CompressionRatio) -> Self {
+        self.config.agc.compression_ratio = ratio;
+        self
+    }
+
+    /// Attack time register value
+    pub const fn attack_time(mut self, val: u8) -> Self {
+        self.config.agc.attack_time = val;
+        self
+    }
+
+    /// Release time register value
+    pub const fn release_time(mut self, val: u8) -> Self {
+        self.config.agc.release_time = val;
+        self
+    }
+
+    /// Hold time register value
+    pub const fn hold_time(mut self, val: u8) -> Self {
+        self.config.agc.hold_time = val;
+        self
+    }
+
+    /// Output limiter level register value
+    pub const fn limiter_level(mut self, val: u8) -> Self {
+        self.config.agc.output_limiter_level = val;
+        self
+    }
+
+    /// Enable or disable the output limiter, it can only be disabled with a
+    /// compression ratio of 1:1
+    pub const fn output_limiter(mut self, enable: bool) -> Self {
+        self.config.agc.output_limiter = enable;
+        self
+    }
+
+    /// Enable the noise gate with `threshold`
+    pub const fn noise_gate(mut self, threshold: NoiseGateThreshold) -> Self {
+        self.config.noise_gate = true;
+        self.config.noise_gate_threshold = threshold;
+        self
+    }
+
+    /// Disable the noise gate
+    pub const fn noise_gate_off(mut self) -> Self {
+        self.config.noise_gate = false;
+        self
+    }
+
+    /// Validate the settings and return the configuration
+    pub const fn build(self) -> Result<Config, AgcConfigError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        match self.config.validate() {
+            Ok(()) => Ok(self.config),
+            Err(error) => Err(error),
+        }
+    }
+
+    const fn fail(mut self, error: AgcConfigError) -> Self {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+        self
+    }
+}
+
+// Helpers for `tpa2016_config!`, panics are reported as build errors
+#[doc(hidden)]
+impl Config {
+    pub const fn __validated(self) -> Self {
+        match self.validate() {
+            Ok(()) => self,
+            Err(AgcConfigError::AttackTime) => panic!("attack time out of range"),
+            Err(AgcConfigError::ReleaseTime) => panic!("release time out of range"),
+            Err(AgcConfigError::HoldTime) => panic!("hold time out of range"),
+            Err(AgcConfigError::FixedGain) => {
+                panic!("gain must be -28 to 30 dB, and 0 dB to max gain with compression")
+            }
+            Err(AgcConfigError::MaxGain) => panic!("max gain out of range"),
+            Err(AgcConfigError::OutputLimiterLevel) => panic!("limiter level out of range"),
+            Err(AgcConfigError::OutputLimiter) => {
+                panic!("the limiter can only be off with compression 1:1")
+            }
+        }
+    }
+
+    pub const fn __left(mut self, on: bool) -> Self {
+        self.speaker_left = on;
+        self
+    }
+
+    pub const fn __right(mut self, on: bool) -> Self {
+        self.speaker_right = on;
+        self
+    }
+
+    pub const fn __gain_db(mut self, db: f64) -> Self {
+        let code = steps(db + 28.0, 1.0, 58, "gain must be whole dB from -28 to 30");
+        self.agc.fixed_gain = ((code as i8 - 28) as u8) & 0x3F;
+        self
+    }
+
+    pub const fn __max_gain_db(mut self, db: f64) -> Self {
+        self.agc.max_gain = steps(
+            db - 18.0,
+            1.0,
+            12,
+            "max gain must be whole dB from 18 to 30",
+        );
+        self
+    }
+
+    pub const fn __compression(mut self, ratio: u32) -> Self {
+        self.agc.compression_ratio = match ratio {
+            1 => CompressionRatio::Ratio1,
+            2 => CompressionRatio::Ratio2,
+            4 => CompressionRatio::Ratio4,
+            8 => CompressionRatio::Ratio8,
+            _ => panic!("compression must be 1:1, 2:1, 4:1 or 8:1"),
+        };
+        self
+    }
+
+    pub const fn __attack_ms(mut self, ms: f64) -> Self {
+        self.agc.attack_time = nearest_step(ms, 0.1067, "attack time must be 0 to 6.72 ms");
+        self
+    }
+
+    pub const fn __release_ms(mut self, ms: f64) -> Self {
+        self.agc.release_time = nearest_step(ms, 164.4, "release time must be 0 to 10357 ms");
+        self
+    }
+
+    pub const fn __hold_ms(mut self, ms: f64) -> Self {
+        self.agc.hold_time = nearest_step(ms, 13.7, "hold time must be 0 to 863 ms");
+        self
+    }
+
+    pub const fn __limiter_dbv(mut self, dbv: f64) -> Self {
+        self.agc.output_limiter_level = steps(
+            dbv + 6.5,
+            0.5,
+            31,
+            "limiter level must be -6.5 to 9 dBV in 0.5 dB steps",
+        );
+        self
+    }
+
+    pub const fn __limiter_off(mut self) -> Self {
+        self.agc.output_limiter = false;
+        self
+    }
+
+    pub const fn __noise_gate_mv(mut self, mv: u32) -> Self {
+        self.noise_gate = true;
+        self.noise_gate_threshold = match mv {
+            1 => NoiseGateThreshold::Ngt1mV,
+            4 => NoiseGateThreshold::Ngt4mV,
+            10 => NoiseGateThreshold::Ngt10mV,
+            20 => NoiseGateThreshold::Ngt20mV,
+            _ => panic!("noise gate threshold must be 1, 4, 10 or 20 mV"),
+        };
+        self
+    }
+
+    pub const fn __noise_gate_off(mut self) -> Self {
+        self.noise_gate = false;
+        self
+    }
+}
+
+/// `val / step` as register value, which must be exact and at most `max`
+const fn steps(val: f64, step: f64, max: u8, msg: &str) -> u8 {
+    let n = val / step;
+    if n < 0.0 || n > max as f64 || n != (n as u8) as f64 {
+        panic!("{}", msg);
+    }
+    n as u8
+}
+
+/// `val / step` rounded to the nearest register value, at most 63
+const fn nearest_step(val: f64, step: f64, msg: &str) -> u8 {
+    let n = val / step + 0.5;
+    if n < 0.0 || n >= 64.0 {
+        panic!("{}", msg);
+    }
+    n as u8
+}
+
+/// Build a [`Config`] from settings in physical units, checked at compile time
+///
+/// See the [`config`](crate::config) module for the syntax.
+#[macro_export]
+macro_rules! tpa2016_config {
+    (@set $c:expr; ) => { $c };
+    (@set $c:expr; left: on $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__left(true); $($($rest)*)?)
+    };
+    (@set $c:expr; left: off $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__left(false); $($($rest)*)?)
+    };
+    (@set $c:expr; right: on $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__right(true); $($($rest)*)?)
+    };
+    (@set $c:expr; right: off $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__right(false); $($($rest)*)?)
+    };
+    (@set $c:expr; gain: $v:literal dB $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__gain_db($v as f64); $($($rest)*)?)
+    };
+    (@set $c:expr; max_gain: $v:literal dB $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__max_gain_db($v as f64); $($($rest)*)?)
+    };
+    (@set $c:expr; compression: $v:literal : 1 $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__compression($v); $($($rest)*)?)
+    };
+    (@set $c:expr; attack: $v:literal ms $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__attack_ms($v as f64); $($($rest)*)?)
+    };
+    (@set $c:expr; release: $v:literal ms $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__release_ms($v as f64); $($($rest)*)?)
+    };
+    (@set $c:expr; hold: $v:literal ms $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__hold_ms($v as f64); $($($rest)*)?)
+    };
+    (@set $c:expr; limiter: off $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__limiter_off(); $($($rest)*)?)
+    };
+    (@set $c:expr; limiter: $v:literal dBV $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__limiter_dbv($v as f64); $($($rest)*)?)
+    };
+    (@set $c:expr; noise_gate: off $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__noise_gate_off(); $($($rest)*)?)
+    };
+    (@set $c:expr; noise_gate: $v:literal mV $(, $($rest:tt)*)?) => {
+        $crate::tpa2016_config!(@set $c.__noise_gate_mv($v); $($($rest)*)?)
+    };
+    ($($settings:tt)*) => {{
+        const CONFIG: $crate::Config =
+            $crate::tpa2016_config!(@set $crate::Config::DEFAULT; $($settings)*).__validated();
+        CONFIG
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder() {
+        let config = Config::builder()
+            .speakers(true, false)
+            .gain(-6)
+            .compression(CompressionRatio::Ratio1)
+            .max_gain(24)
+            .noise_gate_off()
+            .build()
+            .unwrap();
+        assert!(!config.speaker_right);
+        assert_eq!(config.agc.fixed_gain, 0b11_1010);
+        assert_eq!(config.agc.max_gain, 6);
+        assert!(!config.noise_gate);
+
+        // Negative gain with compression
+        assert_eq!(
+            Config::builder().gain(-6).build(),
+            Err(AgcConfigError::FixedGain)
+        );
+        assert_eq!(
+            Config::builder().max_gain(31).gain(40).build(),
+            Err(AgcConfigError::MaxGain)
+        );
+        assert_eq!(
+            Config::builder().output_limiter(false).build(),
+            Err(AgcConfigError::OutputLimiter)
+        );
+    }
+
+    #[test]
+    fn compare_with_defaults() {
+        assert!(Config::DEFAULT.compare_with_defaults().is_empty());
+
+        let mut config = Config::DEFAULT;
+        config.speaker_right = false;
+        config.agc.fixed_gain = 12;
+        config.agc.output_limiter = false;
+        let changes = config.compare_with_defaults();
+        assert_eq!(
+            changes,
+            ConfigPatch {
+                speaker_right: Some(false),
+                fixed_gain: Some(12),
+                output_limiter: Some(false),
+                ..ConfigPatch::default()
+            }
+        );
+
+        let mut restored = config;
+        restored.apply_patch(&Config::DEFAULT.changes_from(&config));
+        assert_eq!(restored, Config::DEFAULT);
+    }
+
+    #[test]
+    fn register_round_trip() {
+        let regs = Config::DEFAULT.to_registers();
+        assert_eq!(regs, [0xC3, 0x05, 0x0B, 0x00, 0x06, 0x3A, 0xC2]);
+        assert_eq!(Config::from_registers(&regs), Config::DEFAULT);
+
+        let config =
+            tpa2016_config! { right: off, gain: -3 dB, compression: 1:1, noise_gate: 20 mV };
+        assert_eq!(Config::from_registers(&config.to_registers()), config);
+
+        let config = tpa2016_config! { compression: 1:1, limiter: off };
+        assert_eq!(config.to_registers()[5], 0xBA);
+        assert_eq!(Config::from_registers(&config.to_registers()), config);
+    }
+
+    #[test]
+    fn patch() {
+        let mut config = Config::DEFAULT;
+        config.apply_patch(&ConfigPatch {
+            speaker_right: Some(false),
+            output_limiter_level: Some(20),
+            ..ConfigPatch::default()
+        });
+
+        let mut expected = Config::DEFAULT;
+        expected.speaker_right = false;
+        expected.agc.output_limiter_level = 20;
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn macro_defaults() {
+        const C: Config = tpa2016_config! {};
+        assert_eq!(C, Config::DEFAULT);
+    }
+
+    #[test]
+    fn macro_settings() {
+        let c = tpa2016_config! {
+            left: off,
+            gain: -6 dB,
+            max_gain: 24 dB,
+            compression: 1:1,
+            attack: 2.56 ms,
+            release: 1644 ms,
+            hold: 137 ms,
+            limiter: -1.5 dBV,
+            noise_gate: 10 mV,
+        };
+
+        assert!(!c.speaker_left);
+        assert!(c.speaker_right);
+        assert_eq!(c.noise_gate_threshold, NoiseGateThreshold::Ngt10mV);
+        assert_eq!(
+            c.agc,
+            AgcConfig {
+                attack_time: 24,
+                release_time: 10,
+                hold_time: 10,
+                fixed_gain: 0b11_1010,
+                max_gain: 6,
+                output_limiter_level: 10,
+                output_limiter: true,
+                compression_ratio: CompressionRatio::Ratio1,
+            }
+        );
+    }
+}
@@ -0,0 +1,468 @@
+//! Driver level control trait
+//!
+//! [`Tpa2016Control`] is implemented by the real driver and by [`FakeAmp`],
+//! so application logic can be written against the trait and unit tested
+//! without an i2c bus.
+
+use crate::limits::{LIMITER_LEVEL_CODE_MAX, MAX_GAIN_CODE, TIME_CODE_MAX};
+use crate::{
+    agc, AgcConfig, AgcPreset, CompressionRatio, Config, ConfigChange, Faults, NoiseGateThreshold,
+};
+#[cfg(driver)]
+use crate::{Error, RegisterInterface, Tpa2016d2};
+
+/// The control surface of the amplifier
+pub trait Tpa2016Control {
+    /// Error of the calls, configurations failing validation convert into it
+    type Error: From<agc::AgcConfigError>;
+
+    /// Enable or disable speakers
+    fn speaker_enable(&mut self, le: bool, re: bool) -> Result<(), Self::Error>;
+    /// Read the fault flags
+    fn get_faults(&mut self) -> Result<Faults, Self::Error>;
+    /// Shutdown the device
+    fn disable_device(&mut self) -> Result<(), Self::Error>;
+    /// Wake the device from software shutdown
+    fn enable_device(&mut self) -> Result<(), Self::Error>;
+    /// Enable or disable the noise gate
+    fn set_noise_gate(&mut self, enable: bool) -> Result<(), Self::Error>;
+    fn set_attack_time(&mut self, val: u8) -> Result<(), Self::Error>;
+    fn set_release_time(&mut self, val: u8) -> Result<(), Self::Error>;
+    fn set_hold_time(&mut self, val: u8) -> Result<(), Self::Error>;
+    /// Set the gain
+    fn gain(&mut self, gain: u8) -> Result<(), Self::Error>;
+    fn noise_gate_threshold(&mut self, val: NoiseGateThreshold) -> Result<(), Self::Error>;
+    fn output_limiter_level(&mut self, val: u8) -> Result<(), Self::Error>;
+    fn compression_ratio(&mut self, ratio: CompressionRatio) -> Result<(), Self::Error>;
+    fn set_agc_preset(&mut self, preset: AgcPreset) -> Result<(), Self::Error>;
+    /// Set the max gain, 18 dB + `val`
+    fn set_max_gain(&mut self, val: u8) -> Result<(), Self::Error>;
+    /// Write a complete AGC configuration
+    fn set_agc_config(&mut self, config: &AgcConfig) -> Result<(), Self::Error>;
+
+    /// Write a complete configuration
+    ///
+    /// The configuration is validated before anything is written.
+    fn apply_config(&mut self, config: &Config) -> Result<(), Self::Error> {
+        config.validate()?;
+        self.speaker_enable(config.speaker_left, config.speaker_right)?;
+        self.set_noise_gate(config.noise_gate)?;
+        self.noise_gate_threshold(config.noise_gate_threshold)?;
+        self.set_agc_config(&config.agc)
+    }
+
+    /// Apply a single configuration change
+    fn apply_change(&mut self, change: ConfigChange) -> Result<(), Self::Error> {
+        match change {
+            ConfigChange::SpeakerEnable { left, right } => self.speaker_enable(left, right),
+            ConfigChange::Shutdown => self.disable_device(),
+            ConfigChange::Wake => self.enable_device(),
+            ConfigChange::NoiseGate(enable) => self.set_noise_gate(enable),
+            ConfigChange::AttackTime(val) => self.set_attack_time(val),
+            ConfigChange::ReleaseTime(val) => self.set_release_time(val),
+            ConfigChange::HoldTime(val) => self.set_hold_time(val),
+            ConfigChange::Gain(val) => self.gain(val),
+            ConfigChange::NoiseGateThreshold(val) => self.noise_gate_threshold(val),
+            ConfigChange::OutputLimiterLevel(val) => self.output_limiter_level(val),
+            ConfigChange::CompressionRatio(val) => self.compression_ratio(val),
+            ConfigChange::AgcPreset(val) => self.set_agc_preset(val),
+            ConfigChange::MaxGain(val) => self.set_max_gain(val),
+        }
+    }
+}
+
+#[cfg(driver)]
+impl<I2C, E> Tpa2016Control for Tpa2016d2<I2C>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    type Error = Error<E>;
+
+    fn speaker_enable(&mut self, le: bool, re: bool) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::speaker_enable(self, le, re)
+    }
+
+    fn get_faults(&mut self) -> Result<Faults, Error<E>> {
+        Tpa2016d2::<I2C>::get_faults(self)
+    }
+
+    fn disable_device(&mut self) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::disable_device(self)
+    }
+
+    fn enable_device(&mut self) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::enable_device(self)
+    }
+
+    fn set_noise_gate(&mut self, enable: bool) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::set_noise_gate(self, enable)
+    }
+
+    fn set_attack_time(&mut self, val: u8) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::set_attack_time(self, val)
+    }
+
+    fn set_release_time(&mut self, val: u8) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::set_release_time(self, val)
+    }
+
+    fn set_hold_time(&mut self, val: u8) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::set_hold_time(self, val)
+    }
+
+    fn gain(&mut self, gain: u8) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::gain(self, gain)
+    }
+
+    fn noise_gate_threshold(&mut self, val: NoiseGateThreshold) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::noise_gate_threshold(self, val)
+    }
+
+    fn output_limiter_level(&mut self, val: u8) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::output_limiter_level(self, val)
+    }
+
+    fn compression_ratio(&mut self, ratio: CompressionRatio) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::compression_ratio(self, ratio)
+    }
+
+    fn set_agc_preset(&mut self, preset: AgcPreset) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::set_agc_preset(self, preset)
+    }
+
+    fn set_max_gain(&mut self, val: u8) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::set_max_gain(self, val)
+    }
+
+    fn set_agc_config(&mut self, config: &AgcConfig) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::set_agc_config(self, config)
+    }
+
+    fn apply_config(&mut self, config: &Config) -> Result<(), Error<E>> {
+        Tpa2016d2::<I2C>::apply_config(self, config)
+    }
+}
+
+/// Maximum number of calls recorded by [`FakeAmp`]
+pub const FAKE_AMP_LOG_LEN: usize = 32;
+
+/// Error returned by [`FakeAmp`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FakeError {
+    /// A failure injected with [`FakeAmp::fail_next`]
+    Injected,
+    /// A value out of range for its register field
+    InvalidValue,
+}
+
+impl From<agc::AgcConfigError> for FakeError {
+    fn from(_: agc::AgcConfigError) -> Self {
+        FakeError::InvalidValue
+    }
+}
+
+/// Amplifier without a bus, for testing application code
+///
+/// Keeps the state the real device would have after each call and records
+/// the calls made as [`ConfigChange`]s.
+#[derive(Clone, Debug)]
+pub struct FakeAmp {
+    pub speaker_left: bool,
+    pub speaker_right: bool,
+    pub shutdown: bool,
+    pub noise_gate: bool,
+    pub attack_time: u8,
+    pub release_time: u8,
+    pub hold_time: u8,
+    pub gain: u8,
+    pub noise_gate_threshold: NoiseGateThreshold,
+    pub output_limiter_level: u8,
+    pub max_gain: u8,
+    pub compression_ratio: CompressionRatio,
+    /// Faults reported by `get_faults()`
+    pub faults: Faults,
+    /// Make the next call fail with [`FakeError`]
+    pub fail_next: bool,
+    /// Number of calls to `get_faults()`
+    pub fault_reads: usize,
+    log: [ConfigChange; FAKE_AMP_LOG_LEN],
+    log_len: usize,
+    calls: usize,
+}
+
+impl Default for FakeAmp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeAmp {
+    /// Creates a fake amplifier in the power-on state
+    pub fn new() -> Self {
+        FakeAmp {
+            speaker_left: true,
+            speaker_right: true,
+            shutdown: false,
+            noise_gate: true,
+            attack_time: 0x05,
+            release_time: 0x0B,
+            hold_time: 0x00,
+            gain: 0x06,
+            noise_gate_threshold: NoiseGateThreshold::Ngt4mV,
+            output_limiter_level: 0b11010,
+            max_gain: 0b1100,
+            compression_ratio: CompressionRatio::Ratio4,
+            faults: Faults::default(),
+            fail_next: false,
+            fault_reads: 0,
+            log: [ConfigChange::Shutdown; FAKE_AMP_LOG_LEN],
+            log_len: 0,
+            calls: 0,
+        }
+    }
+
+    /// The first [`FAKE_AMP_LOG_LEN`] configuration calls made
+    pub fn calls(&self) -> &[ConfigChange] {
+        &self.log[..self.log_len]
+    }
+
+    /// Total number of configuration calls made
+    pub fn call_count(&self) -> usize {
+        self.calls
+    }
+
+    /// Forget the recorded calls
+    pub fn clear_calls(&mut self) {
+        self.log_len = 0;
+        self.calls = 0;
+    }
+
+    fn record(&mut self, change: ConfigChange) -> Result<(), FakeError> {
+        self.check_fail()?;
+        if self.log_len < FAKE_AMP_LOG_LEN {
+            self.log[self.log_len] = change;
+            self.log_len += 1;
+        }
+        self.calls += 1;
+        Ok(())
+    }
+
+    fn check_fail(&mut self) -> Result<(), FakeError> {
+        if self.fail_next {
+            self.fail_next = false;
+            return Err(FakeError::Injected);
+        }
+        Ok(())
+    }
+
+    fn check(valid: bool) -> Result<(), FakeError> {
+        if valid {
+            Ok(())
+        } else {
+            Err(FakeError::InvalidValue)
+        }
+    }
+}
+
+impl Tpa2016Control for FakeAmp {
+    type Error = FakeError;
+
+    fn speaker_enable(&mut self, le: bool, re: bool) -> Result<(), FakeError> {
+        self.record(ConfigChange::SpeakerEnable {
+            left: le,
+            right: re,
+        })?;
+        self.speaker_left = le;
+        self.speaker_right = re;
+        Ok(())
+    }
+
+    fn get_faults(&mut self) -> Result<Faults, FakeError> {
+        self.check_fail()?;
+        self.fault_reads += 1;
+        Ok(self.faults)
+    }
+
+    fn disable_device(&mut self) -> Result<(), FakeError> {
+        self.record(ConfigChange::Shutdown)?;
+        self.shutdown = true;
+        Ok(())
+    }
+
+    fn enable_device(&mut self) -> Result<(), FakeError> {
+        self.record(ConfigChange::Wake)?;
+        self.shutdown = false;
+        Ok(())
+    }
+
+    fn set_noise_gate(&mut self, enable: bool) -> Result<(), FakeError> {
+        self.record(ConfigChange::NoiseGate(enable))?;
+        self.noise_gate = enable;
+        Ok(())
+    }
+
+    fn set_attack_time(&mut self, val: u8) -> Result<(), FakeError> {
+        Self::check(val <= TIME_CODE_MAX)?;
+        self.record(ConfigChange::AttackTime(val))?;
+        self.attack_time = val;
+        Ok(())
+    }
+
+    fn set_release_time(&mut self, val: u8) -> Result<(), FakeError> {
+        Self::check(val <= TIME_CODE_MAX)?;
+        self.record(ConfigChange::ReleaseTime(val))?;
+        self.release_time = val;
+        Ok(())
+    }
+
+    fn set_hold_time(&mut self, val: u8) -> Result<(), FakeError> {
+        Self::check(val <= TIME_CODE_MAX)?;
+        self.record(ConfigChange::HoldTime(val))?;
+        self.hold_time = val;
+        Ok(())
+    }
+
+    fn gain(&mut self, gain: u8) -> Result<(), FakeError> {
+        Self::check(agc::fixed_gain_db(gain).is_some())?;
+        self.record(ConfigChange::Gain(gain))?;
+        self.gain = gain;
+        Ok(())
+    }
+
+    fn noise_gate_threshold(&mut self, val: NoiseGateThreshold) -> Result<(), FakeError> {
+        self.record(ConfigChange::NoiseGateThreshold(val))?;
+        self.noise_gate_threshold = val;
+        Ok(())
+    }
+
+    fn output_limiter_level(&mut self, val: u8) -> Result<(), FakeError> {
+        Self::check(val <= LIMITER_LEVEL_CODE_MAX)?;
+        self.record(ConfigChange::OutputLimiterLevel(val))?;
+        self.output_limiter_level = val;
+        Ok(())
+    }
+
+    fn compression_ratio(&mut self, ratio: CompressionRatio) -> Result<(), FakeError> {
+        self.record(ConfigChange::CompressionRatio(ratio))?;
+        self.compression_ratio = ratio;
+        Ok(())
+    }
+
+    fn set_agc_preset(&mut self, preset: AgcPreset) -> Result<(), FakeError> {
+        self.record(ConfigChange::AgcPreset(preset))?;
+
+        let (cr, atk, rel_time, hold_time, fixed_gain, limiter_level) = preset.register_values();
+        self.attack_time = atk;
+        self.release_time = rel_time;
+        self.hold_time = hold_time;
+        self.gain = fixed_gain;
+        self.output_limiter_level = limiter_level & LIMITER_LEVEL_CODE_MAX;
+        self.compression_ratio = cr;
+        Ok(())
+    }
+
+    fn set_max_gain(&mut self, val: u8) -> Result<(), FakeError> {
+        Self::check(val <= MAX_GAIN_CODE)?;
+        self.record(ConfigChange::MaxGain(val))?;
+        self.max_gain = val;
+        Ok(())
+    }
+
+    fn set_agc_config(&mut self, config: &AgcConfig) -> Result<(), FakeError> {
+        Self::check(config.validate().is_ok())?;
+        self.check_fail()?;
+        // Recorded as the individual changes, the max gain is not recorded
+        for &change in &[
+            ConfigChange::AttackTime(config.attack_time),
+            ConfigChange::ReleaseTime(config.release_time),
+            ConfigChange::HoldTime(config.hold_time),
+            ConfigChange::Gain(config.fixed_gain),
+            ConfigChange::OutputLimiterLevel(config.output_limiter_level),
+            ConfigChange::CompressionRatio(config.compression_ratio),
+        ] {
+            self.apply_change(change)?;
+        }
+        self.max_gain = config.max_gain;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fake_records_calls() {
+        let mut amp = FakeAmp::new();
+
+        amp.gain(12).unwrap();
+        amp.apply_change(ConfigChange::NoiseGate(false)).unwrap();
+        amp.set_agc_preset(AgcPreset::Jazz).unwrap();
+
+        assert_eq!(
+            amp.calls(),
+            [
+                ConfigChange::Gain(12),
+                ConfigChange::NoiseGate(false),
+                ConfigChange::AgcPreset(AgcPreset::Jazz)
+            ]
+        );
+        assert!(!amp.noise_gate);
+        assert_eq!(amp.gain, 6);
+        assert_eq!(amp.compression_ratio, CompressionRatio::Ratio2);
+        assert_eq!(amp.output_limiter_level, 0b1_1101);
+    }
+
+    #[test]
+    fn fake_injected_failure() {
+        let mut amp = FakeAmp::new();
+        amp.fail_next = true;
+
+        assert_eq!(amp.gain(12), Err(FakeError::Injected));
+        assert_eq!(amp.gain, 6);
+        assert_eq!(amp.gain(12), Ok(()));
+        assert_eq!(amp.call_count(), 1);
+    }
+
+    #[test]
+    fn fake_wakes_from_shutdown() {
+        let mut amp = FakeAmp::new();
+
+        amp.apply_change(ConfigChange::Shutdown).unwrap();
+        assert!(amp.shutdown);
+        amp.apply_change(ConfigChange::Wake).unwrap();
+        assert!(!amp.shutdown);
+        assert_eq!(amp.calls(), [ConfigChange::Shutdown, ConfigChange::Wake]);
+    }
+
+    #[test]
+    fn fake_rejects_invalid_values() {
+        let mut amp = FakeAmp::new();
+
+        assert_eq!(amp.gain(31), Err(FakeError::InvalidValue));
+        assert_eq!(amp.set_attack_time(0x40), Err(FakeError::InvalidValue));
+        assert_eq!(amp.set_release_time(0x40), Err(FakeError::InvalidValue));
+        assert_eq!(amp.set_hold_time(0x40), Err(FakeError::InvalidValue));
+        assert_eq!(amp.output_limiter_level(0x20), Err(FakeError::InvalidValue));
+        assert_eq!(amp.set_max_gain(13), Err(FakeError::InvalidValue));
+
+        let config = AgcConfig {
+            max_gain: 13,
+            ..Config::DEFAULT.agc
+        };
+        assert_eq!(amp.set_agc_config(&config), Err(FakeError::InvalidValue));
+
+        // Nothing is written when the AGC part is invalid
+        let config = Config {
+            speaker_left: false,
+            agc: config,
+            ..Config::DEFAULT
+        };
+        assert_eq!(amp.apply_config(&config), Err(FakeError::InvalidValue));
+        assert!(amp.speaker_left);
+
+        assert_eq!(amp.gain, 6);
+        assert_eq!(amp.max_gain, 0b1100);
+        assert_eq!(amp.call_count(), 0);
+    }
+}
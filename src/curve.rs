@@ -0,0 +1,72 @@
+//! Static compressor transfer curve
+//!
+//! The output level the AGC settles at for a constant input level, from the
+//! compression ratio, fixed gain, max gain and limiter level of a [`Config`].
+//! For drawing the compressor curve in a UI or checking settings numerically:
+//!
+//! ```
+//! use tpa2016d2::curve::output_level_dbv;
+//! use tpa2016d2::Config;
+//!
+//! // Fixed gain 6 dB, max gain 30 dB, limiter 6.5 dBV, 4:1
+//! let config = Config::DEFAULT;
+//! assert_eq!(output_level_dbv(-3.5, &config), 5.5);
+//! assert_eq!(output_level_dbv(3.0, &config), 6.5);
+//! ```
+//!
+//! Above the knee, where the fixed gain brings the input to the limiter
+//! level, the output is held at the limiter level. Below it the output falls
+//! by 1 dB per ratio dB of input, until the gain reaches the max gain. At 1:1
+//! the gain is the fixed gain. The noise gate is not part of the curve.
+
+use crate::agc::fixed_gain_to_db;
+use crate::limits::MAX_GAIN_DB_RANGE;
+use crate::{CompressionRatio, Config};
+
+/// Output level in dBV for a constant input level in dBV
+pub fn output_level_dbv(input_dbv: f32, config: &Config) -> f32 {
+    input_dbv + gain_db(input_dbv, config)
+}
+
+/// Gain in dB applied at a constant input level in dBV
+pub fn gain_db(input_dbv: f32, config: &Config) -> f32 {
+    let agc = &config.agc;
+    let fixed_gain_db = fixed_gain_to_db(agc.fixed_gain) as f32;
+    let max_gain_db = (*MAX_GAIN_DB_RANGE.start() + agc.max_gain as i8) as f32;
+    let limiter_dbv = -6.5 + (agc.output_limiter_level & 0b1_1111) as f32 * 0.5;
+    let ratio = match agc.compression_ratio {
+        CompressionRatio::Ratio1 => 1.0,
+        CompressionRatio::Ratio2 => 2.0,
+        CompressionRatio::Ratio4 => 4.0,
+        CompressionRatio::Ratio8 => 8.0,
+    };
+
+    let knee_dbv = limiter_dbv - fixed_gain_db;
+    let gain = if input_dbv >= knee_dbv {
+        limiter_dbv - input_dbv
+    } else {
+        fixed_gain_db + (knee_dbv - input_dbv) * (1.0 - 1.0 / ratio)
+    };
+    gain.min(max_gain_db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_curve() {
+        let mut config = Config::DEFAULT;
+
+        // Knee at 0.5 dBV
+        assert_eq!(gain_db(3.5, &config), 3.0);
+        assert_eq!(gain_db(0.5, &config), 6.0);
+        assert_eq!(gain_db(-3.5, &config), 9.0);
+        assert_eq!(gain_db(-60.0, &config), 30.0);
+        assert_eq!(output_level_dbv(-60.0, &config), -30.0);
+
+        config.agc.compression_ratio = CompressionRatio::Ratio1;
+        assert_eq!(output_level_dbv(-20.0, &config), -14.0);
+        assert_eq!(output_level_dbv(10.0, &config), 6.5);
+    }
+}
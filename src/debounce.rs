@@ -0,0 +1,159 @@
+//! Rate limiting of speaker enable changes
+//!
+//! Bouncy buttons or flapping connections can make upper layers toggle the
+//! speakers faster than is good for the output stage, and audible as chatter.
+//! [`SpeakerDebouncer`] enforces a minimum interval between changes. A change
+//! requested too early is held back and applied by [`SpeakerDebouncer::poll`]
+//! once the interval has passed, only the last requested state is kept:
+//!
+//! ```
+//! # use tpa2016d2::control::FakeAmp;
+//! # struct Ticks(u32);
+//! # impl tpa2016d2::Clock for Ticks { fn now_ms(&mut self) -> u32 { self.0 } }
+//! use tpa2016d2::debounce::SpeakerDebouncer;
+//!
+//! # let mut amp = FakeAmp::new();
+//! # let mut clock = Ticks(0);
+//! let mut speakers = SpeakerDebouncer::new(200);
+//!
+//! // From the UI
+//! speakers.request(&mut amp, &mut clock, true, true)?;
+//!
+//! // From the main loop
+//! speakers.poll(&mut amp, &mut clock)?;
+//! # Ok::<(), tpa2016d2::control::FakeError>(())
+//! ```
+
+use crate::{Clock, Tpa2016Control};
+
+/// Minimum interval enforcement for speaker enable changes
+#[derive(Clone, Copy, Debug)]
+pub struct SpeakerDebouncer {
+    min_interval_ms: u32,
+    applied: Option<(bool, bool)>,
+    last_change_ms: u32,
+    pending: Option<(bool, bool)>,
+}
+
+impl SpeakerDebouncer {
+    /// Allow at most one change per `min_interval_ms`
+    pub const fn new(min_interval_ms: u32) -> Self {
+        SpeakerDebouncer {
+            min_interval_ms,
+            applied: None,
+            last_change_ms: 0,
+            pending: None,
+        }
+    }
+
+    /// Request the speakers to be enabled or disabled
+    ///
+    /// Returns true if the change was written now, false if it is held back
+    /// or the speakers are already in the requested state. A request for the
+    /// state already applied cancels a held back change.
+    pub fn request<A, C>(
+        &mut self,
+        amp: &mut A,
+        clock: &mut C,
+        left: bool,
+        right: bool,
+    ) -> Result<bool, A::Error>
+    where
+        A: Tpa2016Control,
+        C: Clock,
+    {
+        if self.applied == Some((left, right)) {
+            self.pending = None;
+            return Ok(false);
+        }
+        self.pending = Some((left, right));
+        self.poll(amp, clock)
+    }
+
+    /// Apply a held back change if the interval has passed
+    ///
+    /// Returns true if a change was written.
+    pub fn poll<A, C>(&mut self, amp: &mut A, clock: &mut C) -> Result<bool, A::Error>
+    where
+        A: Tpa2016Control,
+        C: Clock,
+    {
+        let (left, right) = match self.pending {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+
+        let now = clock.now_ms();
+        if self.applied.is_some() && now.wrapping_sub(self.last_change_ms) < self.min_interval_ms {
+            return Ok(false);
+        }
+
+        amp.speaker_enable(left, right)?;
+        self.applied = Some((left, right));
+        self.last_change_ms = now;
+        self.pending = None;
+        Ok(true)
+    }
+
+    /// The change waiting for the interval to pass, as `(left, right)`
+    pub fn pending(&self) -> Option<(bool, bool)> {
+        self.pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::FakeAmp;
+    use crate::mock::TestClock;
+    use crate::ConfigChange;
+
+    #[test]
+    fn holds_back_fast_toggles() {
+        let mut amp = FakeAmp::new();
+        let mut clock = TestClock(1000);
+        let mut speakers = SpeakerDebouncer::new(100);
+
+        assert_eq!(
+            speakers.request(&mut amp, &mut clock, false, false),
+            Ok(true)
+        );
+        clock.0 = 1010;
+        assert_eq!(
+            speakers.request(&mut amp, &mut clock, true, true),
+            Ok(false)
+        );
+        clock.0 = 1020;
+        assert_eq!(
+            speakers.request(&mut amp, &mut clock, true, false),
+            Ok(false)
+        );
+        clock.0 = 1099;
+        assert_eq!(speakers.poll(&mut amp, &mut clock), Ok(false));
+        clock.0 = 1100;
+        assert_eq!(speakers.poll(&mut amp, &mut clock), Ok(true));
+
+        // Bouncing back to the applied state cancels the held back change
+        clock.0 = 1150;
+        speakers
+            .request(&mut amp, &mut clock, false, false)
+            .unwrap();
+        speakers.request(&mut amp, &mut clock, true, false).unwrap();
+        clock.0 = 1300;
+        assert_eq!(speakers.poll(&mut amp, &mut clock), Ok(false));
+
+        assert_eq!(
+            amp.calls(),
+            [
+                ConfigChange::SpeakerEnable {
+                    left: false,
+                    right: false
+                },
+                ConfigChange::SpeakerEnable {
+                    left: true,
+                    right: false
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! embedded-hal 1.0 support
+//!
+//! Requires the `eh1` feature, the `hal` feature and with it embedded-hal 0.2
+//! are not needed. [`I2cCompat`] implements the [`I2cBus`] of the driver on
+//! top of an [`embedded_hal_1::i2c::I2c`] bus:
+//!
+//! ```ignore
+//! let mut amp = Tpa2016d2::new_eh1(i2c);
+//! amp.gain(12)?;
+//!
+//! // Get the bus back
+//! let i2c = amp.release().into_inner();
+//! ```
+//!
+//! The functions taking an embedded-hal 0.2 `DelayMs`, like
+//! `Tpa2016d2::init` and the fades, need the `hal`
+//! feature as well.
+
+use embedded_hal_1::i2c::I2c;
+
+use crate::{I2cBus, Tpa2016d2};
+
+/// Adapter from an embedded-hal 1.0 [`I2c`] bus to the [`I2cBus`] of the driver
+pub struct I2cCompat<I2C>(pub I2C);
+
+impl<I2C> I2cCompat<I2C> {
+    /// Return the wrapped bus
+    pub fn into_inner(self) -> I2C {
+        self.0
+    }
+}
+
+impl<I2C: I2c> I2cBus for I2cCompat<I2C> {
+    type Error = I2C::Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.0.write(addr, bytes)
+    }
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.write_read(addr, bytes, buffer)
+    }
+}
+
+impl<I2C: I2c> Tpa2016d2<I2cCompat<I2C>> {
+    /// Creates a driver on an embedded-hal 1.0 bus
+    pub fn new_eh1(i2c: I2C) -> Self {
+        Tpa2016d2::new(I2cCompat(i2c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Register;
+    use embedded_hal_1::i2c::{ErrorKind, ErrorType, Operation};
+
+    struct Bus {
+        regs: [u8; 8],
+    }
+
+    impl ErrorType for Bus {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for Bus {
+        fn transaction(
+            &mut self,
+            addr: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), ErrorKind> {
+            assert_eq!(addr, crate::TPA2016_I2C_ADDR);
+            let mut reg = 0;
+            for op in operations {
+                match op {
+                    Operation::Write(bytes) => {
+                        reg = bytes[0] as usize;
+                        for (i, &val) in bytes[1..].iter().enumerate() {
+                            self.regs[reg + i] = val;
+                        }
+                    }
+                    Operation::Read(buf) => buf.copy_from_slice(&self.regs[reg..reg + buf.len()]),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn eh1_bus() {
+        let mut amp = Tpa2016d2::new_eh1(Bus { regs: [0; 8] });
+        amp.gain(12).unwrap();
+        amp.bus_mut().0.regs[6] = 0x1A;
+        amp.sync().unwrap();
+
+        assert_eq!(amp.device_reg(Register::Agc1).unwrap(), 0x1A);
+        assert_eq!(amp.release().into_inner().regs[5], 12);
+    }
+}
@@ -0,0 +1,69 @@
+//! Driver errors
+
+use core::fmt;
+
+use crate::agc::AgcConfigError;
+
+/// Error returned by the driver
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// Bus error
+    I2c(E),
+    /// An argument is outside the range of the device
+    InvalidValue,
+    /// The device did not acknowledge its address
+    DeviceNotFound,
+    /// A register read back differently than written
+    VerifyFailed,
+    /// Driving the hardware shutdown pin failed
+    Pin,
+}
+
+impl<E> Error<E> {
+    /// The bus error, if this is one
+    pub fn i2c(self) -> Option<E> {
+        match self {
+            Error::I2c(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl<E> From<AgcConfigError> for Error<E> {
+    fn from(_: AgcConfigError) -> Self {
+        Error::InvalidValue
+    }
+}
+
+impl<E: fmt::Debug> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::I2c(e) => write!(f, "i2c bus error: {:?}", e),
+            Error::InvalidValue => f.write_str("value out of range"),
+            Error::DeviceNotFound => f.write_str("device not found"),
+            Error::VerifyFailed => f.write_str("register verification failed"),
+            Error::Pin => f.write_str("shutdown pin error"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug> std::error::Error for Error<E> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        extern crate std;
+        use std::string::ToString;
+
+        assert_eq!(Error::I2c(5).to_string(), "i2c bus error: 5");
+        assert_eq!(
+            Error::<()>::VerifyFailed.to_string(),
+            "register verification failed"
+        );
+    }
+}
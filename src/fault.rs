@@ -0,0 +1,325 @@
+//! Fault edge detection
+//!
+//! [`FaultMonitor`] keeps the previous fault state and calls a
+//! [`FaultHandler`] only when a fault is raised or cleared, not on every poll
+//! while it is present.
+//!
+//! ```
+//! # use tpa2016d2::control::FakeAmp;
+//! use tpa2016d2::fault::{FaultCallbacks, FaultMonitor};
+//!
+//! # let mut amp = FakeAmp::new();
+//! let mut monitor = FaultMonitor::new();
+//! let mut callbacks = FaultCallbacks::new(
+//!     |fault| { /* turn on fault LED */ },
+//!     |fault| { /* turn off fault LED */ },
+//! );
+//!
+//! // From a periodic task
+//! monitor.poll(&mut amp, &mut callbacks)?;
+//! # Ok::<(), tpa2016d2::control::FakeError>(())
+//! ```
+//!
+//! Or, without a handler, iterating over the transitions:
+//!
+//! ```
+//! # use tpa2016d2::control::FakeAmp;
+//! use tpa2016d2::fault::{FaultEvent, FaultMonitor};
+//!
+//! # let mut amp = FakeAmp::new();
+//! let mut monitor = FaultMonitor::new();
+//! for event in monitor.poll_events(&mut amp)? {
+//!     match event {
+//!         FaultEvent::Raised(fault) => { /* log the fault */ }
+//!         FaultEvent::Cleared(fault) => (),
+//!     }
+//! }
+//! # Ok::<(), tpa2016d2::control::FakeError>(())
+//! ```
+
+use crate::throttle::TokenBucket;
+use crate::{Clock, Faults, Tpa2016Control};
+
+/// A single fault condition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Fault {
+    /// Short circuit on the left channel
+    ShortCircuitLeft,
+    /// Short circuit on the right channel
+    ShortCircuitRight,
+    /// Thermal shutdown
+    Thermal,
+}
+
+impl Fault {
+    /// All faults
+    pub const ALL: [Fault; 3] = [
+        Fault::ShortCircuitLeft,
+        Fault::ShortCircuitRight,
+        Fault::Thermal,
+    ];
+
+    /// Returns true if this fault is set in `faults`
+    pub fn is_set(self, faults: &Faults) -> bool {
+        match self {
+            Fault::ShortCircuitLeft => faults.fault_l,
+            Fault::ShortCircuitRight => faults.fault_r,
+            Fault::Thermal => faults.thermal,
+        }
+    }
+}
+
+/// A fault transition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FaultEvent {
+    /// The fault went from clear to set
+    Raised(Fault),
+    /// The fault went from set to clear
+    Cleared(Fault),
+}
+
+impl FaultEvent {
+    /// The fault that changed
+    pub fn fault(self) -> Fault {
+        match self {
+            FaultEvent::Raised(fault) | FaultEvent::Cleared(fault) => fault,
+        }
+    }
+}
+
+/// Receiver of fault transitions
+pub trait FaultHandler {
+    /// Called when `fault` goes from clear to set
+    fn on_fault_raised(&mut self, _fault: Fault) {}
+    /// Called when `fault` goes from set to clear
+    fn on_fault_cleared(&mut self, _fault: Fault) {}
+}
+
+/// [`FaultHandler`] calling closures
+pub struct FaultCallbacks<R, C> {
+    raised: R,
+    cleared: C,
+}
+
+impl<R, C> FaultCallbacks<R, C>
+where
+    R: FnMut(Fault),
+    C: FnMut(Fault),
+{
+    pub fn new(raised: R, cleared: C) -> Self {
+        FaultCallbacks { raised, cleared }
+    }
+}
+
+impl<R, C> FaultHandler for FaultCallbacks<R, C>
+where
+    R: FnMut(Fault),
+    C: FnMut(Fault),
+{
+    fn on_fault_raised(&mut self, fault: Fault) {
+        (self.raised)(fault)
+    }
+
+    fn on_fault_cleared(&mut self, fault: Fault) {
+        (self.cleared)(fault)
+    }
+}
+
+/// Edge detector for the fault flags
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FaultMonitor {
+    previous: Faults,
+}
+
+impl FaultMonitor {
+    /// Creates a monitor assuming no faults are present
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The fault state seen by the last update
+    pub fn faults(&self) -> Faults {
+        self.previous
+    }
+
+    /// Compare `faults` to the previous state and report the transitions
+    pub fn update<H: FaultHandler>(&mut self, faults: Faults, handler: &mut H) {
+        for &fault in &Fault::ALL {
+            match (fault.is_set(&self.previous), fault.is_set(&faults)) {
+                (false, true) => handler.on_fault_raised(fault),
+                (true, false) => handler.on_fault_cleared(fault),
+                _ => (),
+            }
+        }
+        self.previous = faults;
+    }
+
+    /// Read the faults from the device and report the transitions
+    pub fn poll<A, H>(&mut self, amp: &mut A, handler: &mut H) -> Result<Faults, A::Error>
+    where
+        A: Tpa2016Control,
+        H: FaultHandler,
+    {
+        let faults = amp.get_faults()?;
+        self.update(faults, handler);
+        Ok(faults)
+    }
+
+    /// Compare `faults` to the previous state and return the transitions
+    pub fn update_events(&mut self, faults: Faults) -> EventQueue {
+        let mut events = EventQueue::default();
+        self.update(faults, &mut events);
+        events
+    }
+
+    /// Read the faults from the device and return the transitions
+    pub fn poll_events<A>(&mut self, amp: &mut A) -> Result<EventQueue, A::Error>
+    where
+        A: Tpa2016Control,
+    {
+        let faults = amp.get_faults()?;
+        Ok(self.update_events(faults))
+    }
+
+    /// Like [`FaultMonitor::poll`], but only if `bucket` has a token
+    ///
+    /// Returns `None` without accessing the bus when throttled.
+    pub fn poll_throttled<A, H, C>(
+        &mut self,
+        amp: &mut A,
+        handler: &mut H,
+        bucket: &mut TokenBucket,
+        clock: &mut C,
+    ) -> Result<Option<Faults>, A::Error>
+    where
+        A: Tpa2016Control,
+        H: FaultHandler,
+        C: Clock,
+    {
+        if !bucket.try_take_now(clock, 1) {
+            return Ok(None);
+        }
+        self.poll(amp, handler).map(Some)
+    }
+}
+
+/// Transitions from one update, at most one per fault, in the order of
+/// [`Fault::ALL`]
+#[derive(Clone, Debug, Default)]
+pub struct EventQueue {
+    events: [Option<FaultEvent>; 3],
+    len: usize,
+}
+
+impl EventQueue {
+    pub(crate) fn pop(&mut self) -> Option<FaultEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[0].take();
+        self.events.rotate_left(1);
+        self.len -= 1;
+        event
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, event: FaultEvent) {
+        self.events[self.len] = Some(event);
+        self.len += 1;
+    }
+}
+
+impl Iterator for EventQueue {
+    type Item = FaultEvent;
+
+    fn next(&mut self) -> Option<FaultEvent> {
+        self.pop()
+    }
+}
+
+impl FaultHandler for EventQueue {
+    fn on_fault_raised(&mut self, fault: Fault) {
+        self.push(FaultEvent::Raised(fault));
+    }
+
+    fn on_fault_cleared(&mut self, fault: Fault) {
+        self.push(FaultEvent::Cleared(fault));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::FakeAmp;
+
+    #[derive(Default)]
+    struct Recorder {
+        raised: usize,
+        cleared: usize,
+        last: Option<Fault>,
+    }
+
+    impl FaultHandler for Recorder {
+        fn on_fault_raised(&mut self, fault: Fault) {
+            self.raised += 1;
+            self.last = Some(fault);
+        }
+
+        fn on_fault_cleared(&mut self, fault: Fault) {
+            self.cleared += 1;
+            self.last = Some(fault);
+        }
+    }
+
+    #[test]
+    fn reports_edges_only() {
+        let mut amp = FakeAmp::new();
+        let mut monitor = FaultMonitor::new();
+        let mut rec = Recorder::default();
+
+        monitor.poll(&mut amp, &mut rec).unwrap();
+        assert_eq!(rec.raised, 0);
+
+        amp.faults.thermal = true;
+        monitor.poll(&mut amp, &mut rec).unwrap();
+        monitor.poll(&mut amp, &mut rec).unwrap();
+        assert_eq!(rec.raised, 1);
+        assert_eq!(rec.last, Some(Fault::Thermal));
+
+        amp.faults.thermal = false;
+        amp.faults.fault_l = true;
+        monitor.poll(&mut amp, &mut rec).unwrap();
+        assert_eq!(rec.raised, 2);
+        assert_eq!(rec.cleared, 1);
+    }
+
+    #[test]
+    fn poll_events() {
+        let mut amp = FakeAmp::new();
+        let mut monitor = FaultMonitor::new();
+
+        amp.faults.thermal = true;
+        amp.faults.fault_l = true;
+        let mut events = monitor.poll_events(&mut amp).unwrap();
+        assert_eq!(
+            events.next(),
+            Some(FaultEvent::Raised(Fault::ShortCircuitLeft))
+        );
+        assert_eq!(events.next(), Some(FaultEvent::Raised(Fault::Thermal)));
+        assert_eq!(events.next(), None);
+        assert!(monitor.poll_events(&mut amp).unwrap().is_empty());
+
+        amp.faults.fault_l = false;
+        let mut events = monitor.poll_events(&mut amp).unwrap();
+        assert_eq!(
+            events.next(),
+            Some(FaultEvent::Cleared(Fault::ShortCircuitLeft))
+        );
+        assert_eq!(events.next(), None);
+    }
+}
@@ -0,0 +1,162 @@
+//! `f32` conversions
+//!
+//! Requires the `float` feature. Values outside the range of the device are
+//! clamped and rounded to the nearest register step.
+
+#[cfg(driver)]
+use libm::{log10f, powf, roundf};
+
+#[cfg(driver)]
+use crate::{Error, RegisterInterface, Tpa2016d2};
+
+/// Attack time step in ms
+pub const ATTACK_STEP_MS: f32 = 0.1067;
+/// Release time step in ms
+pub const RELEASE_STEP_MS: f32 = 164.4;
+/// Hold time step in ms
+pub const HOLD_STEP_MS: f32 = 13.7;
+
+/// Fixed gain at 100 % volume in dB
+const VOLUME_MAX_DB: f32 = 30.0;
+/// Lowest fixed gain in dB
+const VOLUME_MIN_DB: f32 = -28.0;
+
+fn to_code(val: f32, step: f32, max: u8) -> u8 {
+    let code = roundf(val / step);
+    if code <= 0.0 {
+        0
+    } else if code >= max as f32 {
+        max
+    } else {
+        code as u8
+    }
+}
+
+/// Output limiter level register value from dBV, -6.5 to 9 dBV
+pub fn limiter_level_from_dbv(dbv: f32) -> u8 {
+    to_code(dbv + 6.5, 0.5, 31)
+}
+
+/// Output limiter level in dBV from register value
+pub fn limiter_level_to_dbv(val: u8) -> f32 {
+    -6.5 + (val & 0b1_1111) as f32 * 0.5
+}
+
+/// Attack time register value from ms per 6 dB
+pub fn attack_time_from_ms(ms: f32) -> u8 {
+    to_code(ms, ATTACK_STEP_MS, 63)
+}
+
+/// Release time register value from ms per 6 dB
+pub fn release_time_from_ms(ms: f32) -> u8 {
+    to_code(ms, RELEASE_STEP_MS, 63)
+}
+
+/// Hold time register value from ms
+pub fn hold_time_from_ms(ms: f32) -> u8 {
+    to_code(ms, HOLD_STEP_MS, 63)
+}
+
+/// Fixed gain register value from a volume in percent of full amplitude
+///
+/// 100 % is 30 dB of gain, lower volumes are attenuated logarithmically
+/// down to the minimum gain of -28 dB.
+pub fn gain_from_volume_percent(percent: f32) -> u8 {
+    let db = if percent <= 0.0 {
+        VOLUME_MIN_DB
+    } else {
+        (VOLUME_MAX_DB + 20.0 * log10f(percent / 100.0)).clamp(VOLUME_MIN_DB, VOLUME_MAX_DB)
+    };
+    (roundf(db) as i8 as u8) & 0x3F
+}
+
+/// Volume in percent of full amplitude from a fixed gain register value
+pub fn gain_to_volume_percent(val: u8) -> f32 {
+    let db = ((val << 2) as i8 >> 2) as f32;
+    100.0 * powf(10.0, (db - VOLUME_MAX_DB) / 20.0)
+}
+
+#[cfg(driver)]
+impl<I2C, E> Tpa2016d2<I2C>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    /// Set the output limiter level in dBV
+    pub fn set_output_limiter_dbv_f32(&mut self, dbv: f32) -> Result<(), Error<E>> {
+        self.output_limiter_level(limiter_level_from_dbv(dbv))
+    }
+
+    /// The output limiter level in dBV
+    pub fn output_limiter_dbv_f32(&self) -> f32 {
+        limiter_level_to_dbv(self.agc_config().output_limiter_level)
+    }
+
+    /// Set the attack time in ms per 6 dB
+    pub fn set_attack_time_ms_f32(&mut self, ms: f32) -> Result<(), Error<E>> {
+        self.set_attack_time(attack_time_from_ms(ms))
+    }
+
+    /// The attack time in ms per 6 dB
+    pub fn attack_time_ms_f32(&self) -> f32 {
+        self.agc_config().attack_time as f32 * ATTACK_STEP_MS
+    }
+
+    /// Set the release time in ms per 6 dB
+    pub fn set_release_time_ms_f32(&mut self, ms: f32) -> Result<(), Error<E>> {
+        self.set_release_time(release_time_from_ms(ms))
+    }
+
+    /// The release time in ms per 6 dB
+    pub fn release_time_ms_f32(&self) -> f32 {
+        self.agc_config().release_time as f32 * RELEASE_STEP_MS
+    }
+
+    /// Set the hold time in ms
+    pub fn set_hold_time_ms_f32(&mut self, ms: f32) -> Result<(), Error<E>> {
+        self.set_hold_time(hold_time_from_ms(ms))
+    }
+
+    /// The hold time in ms
+    pub fn hold_time_ms_f32(&self) -> f32 {
+        self.agc_config().hold_time as f32 * HOLD_STEP_MS
+    }
+
+    /// Set the volume in percent, see [`gain_from_volume_percent`]
+    pub fn set_volume_percent_f32(&mut self, percent: f32) -> Result<(), Error<E>> {
+        self.gain(gain_from_volume_percent(percent))
+    }
+
+    /// The volume in percent, see [`gain_to_volume_percent`]
+    pub fn volume_percent_f32(&self) -> f32 {
+        gain_to_volume_percent(self.agc_config().fixed_gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limiter_level() {
+        assert_eq!(limiter_level_from_dbv(6.5), 0b11010);
+        assert_eq!(limiter_level_from_dbv(-10.0), 0);
+        assert_eq!(limiter_level_from_dbv(12.0), 31);
+        assert_eq!(limiter_level_to_dbv(0b11010), 6.5);
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(attack_time_from_ms(0.5335), 5);
+        assert_eq!(release_time_from_ms(1644.0), 10);
+        assert_eq!(hold_time_from_ms(137.0), 10);
+        assert_eq!(hold_time_from_ms(10_000.0), 63);
+    }
+
+    #[test]
+    fn volume() {
+        assert_eq!(gain_from_volume_percent(100.0), 30);
+        assert_eq!(gain_from_volume_percent(50.0), 24);
+        assert_eq!(gain_from_volume_percent(0.0), 0b10_0100);
+        assert!((gain_to_volume_percent(24) - 50.1).abs() < 0.1);
+    }
+}
@@ -1,31 +1,209 @@
 //! This is documentation for the `tpa2016d2` module.
 
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 #![allow(dead_code)]
 
-use embedded_hal::blocking::i2c;
+use core::convert::TryFrom;
 
-mod regmap;
+#[cfg(driver)]
+#[macro_use]
+mod ops;
+
+#[cfg(feature = "hal")]
+use embedded_hal::blocking::delay::DelayMs;
+
+pub mod regmap;
+pub use regmap::Register;
+#[cfg(driver)]
 use regmap::*;
 
+pub mod agc;
+pub use agc::AgcConfig;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod avrcp;
+#[cfg(driver)]
+pub mod blocks;
+#[cfg(driver)]
+pub mod bus;
+#[cfg(driver)]
+pub use bus::{I2cBus, RegisterInterface};
+
+pub mod config;
+pub use config::{Config, ConfigBuilder, ConfigPatch};
+
+pub mod control;
+pub use control::Tpa2016Control;
+
+pub mod curve;
+
+pub mod debounce;
+
+mod error;
+pub use error::Error;
+
+#[cfg(feature = "eh1")]
+pub mod eh1;
+
+pub mod fault;
+#[cfg(feature = "float")]
+pub mod float;
+pub mod limits;
+#[cfg(driver)]
+pub mod multi;
+
+#[cfg(driver)]
+pub mod policy;
+pub mod power;
+pub mod protection;
+pub use protection::ProtectionProfile;
+pub mod ramp;
+#[cfg(driver)]
+pub mod raw;
+#[cfg(feature = "rtic")]
+pub mod rtic;
+pub mod sansio;
+#[cfg(driver)]
+use sansio::Core;
+#[cfg(driver)]
+use sansio::{Command, Commands};
+#[cfg(driver)]
+pub mod recorder;
+pub mod scheduler;
+#[cfg(feature = "hal")]
+pub mod sdz;
+pub mod sequence;
+#[cfg(feature = "serde")]
+pub mod serde;
+#[cfg(feature = "embassy")]
+pub mod shared;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+pub mod spl;
+pub mod state;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(driver)]
+use spl::SplCalibration;
+#[cfg(feature = "minicbor")]
+pub mod telemetry;
+pub mod test_vectors;
+pub mod thermal;
+pub mod throttle;
+pub mod timings;
+#[cfg(feature = "trace")]
+mod trace;
+#[cfg(feature = "hal")]
+pub mod typestate;
+pub mod volume;
+#[cfg(feature = "embedded-graphics")]
+pub mod widget;
+
+#[cfg(test)]
+mod mock;
+
 // The datasheet uses the adresses 0xB0 and 0xB1 for its examples
 // So it is defined like this for clarity.
 const TPA2016_I2C_ADDR: u8 = 0xB0 >> 1;
 
 /// Representation of a Texas Instruments TPA2016d2 audio amplifier
+#[cfg(driver)]
 pub struct Tpa2016d2<I2C> {
     i2c: I2C,
-    regmap: RegisterMap,
+    address: u8,
+    core: Core,
+    spl: Option<SplCalibration>,
+    verify: bool,
+    deferred: bool,
+    // Bit n set when register n has a deferred write
+    dirty: u8,
+    #[cfg(feature = "stats")]
+    stats: stats::BusStats,
+    #[cfg(feature = "stats")]
+    stats_clock: Option<fn() -> u32>,
+    #[cfg(feature = "trace")]
+    tracer: trace::Tracer,
 }
 
 /// Faults
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "minicbor", derive(minicbor::Encode, minicbor::Decode))]
+#[cfg_attr(feature = "minicbor", cbor(map))]
 pub struct Faults {
+    #[cfg_attr(feature = "minicbor", n(0))]
     pub fault_r: bool,
+    #[cfg_attr(feature = "minicbor", n(1))]
     pub fault_l: bool,
+    #[cfg_attr(feature = "minicbor", n(2))]
     pub thermal: bool,
 }
 
+/// A speaker channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Channel {
+    Left,
+    Right,
+}
+
+/// Whether a channel is enabled, and if not why
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChannelState {
+    Enabled,
+    /// Turned off by the device after a short circuit or thermal fault
+    DisabledByFault,
+    /// Turned off through the driver
+    DisabledByUser,
+}
+
+/// Device status
+///
+/// Taken from the driver's view of the registers, the faults are as of the
+/// last call to [`Tpa2016d2::get_faults`], [`Tpa2016d2::read_status`] or
+/// [`Tpa2016d2::sync`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "minicbor", derive(minicbor::Encode, minicbor::Decode))]
+#[cfg_attr(feature = "minicbor", cbor(map))]
+pub struct Status {
+    #[cfg_attr(feature = "minicbor", n(0))]
+    pub speaker_left: bool,
+    #[cfg_attr(feature = "minicbor", n(1))]
+    pub speaker_right: bool,
+    /// Software shutdown
+    #[cfg_attr(feature = "minicbor", n(2))]
+    pub shutdown: bool,
+    #[cfg_attr(feature = "minicbor", n(3))]
+    pub faults: Faults,
+    /// Fixed gain in dB
+    #[cfg_attr(feature = "minicbor", n(4))]
+    pub fixed_gain_db: i8,
+    /// The AGC preset, if the AGC settings have not been changed since it was set
+    #[cfg_attr(feature = "minicbor", n(5))]
+    pub preset: Option<AgcPreset>,
+}
+
+impl Status {
+    /// Returns true if no audio is output
+    pub fn muted(&self) -> bool {
+        self.shutdown || !(self.speaker_left || self.speaker_right)
+    }
+}
+
+impl Faults {
+    /// Returns true if any fault is set
+    pub fn any(&self) -> bool {
+        self.fault_r || self.fault_l || self.thermal
+    }
+}
+
 /// Compression Ratio
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum CompressionRatio {
     /// Ratio 1:1
     Ratio1 = 0b00,
@@ -37,7 +215,22 @@ pub enum CompressionRatio {
     Ratio8 = 0b11,
 }
 
+/// From the register field value, the rejected value is the error
+impl TryFrom<u8> for CompressionRatio {
+    type Error = u8;
+
+    fn try_from(bits: u8) -> Result<Self, u8> {
+        match bits {
+            0..=0b11 => Ok(compression_ratio_from_bits(bits)),
+            _ => Err(bits),
+        }
+    }
+}
+
 /// Noise Gate Threshold
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum NoiseGateThreshold {
     Ngt20mV = 0b11,
     Ngt10mV = 0b10,
@@ -45,34 +238,213 @@ pub enum NoiseGateThreshold {
     Ngt1mV = 0b00,
 }
 
+/// From the register field value, the rejected value is the error
+impl TryFrom<u8> for NoiseGateThreshold {
+    type Error = u8;
+
+    fn try_from(bits: u8) -> Result<Self, u8> {
+        match bits {
+            0..=0b11 => Ok(noise_gate_threshold_from_bits(bits)),
+            _ => Err(bits),
+        }
+    }
+}
+
 /// Automatic Gain Control Presets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "minicbor", derive(minicbor::Encode, minicbor::Decode))]
+#[cfg_attr(feature = "minicbor", cbor(index_only))]
 pub enum AgcPreset {
+    #[cfg_attr(feature = "minicbor", n(0))]
     Pop,
+    #[cfg_attr(feature = "minicbor", n(1))]
     Classical,
+    #[cfg_attr(feature = "minicbor", n(2))]
     Jazz,
+    #[cfg_attr(feature = "minicbor", n(3))]
     Rap,
+    #[cfg_attr(feature = "minicbor", n(4))]
     Rock,
+    #[cfg_attr(feature = "minicbor", n(5))]
     Voice,
 }
 
-impl<I2C, E> Tpa2016d2<I2C>
-where
-    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
-{
+impl AgcPreset {
+    /// All presets
+    pub const ALL: [AgcPreset; 6] = [
+        AgcPreset::Pop,
+        AgcPreset::Classical,
+        AgcPreset::Jazz,
+        AgcPreset::Rap,
+        AgcPreset::Rock,
+        AgcPreset::Voice,
+    ];
+
+    /// Name of the preset
+    pub fn name(self) -> &'static str {
+        match self {
+            AgcPreset::Pop => "Pop",
+            AgcPreset::Classical => "Classical",
+            AgcPreset::Jazz => "Jazz",
+            AgcPreset::Rap => "Rap",
+            AgcPreset::Rock => "Rock",
+            AgcPreset::Voice => "Voice",
+        }
+    }
+
+    /// The AGC settings of the preset
+    ///
+    /// The presets do not set the max gain, it is the power-on value of 30 dB.
+    pub fn config(self) -> AgcConfig {
+        let (compression_ratio, attack_time, release_time, hold_time, fixed_gain, limiter_level) =
+            self.register_values();
+        AgcConfig {
+            attack_time,
+            release_time,
+            hold_time,
+            fixed_gain,
+            max_gain: Config::DEFAULT.agc.max_gain,
+            output_limiter_level: limiter_level & limits::LIMITER_LEVEL_CODE_MAX,
+            output_limiter: true,
+            compression_ratio,
+        }
+    }
+
+    /// Register values of the preset as
+    /// (ratio, attack, release, hold, fixed gain, limiter level)
+    pub(crate) fn register_values(self) -> (CompressionRatio, u8, u8, u8, u8, u8) {
+        use AgcPreset::*;
+        use CompressionRatio::*;
+
+        // From the data sheet, release and hold time in 0.1 ms
+        let (cr, atk, rel_time, hold_time, fixed_gain, limiter_level) = match self {
+            Pop => (Ratio4, 0b00_0010, 9864, 1370, 6, 0b11_1100),
+            Classical => (Ratio2, 0b00_0010, 11508, 1370, 6, 0b11_1101),
+            Jazz => (Ratio2, 0b00_0110, 32880, 0, 6, 0b11_1101),
+            Rap => (Ratio4, 0b00_0010, 16440, 0, 6, 0b11_1100),
+            Rock => (Ratio2, 0b00_0011, 41100, 0, 6, 0b11_1101),
+            Voice => (Ratio4, 0b00_0010, 16440, 0, 6, 0b11_1110),
+        };
+
+        let rel_time = release_time_to_u6(rel_time);
+        let hold_time = hold_time_to_u6(hold_time);
+
+        (cr, atk, rel_time, hold_time, fixed_gain, limiter_level)
+    }
+}
+
+/// From the index in [`AgcPreset::ALL`], the rejected value is the error
+impl TryFrom<u8> for AgcPreset {
+    type Error = u8;
+
+    fn try_from(idx: u8) -> Result<Self, u8> {
+        AgcPreset::ALL.get(idx as usize).copied().ok_or(idx)
+    }
+}
+
+/// How the outputs are silenced while several registers are rewritten
+///
+/// See [`Tpa2016d2::set_quiet_reconfig`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QuietReconfig {
+    /// Write the registers with the outputs active
+    Off,
+    /// Disable the speakers during the update
+    Mute,
+    /// Put the device in software shutdown during the update
+    Shutdown,
+}
+
+/// From the discriminant, the rejected value is the error
+impl TryFrom<u8> for QuietReconfig {
+    type Error = u8;
+
+    fn try_from(val: u8) -> Result<Self, u8> {
+        match val {
+            0 => Ok(QuietReconfig::Off),
+            1 => Ok(QuietReconfig::Mute),
+            2 => Ok(QuietReconfig::Shutdown),
+            _ => Err(val),
+        }
+    }
+}
+
+/// A single configuration change, as performed by one of the setters
+///
+/// Lets changes be stored and applied later, see [`scheduler`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigChange {
+    /// Enable or disable the left and right speaker
+    SpeakerEnable {
+        left: bool,
+        right: bool,
+    },
+    /// Put the device in software shutdown
+    Shutdown,
+    /// Wake the device from software shutdown
+    Wake,
+    /// Enable or disable the noise gate
+    NoiseGate(bool),
+    /// Attack time register value
+    AttackTime(u8),
+    /// Release time register value
+    ReleaseTime(u8),
+    /// Hold time register value
+    HoldTime(u8),
+    /// Fixed gain register value
+    Gain(u8),
+    NoiseGateThreshold(NoiseGateThreshold),
+    /// Output limiter level register value
+    OutputLimiterLevel(u8),
+    CompressionRatio(CompressionRatio),
+    AgcPreset(AgcPreset),
+    /// Max gain register value, 18 dB + value
+    MaxGain(u8),
+}
+
+/// Source of monotonic time in milliseconds
+///
+/// The value is allowed to wrap around.
+pub trait Clock {
+    fn now_ms(&mut self) -> u32;
+}
+
+#[cfg(driver)]
+impl<I2C> Tpa2016d2<I2C> {
     /// Creates a new device connected through the supplied i2c device
     pub fn new(i2c: I2C) -> Tpa2016d2<I2C> {
-        let regmap = RegisterMap::default();
-
-        Tpa2016d2 { i2c, regmap }
+        Self::new_with_address(i2c, TPA2016_I2C_ADDR)
     }
 
-    /// Read all registers and update our view of the registers
-    pub fn sync(&mut self) -> Result<(), E> {
-        for i in 1..=7 {
-            let val = self.read_reg(i)?;
-            self.regmap.update_map(i, val);
+    /// Creates a new device at the 7 bit i2c address `address`
+    ///
+    /// For buses with an address translator, the device itself always uses
+    /// address 0x58.
+    pub fn new_with_address(i2c: I2C, address: u8) -> Tpa2016d2<I2C> {
+        Tpa2016d2 {
+            i2c,
+            address,
+            core: Core::new(),
+            spl: None,
+            verify: false,
+            deferred: false,
+            dirty: 0,
+            #[cfg(feature = "stats")]
+            stats: stats::BusStats::default(),
+            #[cfg(feature = "stats")]
+            stats_clock: None,
+            #[cfg(feature = "trace")]
+            tracer: trace::Tracer::new(),
         }
-        Ok(())
+    }
+
+    /// The 7 bit i2c address of the device
+    pub fn address(&self) -> u8 {
+        self.address
     }
 
     /// Consume the device and release the i2c device
@@ -80,145 +452,664 @@ where
         self.i2c
     }
 
-    // Get content of register i
-    pub fn device_reg(&mut self, idx: u8) -> Result<u8, E> {
-        Ok(self.regmap.reg_as_byte(idx))
+    /// Read back and compare every register write
+    ///
+    /// A mismatch is reported as [`Error::VerifyFailed`], e.g. to catch
+    /// corrupted writes on a marginal bus. Doubles the bus traffic of the
+    /// setters. The unchecked setters are not verified.
+    pub fn set_write_verify(&mut self, verify: bool) {
+        self.verify = verify;
     }
 
-    /// Enable or disable speakers
-    pub fn speaker_enable(&mut self, le: bool, re: bool) -> Result<(), E> {
-        self.regmap.reg1.SPK_EN_L = le;
-        self.regmap.reg1.SPK_EN_R = re;
-        self.write_regmap_reg(1)
+    /// Defer register writes until [`Tpa2016d2::flush`]
+    ///
+    /// In deferred mode the setters only update the driver's view of the
+    /// registers, each changed register is written once by the next flush.
+    /// Reads still access the bus. Leaving deferred mode does not flush.
+    pub fn set_deferred(&mut self, deferred: bool) {
+        self.deferred = deferred;
     }
 
-    pub fn get_faults(&mut self) -> Result<Faults, E> {
-        // Reload register
-        let val = self.read_reg(1)?;
-        self.regmap.update_map(1, val);
+    /// Returns true if there are deferred writes not yet flushed
+    pub fn has_pending_writes(&self) -> bool {
+        self.dirty != 0
+    }
 
-        Ok(Faults {
-            fault_r: self.regmap.reg1.FAULT_R,
-            fault_l: self.regmap.reg1.FAULT_L,
-            thermal: self.regmap.reg1.Thermal,
-        })
+    /// Install or remove limits protecting the speakers
+    ///
+    /// All following register writes are capped to the profile, see
+    /// [`protection`]. The registers are not written by this call.
+    pub fn set_protection(&mut self, profile: Option<ProtectionProfile>) {
+        self.core.set_protection(profile);
     }
 
-    /// Shutdown the device
-    /// Control, Bias and Oscillators are disabled
-    pub fn disable_device(&mut self) -> Result<(), E> {
-        self.regmap.reg1.SWS = true;
-        self.write_regmap_reg(1)
+    /// The installed protection profile
+    pub fn protection(&self) -> Option<ProtectionProfile> {
+        self.core.protection()
     }
+}
 
-    pub fn noise_gate(&mut self, enable: bool) -> Result<(), E> {
-        self.regmap.reg1.NG_EN = enable;
-        self.write_regmap_reg(1)
+#[cfg(driver)]
+impl<I2C, E> Tpa2016d2<I2C>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    register_ops!();
+
+    fn bus_read_registers(&mut self, reg: Register, values: &mut [u8]) -> Result<(), E> {
+        self.i2c.read_registers(self.address, reg, values)
     }
 
-    pub fn set_attack_time(&mut self, val: u8) -> Result<(), E> {
-        self.regmap.atk_time.set(val);
-        self.write_regmap_reg(2)
+    fn bus_write_registers(&mut self, reg: Register, values: &[u8]) -> Result<(), E> {
+        self.i2c.write_registers(self.address, reg, values)
     }
 
-    /// Set release time / per 6 dB
-    pub fn set_release_time(&mut self, val: u8) -> Result<(), E> {
-        self.regmap.rel_time.set(val);
-        self.write_regmap_reg(3)
+    /// Bring up the device after the supply is applied
+    ///
+    /// Waits for the device to start, checks that it responds and reads all
+    /// registers. The speakers are turned off while [`Config::BUILD_DEFAULT`]
+    /// is written. The device then leaves software shutdown and the speakers
+    /// of the default configuration are enabled last, after the wake-up time.
+    #[cfg(feature = "hal")]
+    pub fn init<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<(), Error<E>> {
+        delay.delay_ms(timings::POWER_UP_MS);
+        self.probe()?;
+        self.sync()?;
+
+        self.speaker_enable(false, false)?;
+        let config = Config::BUILD_DEFAULT;
+        self.apply_config(&Config {
+            speaker_left: false,
+            speaker_right: false,
+            ..config
+        })?;
+        self.enable_device()?;
+        delay.delay_ms(timings::SWS_WAKE_UP_MS);
+
+        self.speaker_enable(config.speaker_left, config.speaker_right)?;
+        delay.delay_ms(timings::ENABLE_SETTLING_MS);
+        Ok(())
     }
 
-    pub fn set_hold_time(&mut self, val: u8) -> Result<(), E> {
-        self.regmap.hold_time.set(val);
-        self.write_regmap_reg(4)
+    /// Read all registers and compare them with the driver's view
+    ///
+    /// Returns the registers that differ, e.g. after the device was power
+    /// cycled or reconfigured by another bus master. The fault flags are not
+    /// compared, and the driver's view is left as is.
+    pub fn verify(&mut self) -> Result<RegisterSet, Error<E>> {
+        let regs = self.dump_registers()?;
+        let mut differ = RegisterSet::new();
+        for (&val, reg) in regs.iter().zip(Register::ALL) {
+            let mask = regmap::verify_mask(reg);
+            if val & mask != self.core.register(reg) & mask {
+                differ.insert(reg);
+            }
+        }
+        Ok(differ)
     }
 
-    /// Set the gain
-    pub fn gain(&mut self, gain: u8) -> Result<(), E> {
-        self.regmap.fixedGain.set(gain);
-        self.write_regmap_reg(5)
+    /// Record bus operation latencies using `now_us`, a µs time source
+    ///
+    /// The time source may wrap around. See [`stats`].
+    #[cfg(feature = "stats")]
+    pub fn set_stats_clock(&mut self, now_us: fn() -> u32) {
+        self.stats_clock = Some(now_us);
     }
 
-    pub fn noise_gate_threshold(&mut self, val: NoiseGateThreshold) -> Result<(), E> {
-        self.regmap.reg6.noise_gate_threshold = val as u8;
-        self.write_regmap_reg(6)
+    /// The latencies recorded so far
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &stats::BusStats {
+        &self.stats
     }
 
-    pub fn output_limiter_level(&mut self, val: u8) -> Result<(), E> {
-        self.regmap.reg6.output_limiter_level = val;
-        self.write_regmap_reg(6)
+    /// Clear the recorded latencies
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats = stats::BusStats::default();
     }
 
-    pub fn compression_ratio(&mut self, ratio: CompressionRatio) -> Result<(), E> {
-        self.regmap.reg7.compression_ratio = ratio as u8;
-        self.write_regmap_reg(7)
+    /// Creates a driver resuming from an exported state, without accessing
+    /// the device
+    pub fn from_state(i2c: I2C, state: &state::DriverState) -> Tpa2016d2<I2C> {
+        let mut amp = Self::new(i2c);
+        amp.import_state(state);
+        amp
     }
 
-    pub fn set_agc_preset(&mut self, preset: AgcPreset) -> Result<(), E> {
-        use AgcPreset::*;
-        use CompressionRatio::*;
+    /// The complete driver state, see [`state`]
+    pub fn export_state(&self) -> state::DriverState {
+        state::DriverState {
+            spl: self.spl,
+            ..self.core.export_state()
+        }
+    }
+
+    /// Restore an exported state, without accessing the device
+    pub fn import_state(&mut self, state: &state::DriverState) {
+        self.core.import_state(state);
+        self.spl = state.spl;
+    }
+
+    /// The protocol core holding the driver's view of the registers
+    pub fn core(&self) -> &Core {
+        &self.core
+    }
+
+    /// Borrow the i2c device, e.g. to talk to another device on the same bus
+    ///
+    /// The driver's view of the registers is not affected, so the amplifier
+    /// should not be accessed through the returned reference.
+    pub fn bus_mut(&mut self) -> &mut I2C {
+        &mut self.i2c
+    }
+
+    /// The device status, see [`Status`]
+    ///
+    /// Does not access the bus, the fault flags are potentially stale.
+    pub fn status(&self) -> Status {
+        self.core.status()
+    }
 
-        // From the data sheet
-        let (cr, atk, rel_time, hold_time, fixed_gain, limiter_level) = match preset {
-            Pop => (Ratio4, 0b00_0010, 986, 137, 6, 0b11_1100),
-            Classical => (Ratio2, 0b00_0010, 1150, 137, 6, 0b11_1101),
-            Jazz => (Ratio2, 0b00_0110, 3288, 0, 6, 0b11_1101),
-            Rap => (Ratio4, 0b00_0010, 1640, 0, 6, 0b11_1100),
-            Rock => (Ratio2, 0b00_0011, 4110, 0, 6, 0b11_1101),
-            Voice => (Ratio4, 0b00_0010, 1640, 0, 6, 0b11_1110),
+    /// The settings changed from the power-on defaults
+    ///
+    /// See [`Config::compare_with_defaults`].
+    pub fn compare_with_defaults(&self) -> ConfigPatch {
+        self.core.config().compare_with_defaults()
+    }
+
+    /// Estimate the average supply current in µA for the current configuration
+    ///
+    /// See [`power`] for the model used.
+    pub fn estimate_current_ua(&self, op: &power::OperatingPoint) -> u32 {
+        if self.core.regmap.sws() {
+            return power::SHUTDOWN_CURRENT_UA;
+        }
+        let channels = self.core.regmap.spk_en_l() as u8 + self.core.regmap.spk_en_r() as u8;
+        power::estimate_current_ua(&self.agc_config(), channels, op)
+    }
+
+    /// The state of `channel`, as of the last read of the fault flags
+    pub fn channel_state(&self, channel: Channel) -> ChannelState {
+        self.core.channel_state(channel)
+    }
+
+    /// Recover from short circuit faults
+    ///
+    /// Reads the fault flags. For each channel with a short circuit flagged,
+    /// the flag is reset and the channel is disabled and enabled again. The
+    /// other channel keeps its state. Ends a [`Tpa2016d2::mute`].
+    ///
+    /// Returns true if no short circuit is flagged after the recovery, false
+    /// if the short is still present.
+    pub fn recover_faults(&mut self) -> Result<bool, Error<E>> {
+        let faults = self.get_faults()?;
+        if !faults.fault_l && !faults.fault_r {
+            return Ok(true);
+        }
+
+        let (left, right) = self.speakers_enabled();
+        self.core.regmap.set_fault_l(false);
+        self.core.regmap.set_fault_r(false);
+        self.speaker_enable(left && !faults.fault_l, right && !faults.fault_r)?;
+        self.speaker_enable(left || faults.fault_l, right || faults.fault_r)?;
+
+        let faults = self.get_faults()?;
+        Ok(!faults.fault_l && !faults.fault_r)
+    }
+
+    /// The fixed gain in dB, including the gain trim
+    pub fn fixed_gain_db(&self) -> i8 {
+        agc::fixed_gain_to_db(self.agc_config().fixed_gain)
+    }
+
+    /// Set the calibration offset in 0.5 dB added to the fixed gain
+    ///
+    /// Used to compensate for speaker sensitivity spread. The offset applies to
+    /// all gain settings made after this call, the application keeps using the
+    /// nominal values. The trimmed gain is rounded to whole dB and clamped to
+    /// the -28 to 30 dB range of the device.
+    pub fn set_gain_trim(&mut self, half_db: i8) {
+        self.core.set_gain_trim(half_db);
+    }
+
+    /// The calibration offset in 0.5 dB
+    pub fn gain_trim(&self) -> i8 {
+        self.core.gain_trim()
+    }
+
+    /// Set the SPL calibration used by [`Tpa2016d2::set_target_spl`]
+    pub fn set_spl_calibration(&mut self, calibration: Option<SplCalibration>) {
+        self.spl = calibration;
+    }
+
+    /// Set the fixed gain for a target SPL at the calibration distance
+    ///
+    /// Returns the gain in dB that was set, or `None` without touching the
+    /// device if there is no SPL calibration. See [`spl`].
+    pub fn set_target_spl(&mut self, spl_db: u8) -> Result<Option<i8>, Error<E>> {
+        let gain_db = match self.spl {
+            Some(cal) => cal.gain_db(spl_db),
+            None => return Ok(None),
         };
+        self.gain(agc::fixed_gain_code(gain_db))?;
+        Ok(Some(gain_db))
+    }
 
-        let rel_time = release_time_to_u6(rel_time);
-        let hold_time = hold_time_to_u6(hold_time);
+    /// Move to an AGC configuration gradually over `duration_ms`
+    ///
+    /// The fixed gain is stepped in 1 dB and the output limiter level in 0.5 dB
+    /// steps, blocking on `delay` in between. Attack, release and hold time,
+    /// max gain and compression ratio are switched at the midpoint.
+    /// Configurations that fail [`AgcConfig::validate`] are rejected with
+    /// [`Error::InvalidValue`].
+    #[cfg(feature = "hal")]
+    pub fn crossfade_to<D: DelayMs<u32>>(
+        &mut self,
+        config: &AgcConfig,
+        duration_ms: u32,
+        delay: &mut D,
+    ) -> Result<(), Error<E>> {
+        check(config.validate().is_ok())?;
+        let from = self.agc_config();
+        let from_gain = agc::fixed_gain_to_db(from.fixed_gain) as i32;
+        let to_gain = agc::fixed_gain_to_db(self.trimmed_gain(config.fixed_gain)) as i32;
+        let from_limiter = from.output_limiter_level as i32;
+        let to_limiter = (config.output_limiter_level & 0b1_1111) as i32;
+
+        let steps = (to_gain - from_gain)
+            .abs()
+            .max((to_limiter - from_limiter).abs())
+            .max(1);
+        let step_ms = duration_ms / steps as u32;
+        let mut switched = false;
+
+        for step in 1..=steps {
+            if !switched && step * 2 >= steps {
+                self.core.regmap.set_attack_time(config.attack_time);
+                self.core.regmap.set_release_time(config.release_time);
+                self.core.regmap.set_hold_time(config.hold_time);
+                self.core.regmap.set_max_gain(U4::masked(config.max_gain));
+                self.core
+                    .regmap
+                    .set_compression_ratio(U2::masked(config.compression_ratio as u8));
+                // The limiter is enabled before and disabled after the ratio
+                // is written, it is only disabled at 1:1
+                let limiter_changed =
+                    config.output_limiter == self.core.regmap.output_limiter_disable();
+                self.core
+                    .regmap
+                    .set_output_limiter_disable(!config.output_limiter);
+                if limiter_changed && config.output_limiter {
+                    self.write_regmap_reg(Register::Agc1)?;
+                }
+                for reg in [
+                    Register::AttackTime,
+                    Register::ReleaseTime,
+                    Register::HoldTime,
+                    Register::Agc2,
+                ] {
+                    self.write_regmap_reg(reg)?;
+                }
+                if limiter_changed && !config.output_limiter {
+                    self.write_regmap_reg(Register::Agc1)?;
+                }
+                switched = true;
+            }
 
-        self.regmap.atk_time.set(atk);
-        self.regmap.rel_time.set(rel_time);
-        self.regmap.hold_time.set(hold_time);
-        self.regmap.fixedGain.set(fixed_gain);
-        self.regmap.reg6.output_limiter_level = limiter_level;
-        self.regmap.reg7.compression_ratio = cr as u8;
+            let gain = from_gain + (to_gain - from_gain) * step / steps;
+            let gain = agc::fixed_gain_code(gain as i8);
+            if gain != self.core.regmap.fixed_gain() {
+                self.write_gain_raw(gain)?;
+            }
 
-        // Send the new settings to the device
-        for rid in 2..=7 {
-            self.write_regmap_reg(rid)?;
+            let limiter = (from_limiter + (to_limiter - from_limiter) * step / steps) as u8;
+            if limiter != self.core.regmap.output_limiter_level().value() {
+                self.core
+                    .regmap
+                    .set_output_limiter_level(U5::masked(limiter));
+                self.write_regmap_reg(Register::Agc1)?;
+            }
+
+            if step < steps {
+                delay.delay_ms(step_ms);
+            }
         }
 
         Ok(())
     }
 
-    fn write_regmap_reg(&mut self, idx: u8) -> Result<(), E> {
-        let b = self.regmap.reg_as_byte(idx);
-        self.write_reg(idx, b)
+    /// Move to an AGC preset gradually over `duration_ms`, see
+    /// [`Tpa2016d2::crossfade_to`]
+    ///
+    /// Avoids the audible jump of [`Tpa2016d2::set_agc_preset`] when switching
+    /// between genres or profiles.
+    #[cfg(feature = "hal")]
+    pub fn crossfade_to_preset<D: DelayMs<u32>>(
+        &mut self,
+        preset: AgcPreset,
+        duration_ms: u32,
+        delay: &mut D,
+    ) -> Result<(), Error<E>> {
+        self.crossfade_to(&preset.config(), duration_ms, delay)?;
+        self.core.preset = Some(preset);
+        Ok(())
+    }
+
+    /// Ramp the fixed gain to `gain` in 1 dB steps, waiting `step_ms` between
+    /// steps
+    ///
+    /// Avoids the pop of a gain jump. The gain trim is added to `gain`, gains
+    /// outside the register range are rejected with [`Error::InvalidValue`].
+    #[cfg(feature = "hal")]
+    pub fn fade_to<D: DelayMs<u32>>(
+        &mut self,
+        gain: u8,
+        step_ms: u32,
+        delay: &mut D,
+    ) -> Result<(), Error<E>> {
+        check(agc::fixed_gain_db(gain).is_some())?;
+        let to = agc::fixed_gain_to_db(self.trimmed_gain(gain));
+        self.ramp_gain(to, step_ms, delay)
     }
 
-    fn read_reg(&mut self, regidx: u8) -> Result<u8, E> {
-        if regidx < 1 || regidx > 7 {
-            return Ok(0);
+    /// Ramp the gain down to the minimum and mute, see [`Tpa2016d2::mute`]
+    ///
+    /// The fixed gain is restored while muted, so [`Tpa2016d2::unmute`] or
+    /// [`Tpa2016d2::unmute_fade_in`] return to the previous settings.
+    #[cfg(feature = "hal")]
+    pub fn fade_out_mute<D: DelayMs<u32>>(
+        &mut self,
+        step_ms: u32,
+        delay: &mut D,
+    ) -> Result<(), Error<E>> {
+        let gain = self.core.regmap.fixed_gain();
+        self.ramp_gain(self.min_gain_db(), step_ms, delay)?;
+        self.mute()?;
+        self.write_gain_raw(gain)
+    }
+
+    /// Unmute at the minimum gain and ramp up to the fixed gain
+    ///
+    /// Does nothing if not muted.
+    #[cfg(feature = "hal")]
+    pub fn unmute_fade_in<D: DelayMs<u32>>(
+        &mut self,
+        step_ms: u32,
+        delay: &mut D,
+    ) -> Result<(), Error<E>> {
+        if !self.core.is_muted() {
+            return Ok(());
         }
+        let to = agc::fixed_gain_to_db(self.core.regmap.fixed_gain());
+        self.write_gain_raw(agc::fixed_gain_code(self.min_gain_db().min(to)))?;
+        self.unmute()?;
+        delay.delay_ms(step_ms);
+        self.ramp_gain(to, step_ms, delay)
+    }
 
-        let mut regbuf = [0u8; 1];
-        self.i2c
-            .write_read(TPA2016_I2C_ADDR, &[regidx], &mut regbuf)?;
+    /// The lowest fixed gain for the compression ratio, in dB
+    fn min_gain_db(&self) -> i8 {
+        if self.core.regmap.compression_ratio().value() == CompressionRatio::Ratio1 as u8 {
+            limits::MIN_FIXED_GAIN_DB
+        } else {
+            limits::MIN_FIXED_GAIN_DB_COMPRESSED
+        }
+    }
 
-        Ok(regbuf[0])
+    /// Step the fixed gain register to `to` dB, without the gain trim
+    #[cfg(feature = "hal")]
+    fn ramp_gain<D: DelayMs<u32>>(
+        &mut self,
+        to: i8,
+        step_ms: u32,
+        delay: &mut D,
+    ) -> Result<(), Error<E>> {
+        let mut db = agc::fixed_gain_to_db(self.core.regmap.fixed_gain());
+        while db != to {
+            db += if to > db { 1 } else { -1 };
+            self.write_gain_raw(agc::fixed_gain_code(db))?;
+            if db != to {
+                delay.delay_ms(step_ms);
+            }
+        }
+        Ok(())
     }
 
-    fn write_reg(&mut self, regaddr: u8, value: u8) -> Result<(), E> {
-        let regbuf = [regaddr, value];
-        self.i2c.write(TPA2016_I2C_ADDR, &regbuf)
+    /// Silence the outputs while presets and configurations are written
+    ///
+    /// Avoids audible artifacts from the registers being in a mixed state
+    /// during the update. Register 1 is restored when done, if a write fails
+    /// the device is left silenced.
+    pub fn set_quiet_reconfig(&mut self, mode: QuietReconfig) {
+        self.core.set_quiet_reconfig(mode);
+    }
+
+    /// Apply a single configuration change
+    pub fn apply_change(&mut self, change: ConfigChange) -> Result<(), Error<E>> {
+        Tpa2016Control::apply_change(self, change)
+    }
+
+    fn trimmed_gain(&self, gain: u8) -> u8 {
+        self.core.trimmed_gain(gain)
+    }
+
+    /// Write all registers from the driver's view, AGC settings first
+    pub(crate) fn rewrite_registers(&mut self) -> Result<(), Error<E>> {
+        let commands = self.core.write_registers(0b1111_1100);
+        self.execute(commands)?;
+        self.write_regmap_reg(Register::Control)
     }
 }
 
+/// Getters
+///
+/// Taken from the driver's view of the registers without accessing the bus,
+/// as register values.
+#[cfg(driver)]
+impl<I2C> Tpa2016d2<I2C> {
+    /// The output limiter level in 0.1 dBV
+    pub fn output_limiter_dbv_x10(&self) -> i16 {
+        agc::limiter_level_dbv_x10(self.agc_config().output_limiter_level)
+    }
+
+    /// The max gain register value, 18 dB + value
+    pub fn max_gain(&self) -> u8 {
+        self.agc_config().max_gain
+    }
+
+    /// The max gain of the AGC in dB
+    pub fn get_max_gain_db(&self) -> u8 {
+        agc::max_gain_db(self.max_gain()) as u8
+    }
+
+    /// The complete configuration
+    pub fn config(&self) -> Config {
+        self.core.config()
+    }
+
+    /// The decoded registers, the fault flags are potentially stale
+    pub fn registers(&self) -> &RegisterMap {
+        self.core.registers()
+    }
+
+    /// The AGC settings
+    pub fn agc_config(&self) -> AgcConfig {
+        self.core.agc_config()
+    }
+
+    /// Left and right speaker enable
+    pub fn speakers_enabled(&self) -> (bool, bool) {
+        let regs = &self.core.regmap;
+        (regs.spk_en_l(), regs.spk_en_r())
+    }
+
+    /// Returns true if muted by [`Tpa2016d2::mute`]
+    pub fn is_muted(&self) -> bool {
+        self.core.is_muted()
+    }
+
+    /// Returns true in software shutdown
+    pub fn is_shutdown(&self) -> bool {
+        self.core.regmap.sws()
+    }
+
+    pub fn noise_gate_enabled(&self) -> bool {
+        self.core.regmap.ng_en()
+    }
+
+    pub fn get_noise_gate_threshold(&self) -> NoiseGateThreshold {
+        noise_gate_threshold_from_bits(self.core.regmap.noise_gate_threshold().value())
+    }
+
+    /// The attack time register value
+    pub fn attack_time(&self) -> u8 {
+        self.agc_config().attack_time
+    }
+
+    /// The release time register value
+    pub fn release_time(&self) -> u8 {
+        self.agc_config().release_time
+    }
+
+    /// The hold time register value
+    pub fn hold_time(&self) -> u8 {
+        self.agc_config().hold_time
+    }
+
+    /// The fixed gain register value, including the gain trim
+    pub fn fixed_gain(&self) -> u8 {
+        self.agc_config().fixed_gain
+    }
+
+    /// The output limiter level register value
+    pub fn get_output_limiter_level(&self) -> u8 {
+        self.agc_config().output_limiter_level
+    }
+
+    pub fn output_limiter_enabled(&self) -> bool {
+        !self.core.regmap.output_limiter_disable()
+    }
+
+    pub fn get_compression_ratio(&self) -> CompressionRatio {
+        self.agc_config().compression_ratio
+    }
+}
+
+/// Unchecked setters
+///
+/// For hot paths with values that are already known to be valid, e.g. a
+/// precomputed ramp. The values are register codes written as is: no gain
+/// trim, no clamping or masking, and the bus error is returned unchanged.
+/// Values outside the register field write reserved bits. Prefer the regular
+/// setters elsewhere. An installed [`ProtectionProfile`] still caps them.
+#[cfg(driver)]
+impl<I2C, E> Tpa2016d2<I2C>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    /// Set the fixed gain register, without the gain trim
+    pub fn gain_unchecked(&mut self, gain: u8) -> Result<(), E> {
+        self.write_unchecked(Register::FixedGain, gain)
+    }
+
+    /// Set the attack time register
+    pub fn set_attack_time_unchecked(&mut self, val: u8) -> Result<(), E> {
+        self.write_unchecked(Register::AttackTime, val)
+    }
+
+    /// Set the release time register
+    pub fn set_release_time_unchecked(&mut self, val: u8) -> Result<(), E> {
+        self.write_unchecked(Register::ReleaseTime, val)
+    }
+
+    /// Set the hold time register
+    pub fn set_hold_time_unchecked(&mut self, val: u8) -> Result<(), E> {
+        self.write_unchecked(Register::HoldTime, val)
+    }
+
+    /// Set the output limiter level, keeping the rest of register 6
+    pub fn output_limiter_level_unchecked(&mut self, val: u8) -> Result<(), E> {
+        let reg6 = self.core.regmap.reg_as_byte(Register::Agc1) & !0b1_1111 | val;
+        self.write_unchecked(Register::Agc1, reg6)
+    }
+
+    fn write_unchecked(&mut self, reg: Register, val: u8) -> Result<(), E> {
+        self.core.receive(reg, val);
+        self.core.preset = None;
+        let val = match self.core.protection() {
+            Some(_) => {
+                self.core.protect(reg);
+                self.core.register(reg)
+            }
+            None => val,
+        };
+        self.write_reg(reg, &[val])
+    }
+}
+
+/// [`Error::InvalidValue`] unless `valid`
+#[cfg(driver)]
+fn check<E>(valid: bool) -> Result<(), Error<E>> {
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidValue)
+    }
+}
+
+fn compression_ratio_from_bits(bits: u8) -> CompressionRatio {
+    match bits & 0b11 {
+        0b00 => CompressionRatio::Ratio1,
+        0b01 => CompressionRatio::Ratio2,
+        0b10 => CompressionRatio::Ratio4,
+        _ => CompressionRatio::Ratio8,
+    }
+}
+
+fn noise_gate_threshold_from_bits(bits: u8) -> NoiseGateThreshold {
+    match bits & 0b11 {
+        0b00 => NoiseGateThreshold::Ngt1mV,
+        0b01 => NoiseGateThreshold::Ngt4mV,
+        0b10 => NoiseGateThreshold::Ngt10mV,
+        _ => NoiseGateThreshold::Ngt20mV,
+    }
+}
+
+// The time conversions avoid division, which is a library call on cores
+// without a divider. Multiplying by 2^k / d and shifting gives the same result
+// as dividing by d over the register range, checked by the tests.
+
+/// Attack time register value from 0.1 µs, v / 1067 saturated at 63
+const fn attack_time_to_u6(v: u32) -> u8 {
+    if v >= 64 * 1067 {
+        return 0x3F;
+    }
+    // 62895 / 2^26 ~ 1 / 1067
+    ((v * 62895) >> 26) as u8
+}
+
+/// Release time register value from 0.1 ms, v / 1644 saturated at 63
 const fn release_time_to_u6(v: u32) -> u8 {
-    (v / 1644) as u8
+    if v >= 64 * 1644 {
+        return 0x3F;
+    }
+    // 1644 = 4 * 411, 20411 / 2^23 ~ 1 / 411
+    (((v >> 2) * 20411) >> 23) as u8
 }
 
+/// Hold time register value from 0.1 ms, v / 137 saturated at 63
 const fn hold_time_to_u6(v: u32) -> u8 {
-    (v / 137) as u8
+    if v >= 64 * 137 {
+        return 0x3F;
+    }
+    // 3827 / 2^19 ~ 1 / 137
+    ((v * 3827) >> 19) as u8
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::regmap::RegisterMap;
 
     #[test]
     fn release_time_conv() {
@@ -231,24 +1122,76 @@ mod tests {
 
     #[test]
     fn hold_time_conv() {
-        let tests = [(137, 0b00_0001), (0411, 0b00_0011), (8631, 0b11_1111)];
+        let tests = [(137, 0b00_0001), (411, 0b00_0011), (8631, 0b11_1111)];
         for &(input, bitval) in &tests {
             let res = hold_time_to_u6(input);
             assert_eq!(res, bitval);
         }
     }
 
+    #[test]
+    fn enums_from_u8() {
+        assert_eq!(CompressionRatio::try_from(3), Ok(CompressionRatio::Ratio8));
+        assert_eq!(CompressionRatio::try_from(4), Err(4));
+        assert_eq!(
+            NoiseGateThreshold::try_from(0),
+            Ok(NoiseGateThreshold::Ngt1mV)
+        );
+        assert_eq!(NoiseGateThreshold::try_from(0xFF), Err(0xFF));
+        assert_eq!(QuietReconfig::try_from(2), Ok(QuietReconfig::Shutdown));
+        assert_eq!(QuietReconfig::try_from(3), Err(3));
+
+        for (idx, &preset) in AgcPreset::ALL.iter().enumerate() {
+            assert_eq!(AgcPreset::try_from(idx as u8), Ok(preset));
+        }
+        assert!(AgcPreset::try_from(AgcPreset::ALL.len() as u8).is_err());
+    }
+
+    #[test]
+    fn time_conv_matches_division() {
+        for v in 0..70 * 1067 {
+            assert_eq!(attack_time_to_u6(v), (v / 1067).min(63) as u8);
+        }
+        for v in 0..70 * 1644 {
+            assert_eq!(release_time_to_u6(v), (v / 1644).min(63) as u8);
+        }
+        for v in 0..70 * 137 {
+            assert_eq!(hold_time_to_u6(v), (v / 137).min(63) as u8);
+        }
+        assert_eq!(attack_time_to_u6(u32::MAX), 63);
+        assert_eq!(release_time_to_u6(u32::MAX), 63);
+        assert_eq!(hold_time_to_u6(u32::MAX), 63);
+    }
+
+    #[test]
+    fn preset_register_values() {
+        use CompressionRatio::*;
+
+        // The data sheet times, release in 164.4 ms and hold in 13.7 ms steps
+        let expected = [
+            (AgcPreset::Pop, (Ratio4, 2, 6, 10, 6, 0b11_1100)),
+            (AgcPreset::Classical, (Ratio2, 2, 7, 10, 6, 0b11_1101)),
+            (AgcPreset::Jazz, (Ratio2, 6, 20, 0, 6, 0b11_1101)),
+            (AgcPreset::Rap, (Ratio4, 2, 10, 0, 6, 0b11_1100)),
+            (AgcPreset::Rock, (Ratio2, 3, 25, 0, 6, 0b11_1101)),
+            (AgcPreset::Voice, (Ratio4, 2, 10, 0, 6, 0b11_1110)),
+        ];
+        for (preset, values) in expected {
+            assert_eq!(preset.register_values(), values, "{:?}", preset);
+        }
+    }
+
     #[test]
     fn test_register_defaults() {
         let regmap = RegisterMap::default();
 
-        let r1 = regmap.reg_as_byte(1);
-        let r2 = regmap.reg_as_byte(2);
-        let r3 = regmap.reg_as_byte(3);
-        let r4 = regmap.reg_as_byte(4);
-        let r5 = regmap.reg_as_byte(5);
-        let r6 = regmap.reg_as_byte(6);
-        let r7 = regmap.reg_as_byte(7);
+        let r1 = regmap.reg_as_byte(Register::Control);
+        let r2 = regmap.reg_as_byte(Register::AttackTime);
+        let r3 = regmap.reg_as_byte(Register::ReleaseTime);
+        let r4 = regmap.reg_as_byte(Register::HoldTime);
+        let r5 = regmap.reg_as_byte(Register::FixedGain);
+        let r6 = regmap.reg_as_byte(Register::Agc1);
+        let r7 = regmap.reg_as_byte(Register::Agc2);
 
         assert_eq!(r1, 0xC3);
         assert_eq!(r2, 0x05);
@@ -259,3 +1202,587 @@ mod tests {
         assert_eq!(r7, 0xC2);
     }
 }
+
+#[cfg(all(test, feature = "hal"))]
+mod driver_tests {
+    use super::*;
+    use crate::mock::TestDelay;
+
+    #[test]
+    fn address() {
+        let mut i2c = mock::MockI2c::new();
+        i2c.addr = 0x4C;
+        let mut amp = Tpa2016d2::new_with_address(i2c, 0x4C);
+        assert_eq!(amp.address(), 0x4C);
+        amp.gain(12).unwrap();
+        amp.sync().unwrap();
+        assert_eq!(amp.fixed_gain(), 12);
+    }
+
+    #[test]
+    fn recover_faults() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        assert!(amp.recover_faults().unwrap());
+        assert!(amp.release().writes.is_empty());
+
+        // Left turned off by the device after a short circuit
+        let mut i2c = mock::MockI2c::new();
+        i2c.regs[1] = 0x83 | 1 << 3;
+        let mut amp = Tpa2016d2::new(i2c);
+        assert!(amp.recover_faults().unwrap());
+        assert_eq!(amp.channel_state(Channel::Left), ChannelState::Enabled);
+        assert_eq!(amp.release().writes, [(1, 0x83), (1, 0xC3)]);
+
+        // The short is still present
+        let mut i2c = mock::MockI2c::new();
+        i2c.regs[1] = 0x83 | 1 << 3;
+        i2c.stuck = Some((1, 0x83 | 1 << 3));
+        let mut amp = Tpa2016d2::new(i2c);
+        assert!(!amp.recover_faults().unwrap());
+    }
+
+    #[test]
+    fn verify() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.gain(12).unwrap();
+        assert!(amp.verify().unwrap().is_empty());
+
+        // Power cycled, with a fault flag set
+        amp.bus_mut().regs = mock::MockI2c::new().regs;
+        amp.bus_mut().regs[1] |= 1 << 2;
+        let differ = amp.verify().unwrap();
+        assert!(differ.contains(Register::FixedGain));
+        assert_eq!(differ.iter().count(), 1);
+        assert_eq!(amp.fixed_gain(), 12);
+    }
+
+    #[test]
+    fn get_agc() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.set_agc_preset(AgcPreset::Jazz).unwrap();
+        assert_eq!(amp.get_agc().unwrap(), AgcPreset::Jazz.config());
+
+        // Power-on values after a brown-out
+        amp.bus_mut().regs = mock::MockI2c::new().regs;
+        assert_eq!(amp.get_agc().unwrap(), Config::DEFAULT.agc);
+        assert_eq!(amp.agc_config(), AgcPreset::Jazz.config());
+    }
+
+    #[test]
+    fn gain_trim() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        amp.set_gain_trim(-3);
+        amp.gain(6).unwrap();
+        amp.gain(0b11_1100).unwrap(); // -4 dB
+        amp.gain(0b11_1110).unwrap(); // -2 dB
+        amp.set_gain_trim(4);
+        amp.gain(29).unwrap();
+
+        let i2c = amp.release();
+        assert_eq!(
+            i2c.writes,
+            [(5, 5), (5, 0b11_1011), (5, 0b11_1101), (5, 30)]
+        );
+    }
+
+    #[test]
+    fn unchecked_setters() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        amp.set_gain_trim(4);
+        amp.gain_unchecked(12).unwrap();
+        amp.set_release_time_unchecked(0x3F).unwrap();
+        amp.output_limiter_level_unchecked(0x1F).unwrap();
+
+        assert_eq!(amp.status().fixed_gain_db, 12);
+        assert_eq!(amp.release().writes, [(5, 12), (3, 0x3F), (6, 0x3F)]);
+    }
+
+    #[test]
+    fn custom_preset() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        for preset in AgcPreset::ALL {
+            assert_eq!(preset.config().validate(), Ok(()));
+        }
+
+        let mut config = AgcPreset::Rock.config();
+        config.max_gain = 8;
+        amp.set_agc_custom(&agc::CustomPreset::new("Rock, bookshelf", config))
+            .unwrap();
+        assert_eq!(amp.agc_config(), config);
+        assert_eq!(amp.status().preset, None);
+
+        config.fixed_gain = 0b11_1110; // -2 dB with compression
+        let invalid = agc::CustomPreset::new("invalid", config);
+        assert_eq!(amp.set_agc_custom(&invalid), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn deferred_writes() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.set_deferred(true);
+
+        amp.gain(12).unwrap();
+        amp.set_attack_time(1).unwrap();
+        amp.gain(18).unwrap();
+        amp.speaker_enable(false, true).unwrap();
+        assert!(amp.has_pending_writes());
+        assert!(amp.bus_mut().writes.is_empty());
+
+        amp.flush().unwrap();
+        assert!(!amp.has_pending_writes());
+        assert_eq!(amp.release().writes, [(1, 0x83), (2, 1), (5, 18)]);
+    }
+
+    #[test]
+    fn deferred_read_keeps_pending() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.set_deferred(true);
+
+        amp.speaker_enable(false, false).unwrap();
+        amp.gain(12).unwrap();
+        amp.bus_mut().regs[1] |= 1 << 2;
+        assert!(amp.get_faults().unwrap().thermal);
+        amp.sync().unwrap();
+        assert_eq!(amp.speakers_enabled(), (false, false));
+
+        amp.flush().unwrap();
+        let writes = amp.release().writes;
+        assert_eq!(writes[0].1 & 0xC0, 0);
+        assert_eq!(writes[1], (5, 12));
+    }
+
+    #[test]
+    fn burst_write() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        amp.set_agc_preset(AgcPreset::Jazz).unwrap();
+        amp.apply_config(&Config::default()).unwrap();
+        let i2c = amp.release();
+        assert_eq!(i2c.transactions, 3);
+        assert_eq!(i2c.writes.len(), 13);
+        assert_eq!(i2c.regs, [0x00, 0xC3, 0x05, 0x0B, 0x00, 0x06, 0x3A, 0xC2]);
+    }
+
+    #[test]
+    fn write_verify() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.set_write_verify(true);
+
+        amp.gain(12).unwrap();
+        // A fault flag raised by the device is not a mismatch
+        amp.bus_mut().regs[1] |= 1 << 2;
+        amp.speaker_enable(true, false).unwrap();
+        assert_eq!(amp.bus_mut().reads, 2);
+
+        amp.bus_mut().stuck = Some((5, 0));
+        assert_eq!(amp.gain(12), Err(Error::VerifyFailed));
+    }
+
+    #[test]
+    fn probe() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.probe().unwrap();
+
+        amp.bus_mut().regs[1] = 0xFD;
+        assert_eq!(amp.probe(), Err(Error::DeviceNotFound));
+
+        amp.bus_mut().fail = true;
+        assert_eq!(amp.probe(), Err(Error::DeviceNotFound));
+    }
+
+    #[test]
+    fn getters() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        assert_eq!(amp.config(), Config::DEFAULT);
+
+        amp.speaker_enable(false, true).unwrap();
+        amp.set_noise_gate(false).unwrap();
+        amp.noise_gate_threshold(NoiseGateThreshold::Ngt20mV)
+            .unwrap();
+        amp.set_attack_time(1).unwrap();
+        amp.set_release_time(2).unwrap();
+        amp.set_hold_time(3).unwrap();
+        amp.gain(4).unwrap();
+        amp.output_limiter_level(5).unwrap();
+        amp.compression_ratio(CompressionRatio::Ratio1).unwrap();
+        amp.output_limiter(false).unwrap();
+
+        assert_eq!(amp.speakers_enabled(), (false, true));
+        assert!(!amp.noise_gate_enabled());
+        assert_eq!(amp.get_noise_gate_threshold(), NoiseGateThreshold::Ngt20mV);
+        assert_eq!(
+            (amp.attack_time(), amp.release_time(), amp.hold_time()),
+            (1, 2, 3)
+        );
+        assert_eq!(amp.fixed_gain(), 4);
+        assert_eq!(amp.get_output_limiter_level(), 5);
+        assert_eq!(amp.get_compression_ratio(), CompressionRatio::Ratio1);
+        assert!(!amp.output_limiter_enabled());
+        assert!(!amp.is_shutdown());
+    }
+
+    #[test]
+    fn time_setters() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        amp.set_attack_time_us(533).unwrap();
+        amp.set_attack_time_us(6722).unwrap();
+        amp.set_release_time_ms(1644).unwrap();
+        amp.set_release_time_ms(10357).unwrap();
+        amp.set_hold_time_ms(6).unwrap();
+        amp.set_hold_time_ms(863).unwrap();
+        assert_eq!(amp.set_attack_time_us(6723), Err(Error::InvalidValue));
+        assert_eq!(amp.set_release_time_ms(10358), Err(Error::InvalidValue));
+        assert_eq!(amp.set_hold_time_ms(864), Err(Error::InvalidValue));
+
+        let writes = amp.release().writes;
+        assert_eq!(writes, [(2, 5), (2, 63), (3, 10), (3, 63), (4, 0), (4, 63)]);
+    }
+
+    #[test]
+    fn limiter_dbv() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        assert_eq!(amp.output_limiter_dbv_x10(), 65);
+
+        amp.set_output_limiter_dbv_x10(-65).unwrap();
+        assert_eq!(amp.get_output_limiter_level(), 0);
+        amp.set_output_limiter_dbv_x10(90).unwrap();
+        assert_eq!(amp.get_output_limiter_level(), 31);
+        assert_eq!(amp.output_limiter_dbv_x10(), 90);
+
+        for invalid in [-70, 95, 12, -64] {
+            assert_eq!(
+                amp.set_output_limiter_dbv_x10(invalid),
+                Err(Error::InvalidValue)
+            );
+        }
+    }
+
+    #[test]
+    fn gain_db() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        amp.gain_db(-28).unwrap();
+        assert_eq!(amp.fixed_gain_db(), -28);
+        amp.gain_db(30).unwrap();
+        assert_eq!(amp.gain_db(-29), Err(Error::InvalidValue));
+        assert_eq!(amp.gain_db(31), Err(Error::InvalidValue));
+        assert_eq!(amp.fixed_gain_db(), 30);
+
+        assert_eq!(amp.release().writes, [(5, 0b10_0100), (5, 30)]);
+    }
+
+    #[test]
+    fn output_limiter() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        assert_eq!(amp.output_limiter(false), Err(Error::InvalidValue));
+        amp.compression_ratio(CompressionRatio::Ratio1).unwrap();
+        amp.output_limiter(false).unwrap();
+        amp.output_limiter(true).unwrap();
+
+        assert_eq!(amp.release().writes, [(7, 0xC0), (6, 0xBA), (6, 0x3A)]);
+    }
+
+    #[test]
+    fn compression_needs_output_limiter() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.disable_agc().unwrap();
+
+        assert_eq!(
+            amp.compression_ratio(CompressionRatio::Ratio4),
+            Err(Error::InvalidValue)
+        );
+        let compress = ConfigPatch {
+            compression_ratio: Some(CompressionRatio::Ratio4),
+            ..ConfigPatch::default()
+        };
+        assert_eq!(amp.apply_patch(&compress), Err(Error::InvalidValue));
+        assert!(!amp.output_limiter_enabled());
+        assert_eq!(amp.get_compression_ratio(), CompressionRatio::Ratio1);
+
+        amp.set_agc_preset(AgcPreset::Jazz).unwrap();
+        assert!(amp.output_limiter_enabled());
+
+        amp.disable_agc().unwrap();
+        amp.set_agc_config(&AgcPreset::Rock.config()).unwrap();
+        assert!(amp.output_limiter_enabled());
+
+        amp.disable_agc().unwrap();
+        amp.apply_config(&Config::DEFAULT).unwrap();
+        assert!(amp.output_limiter_enabled());
+        assert_eq!(amp.get_compression_ratio(), CompressionRatio::Ratio4);
+
+        amp.disable_agc().unwrap();
+        amp.crossfade_to(&AgcPreset::Pop.config(), 0, &mut TestDelay(0))
+            .unwrap();
+        assert!(amp.output_limiter_enabled());
+    }
+
+    #[test]
+    fn max_gain() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        amp.set_max_gain(6).unwrap();
+        assert_eq!(amp.max_gain(), 6);
+        assert_eq!(amp.set_max_gain(13), Err(Error::InvalidValue));
+        assert_eq!(amp.max_gain(), 6);
+
+        amp.max_gain_db(30).unwrap();
+        assert_eq!(amp.get_max_gain_db(), 30);
+        assert_eq!(amp.max_gain_db(17), Err(Error::InvalidValue));
+        assert_eq!(amp.max_gain_db(31), Err(Error::InvalidValue));
+
+        // Compression ratio bits are kept
+        assert_eq!(amp.release().writes, [(7, 0x62), (7, 0xC2)]);
+    }
+
+    #[test]
+    fn mute_unmute() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.speaker_enable(false, true).unwrap();
+        amp.gain(18).unwrap();
+
+        amp.mute().unwrap();
+        amp.mute().unwrap();
+        assert!(amp.is_muted());
+        assert_eq!(amp.speakers_enabled(), (false, false));
+        amp.unmute().unwrap();
+        assert!(!amp.is_muted());
+        assert_eq!(amp.speakers_enabled(), (false, true));
+        assert_eq!(amp.fixed_gain(), 18);
+
+        amp.mute().unwrap();
+        amp.speaker_enable(true, false).unwrap();
+        amp.unmute().unwrap();
+        assert_eq!(amp.speakers_enabled(), (true, false));
+        assert_eq!(amp.release().regs[1], 0x43);
+    }
+
+    #[test]
+    fn software_shutdown() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        amp.disable_device().unwrap();
+        assert!(amp.status().shutdown);
+        amp.enable_device().unwrap();
+        assert!(!amp.status().shutdown);
+        amp.set_software_shutdown(true).unwrap();
+        amp.apply_change(ConfigChange::Wake).unwrap();
+        assert!(!amp.status().shutdown);
+
+        assert_eq!(
+            amp.release().writes,
+            [(1, 0xE3), (1, 0xC3), (1, 0xE3), (1, 0xC3)]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        assert_eq!(amp.gain(32), Err(Error::InvalidValue));
+        assert_eq!(amp.set_attack_time(0x40), Err(Error::InvalidValue));
+        let patch = ConfigPatch {
+            attack_time: Some(1),
+            output_limiter_level: Some(0x20),
+            ..ConfigPatch::default()
+        };
+        assert_eq!(amp.apply_patch(&patch), Err(Error::InvalidValue));
+        assert!(amp.bus_mut().writes.is_empty());
+
+        amp.bus_mut().fail = true;
+        assert_eq!(amp.gain(12), Err(Error::I2c(mock::MockError)));
+        // The unchecked setters return the bus error as is
+        assert_eq!(amp.gain_unchecked(12), Err(mock::MockError));
+    }
+
+    #[test]
+    fn volatile_register() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.bus_mut().regs[1] |= 1 << 2;
+
+        assert!(!amp.status().faults.thermal);
+        assert_eq!(amp.device_reg(Register::AttackTime).unwrap(), 0x05);
+        assert_eq!(amp.bus_mut().reads, 0);
+
+        assert_eq!(amp.device_reg(Register::Control).unwrap() & 1 << 2, 1 << 2);
+        assert!(amp.status().faults.thermal);
+
+        amp.bus_mut().regs[1] &= !(1 << 2);
+        assert!(!amp.read_status().unwrap().faults.thermal);
+        assert_eq!(amp.bus_mut().reads, 2);
+    }
+
+    #[test]
+    fn quiet_reconfig() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        amp.set_quiet_reconfig(QuietReconfig::Mute);
+        amp.set_agc_preset(AgcPreset::Rock).unwrap();
+        amp.set_quiet_reconfig(QuietReconfig::Shutdown);
+        amp.apply_config(&Config::DEFAULT).unwrap();
+
+        let writes = amp.release().writes;
+        assert_eq!(writes[0], (1, 0x03));
+        assert_eq!(writes[7], (1, 0xC3));
+        assert_eq!(writes[8], (1, 0xE3));
+        assert_eq!(writes[15], (1, 0xC3));
+        assert_eq!(writes.len(), 16);
+    }
+
+    #[test]
+    fn init() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        let mut delay = TestDelay(0);
+        amp.init(&mut delay).unwrap();
+        assert_eq!(delay.0, 15);
+        assert_eq!(amp.config(), Config::BUILD_DEFAULT);
+
+        let writes = amp.release().writes;
+        assert_eq!(writes.first(), Some(&(1, 0x03)));
+        assert_eq!(writes.last(), Some(&(1, 0xC3)));
+
+        let mut i2c = mock::MockI2c::new();
+        i2c.fail = true;
+        let mut amp = Tpa2016d2::new(i2c);
+        assert_eq!(amp.init(&mut delay), Err(Error::DeviceNotFound));
+    }
+
+    #[test]
+    fn fade() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        let mut delay = TestDelay(0);
+
+        amp.fade_to(9, 10, &mut delay).unwrap();
+        assert_eq!(delay.0, 20);
+        assert_eq!(amp.fixed_gain(), 9);
+
+        amp.fade_out_mute(10, &mut delay).unwrap();
+        assert!(amp.is_muted());
+        assert_eq!(amp.fixed_gain(), 9);
+        assert_eq!(delay.0, 100);
+
+        amp.unmute_fade_in(10, &mut delay).unwrap();
+        assert!(!amp.is_muted());
+        assert_eq!(delay.0, 190);
+        let writes = amp.release().writes;
+        assert_eq!(writes[..3], [(5, 7), (5, 8), (5, 9)]);
+        assert_eq!(writes[12..15], [(1, 0x03), (5, 9), (5, 0)]);
+        assert_eq!(writes[15..17], [(1, 0xC3), (5, 1)]);
+        assert_eq!(writes.last(), Some(&(5, 9)));
+    }
+
+    #[test]
+    fn crossfade() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        let mut delay = TestDelay(0);
+        let target = AgcConfig {
+            attack_time: 1,
+            release_time: 2,
+            hold_time: 3,
+            fixed_gain: 10,
+            max_gain: 6,
+            output_limiter_level: 28,
+            output_limiter: true,
+            compression_ratio: CompressionRatio::Ratio4,
+        };
+
+        amp.crossfade_to(&target, 400, &mut delay).unwrap();
+
+        assert_eq!(delay.0, 300);
+        assert_eq!(
+            amp.release().writes,
+            [
+                (5, 7),
+                (2, 1),
+                (3, 2),
+                (4, 3),
+                (7, 0x62),
+                (5, 8),
+                (6, 0x3B),
+                (5, 9),
+                (5, 10),
+                (6, 0x3C),
+            ]
+        );
+    }
+
+    #[test]
+    fn agc_bypass() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.disable_agc().unwrap();
+        assert!(!amp.output_limiter_enabled());
+        amp.gain_db(-10).unwrap();
+
+        amp.enable_agc(CompressionRatio::Ratio2).unwrap();
+        assert_eq!(amp.fixed_gain_db(), 0);
+        assert_eq!(
+            amp.release().writes,
+            [
+                (7, 0xC0),
+                (6, 0xBA),
+                (5, 0x36),
+                (6, 0x3A),
+                (5, 0),
+                (7, 0xC1)
+            ]
+        );
+    }
+
+    #[test]
+    fn crossfade_preset() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        let mut delay = TestDelay(0);
+        amp.set_agc_preset(AgcPreset::Pop).unwrap();
+        amp.crossfade_to_preset(AgcPreset::Jazz, 100, &mut delay)
+            .unwrap();
+
+        assert_eq!(amp.agc_config(), AgcPreset::Jazz.config());
+        assert_eq!(amp.status().preset, Some(AgcPreset::Jazz));
+        assert_eq!(amp.get_agc().unwrap(), AgcPreset::Jazz.config());
+    }
+
+    #[test]
+    fn apply_patch_writes_changed_registers() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+
+        amp.apply_patch(&ConfigPatch {
+            noise_gate: Some(false),
+            output_limiter_level: Some(20),
+            noise_gate_threshold: Some(NoiseGateThreshold::Ngt20mV),
+            ..ConfigPatch::default()
+        })
+        .unwrap();
+
+        assert_eq!(amp.release().writes, [(1, 0xC2), (6, 0x74)]);
+    }
+
+    #[test]
+    fn apply_patch_validates_merged_config() {
+        let mut amp = Tpa2016d2::new(mock::MockI2c::new());
+        amp.compression_ratio(CompressionRatio::Ratio1).unwrap();
+        amp.gain_db(-6).unwrap();
+
+        // Compression over a negative fixed gain
+        let compress = ConfigPatch {
+            compression_ratio: Some(CompressionRatio::Ratio4),
+            ..ConfigPatch::default()
+        };
+        assert_eq!(amp.apply_patch(&compress), Err(Error::InvalidValue));
+
+        // Max gain below the fixed gain with compression
+        amp.gain_db(24).unwrap();
+        amp.compression_ratio(CompressionRatio::Ratio4).unwrap();
+        let max_gain = ConfigPatch {
+            max_gain: Some(0),
+            ..ConfigPatch::default()
+        };
+        assert_eq!(amp.apply_patch(&max_gain), Err(Error::InvalidValue));
+
+        assert_eq!(amp.release().writes.len(), 4);
+    }
+}
@@ -0,0 +1,32 @@
+//! Legal ranges from the datasheet
+//!
+//! For validating settings in application code against the same bounds the
+//! driver uses, see also [`AgcConfig::validate`](crate::AgcConfig::validate).
+
+use core::ops::RangeInclusive;
+
+/// Lowest fixed gain, in dB
+pub const MIN_FIXED_GAIN_DB: i8 = -28;
+/// Highest fixed gain, in dB
+pub const MAX_FIXED_GAIN_DB: i8 = 30;
+/// Lowest fixed gain with compression enabled, in dB
+pub const MIN_FIXED_GAIN_DB_COMPRESSED: i8 = 0;
+
+/// Range of the AGC max gain, in dB
+pub const MAX_GAIN_DB_RANGE: RangeInclusive<i8> = 18..=30;
+/// Highest max gain register value, 18 dB + value
+pub const MAX_GAIN_CODE: u8 = 12;
+
+/// Range of the output limiter level, in 0.1 dBV
+pub const LIMITER_LEVEL_RANGE_DBV_X10: RangeInclusive<i16> = -65..=90;
+/// Highest output limiter level register value, -6.5 dBV + value * 0.5 dB
+pub const LIMITER_LEVEL_CODE_MAX: u8 = 0b1_1111;
+
+/// Highest attack, release and hold time register value
+pub const TIME_CODE_MAX: u8 = 0x3F;
+/// Longest attack time per 6 dB, in µs
+pub const MAX_ATTACK_TIME_US: u32 = 6_722;
+/// Longest release time per 6 dB, in ms
+pub const MAX_RELEASE_TIME_MS: u32 = 10_357;
+/// Longest hold time, in ms
+pub const MAX_HOLD_TIME_MS: u32 = 863;
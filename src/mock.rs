@@ -0,0 +1,147 @@
+//! Test doubles shared by the unit tests: a fake i2c bus backed by a
+//! register file, a delay, a clock and an executor for the async tests
+
+extern crate std;
+
+#[cfg(any(feature = "async", feature = "embassy"))]
+use core::future::Future;
+#[cfg(any(feature = "async", feature = "embassy"))]
+use core::pin::pin;
+#[cfg(any(feature = "async", feature = "embassy"))]
+use core::ptr;
+#[cfg(any(feature = "async", feature = "embassy"))]
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+#[cfg(feature = "hal")]
+use std::vec::Vec;
+
+#[cfg(feature = "hal")]
+use embedded_hal::blocking::{delay::DelayMs, i2c};
+
+use crate::Clock;
+#[cfg(feature = "hal")]
+use crate::TPA2016_I2C_ADDR;
+
+#[cfg(feature = "hal")]
+#[derive(Debug, PartialEq)]
+pub struct MockError;
+
+#[cfg(feature = "hal")]
+pub struct MockI2c {
+    /// Address the device responds to
+    pub addr: u8,
+    /// Register file, index 0 is unused
+    pub regs: [u8; 8],
+    /// All register writes as (register, value)
+    pub writes: Vec<(u8, u8)>,
+    /// Number of write transactions
+    pub transactions: usize,
+    /// Number of register reads
+    pub reads: usize,
+    /// Fail all transactions
+    pub fail: bool,
+    /// Register that keeps a value regardless of writes, as (register, value)
+    pub stuck: Option<(u8, u8)>,
+}
+
+#[cfg(feature = "hal")]
+impl MockI2c {
+    pub fn new() -> Self {
+        Self {
+            addr: TPA2016_I2C_ADDR,
+            regs: [0x00, 0xC3, 0x05, 0x0B, 0x00, 0x06, 0x3A, 0xC2],
+            writes: Vec::new(),
+            transactions: 0,
+            reads: 0,
+            fail: false,
+            stuck: None,
+        }
+    }
+}
+
+#[cfg(feature = "hal")]
+impl i2c::Write for MockI2c {
+    type Error = MockError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), MockError> {
+        assert_eq!(addr, self.addr);
+        if self.fail {
+            return Err(MockError);
+        }
+        self.transactions += 1;
+        let (&first, values) = bytes.split_first().unwrap();
+        for (i, &val) in values.iter().enumerate() {
+            let reg = first + i as u8;
+            self.regs[reg as usize] = match self.stuck {
+                Some((stuck, stuck_val)) if stuck == reg => stuck_val,
+                _ => val,
+            };
+            self.writes.push((reg, val));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "hal")]
+impl i2c::WriteRead for MockI2c {
+    type Error = MockError;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), MockError> {
+        assert_eq!(addr, self.addr);
+        if self.fail {
+            return Err(MockError);
+        }
+        for (i, b) in buffer.iter_mut().enumerate() {
+            *b = self.regs[bytes[0] as usize + i];
+            self.reads += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Delay that adds up the milliseconds waited
+#[cfg(feature = "hal")]
+pub struct TestDelay(pub u32);
+
+#[cfg(feature = "hal")]
+impl DelayMs<u32> for TestDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.0 += ms;
+    }
+}
+
+/// Clock that only moves when the test sets it
+pub struct TestClock(pub u32);
+
+impl Clock for TestClock {
+    fn now_ms(&mut self) -> u32 {
+        self.0
+    }
+}
+
+#[cfg(any(feature = "async", feature = "embassy"))]
+static NOOP_WAKER: RawWakerVTable = RawWakerVTable::new(noop_clone, noop, noop, noop);
+
+#[cfg(any(feature = "async", feature = "embassy"))]
+fn noop_clone(_: *const ()) -> RawWaker {
+    RawWaker::new(ptr::null(), &NOOP_WAKER)
+}
+
+#[cfg(any(feature = "async", feature = "embassy"))]
+fn noop(_: *const ()) {}
+
+/// Poll `future` until it completes
+///
+/// The waker does nothing, `Waker::noop` is newer than the minimum supported
+/// Rust version.
+#[cfg(any(feature = "async", feature = "embassy"))]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    // SAFETY: the vtable functions ignore the data pointer
+    let waker = unsafe { Waker::from_raw(noop_clone(ptr::null())) };
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Several amplifiers controlled as one
+//!
+//! [`MultiAmp`] owns `N` drivers, e.g. two devices for four speakers on two
+//! buses, or on one bus behind an address translator, see
+//! [`Tpa2016d2::new_with_address`]. The operations are carried out on every
+//! device in order:
+//!
+//! ```
+//! use tpa2016d2::multi::MultiAmp;
+//! use tpa2016d2::Tpa2016d2;
+//!
+//! # fn run<I2C, E>(front: I2C, rear: I2C) -> Result<(), tpa2016d2::multi::MultiError<E>>
+//! # where
+//! #     I2C: tpa2016d2::RegisterInterface<Error = E>,
+//! # {
+//! let mut amps = MultiAmp::new([Tpa2016d2::new(front), Tpa2016d2::new(rear)]);
+//! amps.gain_db(12)?;
+//! let [front_faults, rear_faults] = amps.get_faults()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! All drivers have the same bus type. Different bus peripherals can be
+//! combined with a bus type of their own implementing
+//! [`RegisterInterface`](crate::RegisterInterface).
+
+use crate::{AgcPreset, Config, Error, Faults, RegisterInterface, Tpa2016d2};
+
+/// Error from one of the amplifiers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MultiError<E> {
+    /// Index of the amplifier that failed
+    pub index: usize,
+    pub error: Error<E>,
+}
+
+/// `N` amplifiers with unified operations
+pub struct MultiAmp<I2C, const N: usize> {
+    amps: [Tpa2016d2<I2C>; N],
+}
+
+impl<I2C, const N: usize> MultiAmp<I2C, N> {
+    pub fn new(amps: [Tpa2016d2<I2C>; N]) -> Self {
+        MultiAmp { amps }
+    }
+
+    /// The amplifiers, for the getters and per device settings
+    pub fn amps(&mut self) -> &mut [Tpa2016d2<I2C>; N] {
+        &mut self.amps
+    }
+
+    /// Release the drivers
+    pub fn into_inner(self) -> [Tpa2016d2<I2C>; N] {
+        self.amps
+    }
+}
+
+impl<I2C, E, const N: usize> MultiAmp<I2C, N>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    /// Run `f` on every amplifier
+    ///
+    /// A failure on one amplifier does not stop the others, the first error
+    /// is returned.
+    pub fn for_each<F>(&mut self, mut f: F) -> Result<(), MultiError<E>>
+    where
+        F: FnMut(&mut Tpa2016d2<I2C>) -> Result<(), Error<E>>,
+    {
+        let mut result = Ok(());
+        for (index, amp) in self.amps.iter_mut().enumerate() {
+            if let Err(error) = f(amp) {
+                if result.is_ok() {
+                    result = Err(MultiError { index, error });
+                }
+            }
+        }
+        result
+    }
+
+    /// See [`Tpa2016d2::gain`]
+    pub fn gain(&mut self, gain: u8) -> Result<(), MultiError<E>> {
+        self.for_each(|amp| amp.gain(gain))
+    }
+
+    /// See [`Tpa2016d2::gain_db`]
+    pub fn gain_db(&mut self, db: i8) -> Result<(), MultiError<E>> {
+        self.for_each(|amp| amp.gain_db(db))
+    }
+
+    /// See [`Tpa2016d2::speaker_enable`]
+    pub fn speaker_enable(&mut self, le: bool, re: bool) -> Result<(), MultiError<E>> {
+        self.for_each(|amp| amp.speaker_enable(le, re))
+    }
+
+    /// See [`Tpa2016d2::mute`]
+    pub fn mute(&mut self) -> Result<(), MultiError<E>> {
+        self.for_each(|amp| amp.mute())
+    }
+
+    /// See [`Tpa2016d2::unmute`]
+    pub fn unmute(&mut self) -> Result<(), MultiError<E>> {
+        self.for_each(|amp| amp.unmute())
+    }
+
+    /// Wake all devices from software shutdown
+    pub fn enable_device(&mut self) -> Result<(), MultiError<E>> {
+        self.for_each(|amp| amp.enable_device())
+    }
+
+    /// Put all devices in software shutdown
+    pub fn disable_device(&mut self) -> Result<(), MultiError<E>> {
+        self.for_each(|amp| amp.disable_device())
+    }
+
+    /// See [`Tpa2016d2::set_agc_preset`]
+    pub fn set_agc_preset(&mut self, preset: AgcPreset) -> Result<(), MultiError<E>> {
+        self.for_each(|amp| amp.set_agc_preset(preset))
+    }
+
+    /// See [`Tpa2016d2::apply_config`]
+    pub fn apply_config(&mut self, config: &Config) -> Result<(), MultiError<E>> {
+        self.for_each(|amp| amp.apply_config(config))
+    }
+
+    /// Read the fault flags of every amplifier, in order
+    pub fn get_faults(&mut self) -> Result<[Faults; N], MultiError<E>> {
+        let mut faults = [Faults::default(); N];
+        for (index, amp) in self.amps.iter_mut().enumerate() {
+            faults[index] = amp
+                .get_faults()
+                .map_err(|error| MultiError { index, error })?;
+        }
+        Ok(faults)
+    }
+}
+
+#[cfg(all(test, feature = "hal"))]
+mod tests {
+    use super::*;
+    use crate::mock::{MockError, MockI2c};
+
+    #[test]
+    fn unified() {
+        let mut rear = MockI2c::new();
+        rear.regs[1] |= 1 << 2;
+        let mut amps = MultiAmp::new([Tpa2016d2::new(MockI2c::new()), Tpa2016d2::new(rear)]);
+
+        amps.gain(12).unwrap();
+        let [front, rear] = amps.get_faults().unwrap();
+        assert!(!front.thermal);
+        assert!(rear.thermal);
+
+        amps.amps()[0].bus_mut().fail = true;
+        assert_eq!(
+            amps.disable_device(),
+            Err(MultiError {
+                index: 0,
+                error: Error::I2c(MockError)
+            })
+        );
+        assert!(amps.amps()[1].is_shutdown());
+
+        let [_, rear] = amps.into_inner();
+        assert_eq!(rear.release().writes, [(5, 12), (1, 0xE7)]);
+    }
+}
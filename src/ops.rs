@@ -0,0 +1,482 @@
+//! Register operations shared by the blocking and the async driver
+//!
+//! `register_ops!` expands to the setters and readers built on the
+//! [`Core`](crate::sansio::Core) commands, including the execution of the
+//! commands. Without arguments it generates blocking functions, with
+//! `async await` async ones, so the register logic is written once for both.
+//! The impl block invoking it provides `bus_read_registers` and
+//! `bus_write_registers`, the only bus accesses.
+
+macro_rules! register_ops {
+    ($($async:ident $await:ident)?) => {
+        /// Write the registers changed in deferred mode, in register order
+        pub $($async)? fn flush(&mut self) -> Result<(), Error<E>> {
+            let commands = self.core.write_registers(self.dirty);
+            let deferred = core::mem::replace(&mut self.deferred, false);
+            let result = self.execute(commands)$(.$await)?;
+            self.deferred = deferred;
+            result?;
+            self.dirty = 0;
+            Ok(())
+        }
+
+        /// Check that the device is present
+        ///
+        /// Reads register 1 and checks its reserved bit, which always reads as 1.
+        /// A failed read or a cleared bit is reported as [`Error::DeviceNotFound`].
+        pub $($async)? fn probe(&mut self) -> Result<(), Error<E>> {
+            let value = self
+                .read_reg(Register::Control)$(.$await)?
+                .map_err(|_| Error::DeviceNotFound)?;
+            if value & 1 << 1 == 0 {
+                return Err(Error::DeviceNotFound);
+            }
+            self.receive(Register::Control, value);
+            Ok(())
+        }
+
+        /// Read all registers and update our view of the registers
+        pub $($async)? fn sync(&mut self) -> Result<(), Error<E>> {
+            let commands = self.core.sync();
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Get content of register `reg`
+        ///
+        /// Volatile registers, register 1 with the fault flags, are read from the
+        /// device, the others are taken from the cache.
+        pub $($async)? fn device_reg(&mut self, reg: Register) -> Result<u8, Error<E>> {
+            if self.core.is_volatile(reg) {
+                let value = self.read_reg(reg)$(.$await)?.map_err(Error::I2c)?;
+                self.receive(reg, value);
+            }
+            Ok(self.core.register(reg))
+        }
+
+        /// Read the AGC settings, registers 2 to 7, from the device
+        ///
+        /// The driver's view of the registers is left as is, compare the result
+        /// with [`Tpa2016d2::agc_config`] to find out if the device lost its
+        /// settings, e.g. after a brown-out.
+        pub $($async)? fn get_agc(&mut self) -> Result<AgcConfig, Error<E>> {
+            let mut regs = [0; 7];
+            regs[0] = self.core.register(Register::Control);
+            self.read_regs(Register::AttackTime, &mut regs[1..])$(.$await)?
+                .map_err(Error::I2c)?;
+            Ok(Config::from_registers(&regs).agc)
+        }
+
+        /// Like [`Tpa2016d2::status`], with the volatile fault flags read from the device
+        pub $($async)? fn read_status(&mut self) -> Result<Status, Error<E>> {
+            let commands = self.core.read_volatile();
+            self.execute(commands)$(.$await)??;
+            Ok(self.core.status())
+        }
+
+        /// Enable or disable speakers
+        pub $($async)? fn speaker_enable(&mut self, le: bool, re: bool) -> Result<(), Error<E>> {
+            let commands = self.core.speaker_enable(le, re);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Turn off both speakers, remembering which were enabled
+        ///
+        /// The other settings, including the fixed gain, are not changed, so
+        /// [`Tpa2016d2::unmute`] restores the previous output exactly.
+        pub $($async)? fn mute(&mut self) -> Result<(), Error<E>> {
+            let commands = self.core.mute();
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Re-enable the speakers enabled before [`Tpa2016d2::mute`]
+        ///
+        /// Does nothing if not muted. Enabling or disabling speakers while muted
+        /// ends the mute, and unmute then does nothing.
+        pub $($async)? fn unmute(&mut self) -> Result<(), Error<E>> {
+            let commands = self.core.unmute();
+            self.execute(commands)$(.$await)?
+        }
+
+        pub $($async)? fn get_faults(&mut self) -> Result<Faults, Error<E>> {
+            let commands = self.core.read_faults();
+            self.execute(commands)$(.$await)??;
+            Ok(self.core.faults())
+        }
+
+        /// Shutdown the device
+        /// Control, Bias and Oscillators are disabled
+        pub $($async)? fn disable_device(&mut self) -> Result<(), Error<E>> {
+            let commands = self.core.disable_device();
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Wake the device from software shutdown
+        ///
+        /// The device needs [`timings::SWS_WAKE_UP_MS`](crate::timings::SWS_WAKE_UP_MS) before it outputs audio.
+        pub $($async)? fn enable_device(&mut self) -> Result<(), Error<E>> {
+            let commands = self.core.enable_device();
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Enter or leave software shutdown
+        pub $($async)? fn set_software_shutdown(&mut self, shutdown: bool) -> Result<(), Error<E>> {
+            let commands = self.core.set_software_shutdown(shutdown);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Enable or disable the noise gate
+        pub $($async)? fn set_noise_gate(&mut self, enable: bool) -> Result<(), Error<E>> {
+            let commands = self.core.set_noise_gate(enable);
+            self.execute(commands)$(.$await)?
+        }
+
+        pub $($async)? fn set_attack_time(&mut self, val: u8) -> Result<(), Error<E>> {
+            check(val <= limits::TIME_CODE_MAX)?;
+            let commands = self.core.set_attack_time(val);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Set release time / per 6 dB
+        pub $($async)? fn set_release_time(&mut self, val: u8) -> Result<(), Error<E>> {
+            check(val <= limits::TIME_CODE_MAX)?;
+            let commands = self.core.set_release_time(val);
+            self.execute(commands)$(.$await)?
+        }
+
+        pub $($async)? fn set_hold_time(&mut self, val: u8) -> Result<(), Error<E>> {
+            check(val <= limits::TIME_CODE_MAX)?;
+            let commands = self.core.set_hold_time(val);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Set the attack time in µs per 6 dB, 0 to 6722
+        ///
+        /// Rounded to the nearest 106.7 µs step. Times out of range are rejected
+        /// with [`Error::InvalidValue`].
+        ///
+        /// Unlike the release and hold times this takes microseconds: the
+        /// longest attack time is 6.72 ms, so whole milliseconds would only
+        /// reach seven of the 64 steps.
+        pub $($async)? fn set_attack_time_us(&mut self, us: u32) -> Result<(), Error<E>> {
+            check(us <= limits::MAX_ATTACK_TIME_US)?;
+            self.set_attack_time(attack_time_to_u6(us * 10 + 1067 / 2))$(.$await)?
+        }
+
+        /// Set the release time in ms per 6 dB, 0 to 10357
+        ///
+        /// Rounded to the nearest 164.4 ms step. Times out of range are rejected
+        /// with [`Error::InvalidValue`].
+        pub $($async)? fn set_release_time_ms(&mut self, ms: u32) -> Result<(), Error<E>> {
+            check(ms <= limits::MAX_RELEASE_TIME_MS)?;
+            self.set_release_time(release_time_to_u6(ms * 10 + 1644 / 2))$(.$await)?
+        }
+
+        /// Set the hold time in ms, 0 to 863, 0 disables hold
+        ///
+        /// Rounded to the nearest 13.7 ms step. Times out of range are rejected
+        /// with [`Error::InvalidValue`].
+        pub $($async)? fn set_hold_time_ms(&mut self, ms: u32) -> Result<(), Error<E>> {
+            check(ms <= limits::MAX_HOLD_TIME_MS)?;
+            self.set_hold_time(hold_time_to_u6(ms * 10 + 137 / 2))$(.$await)?
+        }
+
+        /// Set the gain
+        ///
+        /// The gain trim is added, see [`Tpa2016d2::set_gain_trim`]. Register
+        /// values outside -28 to 30 dB are rejected with [`Error::InvalidValue`].
+        pub $($async)? fn gain(&mut self, gain: u8) -> Result<(), Error<E>> {
+            check(agc::fixed_gain_db(gain).is_some())?;
+            let commands = self.core.gain(gain);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Set the gain in dB, -28 to 30
+        ///
+        /// The gain trim is added, see [`Tpa2016d2::set_gain_trim`]. Gains outside
+        /// the range are rejected with [`Error::InvalidValue`].
+        pub $($async)? fn gain_db(&mut self, db: i8) -> Result<(), Error<E>> {
+            check((limits::MIN_FIXED_GAIN_DB..=limits::MAX_FIXED_GAIN_DB).contains(&db))?;
+            self.gain(agc::fixed_gain_code(db))$(.$await)?
+        }
+
+        pub $($async)? fn noise_gate_threshold(&mut self, val: NoiseGateThreshold) -> Result<(), Error<E>> {
+            let commands = self.core.noise_gate_threshold(val);
+            self.execute(commands)$(.$await)?
+        }
+
+        pub $($async)? fn output_limiter_level(&mut self, val: u8) -> Result<(), Error<E>> {
+            check(val <= limits::LIMITER_LEVEL_CODE_MAX)?;
+            let commands = self.core.output_limiter_level(val);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Set the output limiter level in 0.1 dBV, -65 to 90 in steps of 5
+        ///
+        /// Levels outside the range or between the 0.5 dB steps are rejected with
+        /// [`Error::InvalidValue`].
+        pub $($async)? fn set_output_limiter_dbv_x10(&mut self, dbv_x10: i16) -> Result<(), Error<E>> {
+            match agc::limiter_level_code(dbv_x10) {
+                Some(val) => self.output_limiter_level(val)$(.$await)?,
+                None => Err(Error::InvalidValue),
+            }
+        }
+
+        /// Set the compression ratio
+        ///
+        /// Ratios other than 1:1 are rejected with [`Error::InvalidValue`] while
+        /// the output limiter is disabled, see [`Tpa2016d2::output_limiter`].
+        pub $($async)? fn compression_ratio(&mut self, ratio: CompressionRatio) -> Result<(), Error<E>> {
+            check(ratio == CompressionRatio::Ratio1 || !self.core.regmap.output_limiter_disable())?;
+            let commands = self.core.compression_ratio(ratio);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Bypass the AGC, leaving only the fixed gain
+        ///
+        /// The compression ratio is set to 1:1 before the output limiter is
+        /// disabled, the fixed gain can then be set from -28 to 30 dB.
+        pub $($async)? fn disable_agc(&mut self) -> Result<(), Error<E>> {
+            self.compression_ratio(CompressionRatio::Ratio1)$(.$await)??;
+            self.output_limiter(false)$(.$await)?
+        }
+
+        /// Enable the AGC with compression `ratio`
+        ///
+        /// The output limiter is enabled and the fixed gain is brought into the
+        /// range allowed with compression, 0 dB to the max gain, before the ratio
+        /// is set.
+        pub $($async)? fn enable_agc(&mut self, ratio: CompressionRatio) -> Result<(), Error<E>> {
+            self.output_limiter(true)$(.$await)??;
+
+            let gain = self.core.regmap.fixed_gain();
+            let max_db = 18 + self.max_gain() as i8;
+            let clamped = agc::fixed_gain_code(agc::fixed_gain_to_db(gain).clamp(0, max_db));
+            if clamped != gain {
+                self.write_gain_raw(clamped)$(.$await)??;
+            }
+
+            self.compression_ratio(ratio)$(.$await)?
+        }
+
+        /// Enable or disable the output limiter
+        ///
+        /// The limiter can only be disabled with a compression ratio of 1:1,
+        /// otherwise [`Error::InvalidValue`] is returned.
+        pub $($async)? fn output_limiter(&mut self, enable: bool) -> Result<(), Error<E>> {
+            check(enable || self.agc_config().compression_ratio == CompressionRatio::Ratio1)?;
+            let commands = self.core.output_limiter(enable);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Set the max gain of the AGC, 18 dB + `val`
+        ///
+        /// Values above 12, 30 dB, are rejected with [`Error::InvalidValue`].
+        pub $($async)? fn set_max_gain(&mut self, val: u8) -> Result<(), Error<E>> {
+            check(val <= limits::MAX_GAIN_CODE)?;
+            let commands = self.core.set_max_gain(val);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Set the max gain of the AGC in dB, 18 to 30
+        ///
+        /// Gains outside the range are rejected with [`Error::InvalidValue`].
+        pub $($async)? fn max_gain_db(&mut self, db: u8) -> Result<(), Error<E>> {
+            let range = limits::MAX_GAIN_DB_RANGE;
+            check(db >= *range.start() as u8 && db <= *range.end() as u8)?;
+            self.set_max_gain(db - *range.start() as u8)$(.$await)?
+        }
+
+        /// Write an AGC preset, registers 2 to 7
+        ///
+        /// The presets compress, the output limiter is enabled.
+        pub $($async)? fn set_agc_preset(&mut self, preset: AgcPreset) -> Result<(), Error<E>> {
+            let commands = self.core.set_agc_preset(preset);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Write a complete AGC configuration, registers 2 to 7
+        ///
+        /// Configurations that fail [`AgcConfig::validate`] are rejected with
+        /// [`Error::InvalidValue`].
+        pub $($async)? fn set_agc_config(&mut self, config: &AgcConfig) -> Result<(), Error<E>> {
+            check(config.validate().is_ok())?;
+            let commands = self.core.set_agc_config(config);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Write a user defined AGC preset, registers 2 to 7
+        ///
+        /// Presets that fail [`AgcConfig::validate`] are rejected with
+        /// [`Error::InvalidValue`].
+        pub $($async)? fn set_agc_custom(&mut self, preset: &agc::CustomPreset) -> Result<(), Error<E>> {
+            self.set_agc_config(&preset.config)$(.$await)?
+        }
+
+        /// Write a complete configuration, registers 1 to 7
+        ///
+        /// The software shutdown bit is left as is. The gain trim is added to the
+        /// fixed gain. Configurations that fail [`Config::validate`] are rejected
+        /// with [`Error::InvalidValue`].
+        pub $($async)? fn apply_config(&mut self, config: &Config) -> Result<(), Error<E>> {
+            check(config.validate().is_ok())?;
+            let commands = self.core.apply_config(config);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Change only the settings present in `patch`
+        ///
+        /// Only the registers holding the changed settings are written. The gain
+        /// trim is added to the fixed gain. Values outside their register field,
+        /// or a patch leaving a configuration that fails [`Config::validate`], are
+        /// rejected with [`Error::InvalidValue`], without writing any.
+        pub $($async)? fn apply_patch(&mut self, patch: &ConfigPatch) -> Result<(), Error<E>> {
+            check(patch.in_range())?;
+            let mut merged = self.config();
+            merged.apply_patch(patch);
+            check(merged.validate().is_ok())?;
+            let commands = self.core.apply_patch(patch);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Write the default configuration of this build, see [`Config::BUILD_DEFAULT`]
+        pub $($async)? fn apply_default_config(&mut self) -> Result<(), Error<E>> {
+            self.apply_config(&Config::BUILD_DEFAULT)$(.$await)?
+        }
+
+        /// Write the fixed gain without applying the trim
+        pub(crate) $($async)? fn write_gain_raw(&mut self, gain: u8) -> Result<(), Error<E>> {
+            let commands = self.core.gain_raw(gain);
+            self.execute(commands)$(.$await)?
+        }
+
+        $($async)? fn write_regmap_reg(&mut self, reg: Register) -> Result<(), Error<E>> {
+            let commands = self.core.write(reg);
+            self.execute(commands)$(.$await)?
+        }
+
+        /// Update the driver's view with a value read from the device
+        ///
+        /// A register with a deferred write keeps its pending value, only the
+        /// fault flags are taken from the device.
+        fn receive(&mut self, reg: Register, value: u8) {
+            let value = if self.dirty & reg.bit() != 0 {
+                let volatile = if reg == Register::Control {
+                    regmap::FAULT_BITS
+                } else {
+                    0
+                };
+                self.core.register(reg) & !volatile | value & volatile
+            } else {
+                value
+            };
+            self.core.receive(reg, value);
+        }
+
+        /// Carry out the commands from the core
+        ///
+        /// Writes to consecutive registers are sent as one transaction, using the
+        /// register address auto-increment of the device.
+        $($async)? fn execute(&mut self, commands: Commands) -> Result<(), Error<E>> {
+            let mut rest = commands.as_slice();
+            while let Some((&command, tail)) = rest.split_first() {
+                rest = tail;
+                match command {
+                    Command::Write { reg, .. } if self.deferred => self.dirty |= reg.bit(),
+                    Command::Write { reg, value } => {
+                        let mut values = [value; 7];
+                        let mut len = 1;
+                        while let Some((&Command::Write { reg: next, value }, tail)) =
+                            rest.split_first()
+                        {
+                            if len == values.len() || next.addr() != reg.addr() + len as u8 {
+                                break;
+                            }
+                            values[len] = value;
+                            len += 1;
+                            rest = tail;
+                        }
+                        self.write_verified(reg, &values[..len])$(.$await)??;
+                    }
+                    Command::Read { reg } => {
+                        let mut len = 1;
+                        while let Some((&Command::Read { reg: next }, tail)) = rest.split_first() {
+                            if next.addr() != reg.addr() + len as u8 {
+                                break;
+                            }
+                            len += 1;
+                            rest = tail;
+                        }
+                        let mut values = [0; 7];
+                        self.read_regs(reg, &mut values[..len])$(.$await)?
+                            .map_err(Error::I2c)?;
+                        let first = reg.addr() as usize - 1;
+                        for (&reg, &value) in Register::ALL[first..].iter().zip(&values[..len]) {
+                            self.receive(reg, value);
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Write registers from `reg` on, reading them back in write verify mode
+        $($async)? fn write_verified(&mut self, reg: Register, values: &[u8]) -> Result<(), Error<E>> {
+            self.write_reg(reg, values)$(.$await)?.map_err(Error::I2c)?;
+            if self.verify {
+                let mut read = [0; 7];
+                let read = &mut read[..values.len()];
+                self.read_regs(reg, read)$(.$await)?.map_err(Error::I2c)?;
+                let first = reg.addr() as usize - 1;
+                for ((&reg, &value), &read) in Register::ALL[first..].iter().zip(values).zip(&*read) {
+                    let mask = regmap::verify_mask(reg);
+                    if read & mask != value & mask {
+                        return Err(Error::VerifyFailed);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        $($async)? fn read_reg(&mut self, reg: Register) -> Result<u8, E> {
+            let mut value = [0];
+            self.read_regs(reg, &mut value)$(.$await)??;
+            Ok(value[0])
+        }
+
+        /// Read the registers from `reg` on into `values`, in one transaction
+        $($async)? fn read_regs(&mut self, reg: Register, values: &mut [u8]) -> Result<(), E> {
+            #[cfg(feature = "stats")]
+            let start = self.stats_clock.map(|now_us| now_us());
+
+            self.bus_read_registers(reg, values)$(.$await)??;
+
+            #[cfg(feature = "stats")]
+            if let (Some(start), Some(now_us)) = (start, self.stats_clock) {
+                self.stats.reads.record(now_us().wrapping_sub(start));
+            }
+
+            #[cfg(feature = "trace")]
+            self.tracer.read(reg, values);
+
+            Ok(())
+        }
+
+        /// Write `values` to the registers from `reg` on, in one transaction
+        $($async)? fn write_reg(&mut self, reg: Register, values: &[u8]) -> Result<(), E> {
+            #[cfg(feature = "stats")]
+            let start = self.stats_clock.map(|now_us| now_us());
+
+            self.bus_write_registers(reg, values)$(.$await)??;
+
+            #[cfg(feature = "stats")]
+            if let (Some(start), Some(now_us)) = (start, self.stats_clock) {
+                self.stats.writes.record(now_us().wrapping_sub(start));
+            }
+
+            #[cfg(feature = "trace")]
+            self.tracer.write(reg, values);
+
+            Ok(())
+        }
+    };
+}
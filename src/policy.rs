@@ -0,0 +1,247 @@
+//! Declarative fault handling
+//!
+//! A [`FaultPolicy`] says what to do when each fault is raised, and a
+//! [`FaultSupervisor`] polls the device and carries it out:
+//!
+//! ```
+//! use tpa2016d2::fault::FaultCallbacks;
+//! use tpa2016d2::policy::{FaultAction, FaultPolicy, FaultSupervisor};
+//! # use tpa2016d2::Tpa2016d2;
+//!
+//! # fn supervise<I2C, E>(amp: &mut Tpa2016d2<I2C>) -> Result<(), tpa2016d2::Error<E>>
+//! # where
+//! #     I2C: tpa2016d2::RegisterInterface<Error = E>,
+//! # {
+//! const POLICY: FaultPolicy = FaultPolicy {
+//!     short_circuit_left: FaultAction::DisableChannel,
+//!     short_circuit_right: FaultAction::DisableChannel,
+//!     thermal: FaultAction::Attenuate(6),
+//! };
+//! let hook = FaultCallbacks::new(|_| {}, |_| {});
+//! let mut supervisor = FaultSupervisor::new(POLICY, hook);
+//!
+//! // From a periodic task
+//! supervisor.poll(amp)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::fault::{EventQueue, Fault, FaultEvent, FaultHandler, FaultMonitor};
+use crate::thermal::derated_gain;
+use crate::{Error, Faults, RegisterInterface, Tpa2016d2};
+
+/// What to do when a fault is raised
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Nothing
+    Ignore,
+    /// Lower the fixed gain by this many dB while the fault is present
+    Attenuate(u8),
+    /// Disable the speaker of the faulting channel while the fault is
+    /// present, both speakers for thermal faults
+    DisableChannel,
+    /// Put the device in software shutdown, it stays there until the
+    /// application wakes it up
+    Shutdown,
+    /// Call the hook given to the supervisor
+    Hook,
+}
+
+/// The action for each fault
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultPolicy {
+    pub short_circuit_left: FaultAction,
+    pub short_circuit_right: FaultAction,
+    pub thermal: FaultAction,
+}
+
+impl FaultPolicy {
+    /// The same action for all faults
+    pub const fn uniform(action: FaultAction) -> Self {
+        FaultPolicy {
+            short_circuit_left: action,
+            short_circuit_right: action,
+            thermal: action,
+        }
+    }
+
+    /// The action for `fault`
+    pub fn action(&self, fault: Fault) -> FaultAction {
+        match fault {
+            Fault::ShortCircuitLeft => self.short_circuit_left,
+            Fault::ShortCircuitRight => self.short_circuit_right,
+            Fault::Thermal => self.thermal,
+        }
+    }
+}
+
+/// Executor of a [`FaultPolicy`]
+pub struct FaultSupervisor<H> {
+    policy: FaultPolicy,
+    hook: H,
+    monitor: FaultMonitor,
+    // The gain register value before any attenuation
+    saved_gain: Option<u8>,
+}
+
+impl<H: FaultHandler> FaultSupervisor<H> {
+    /// Creates a supervisor assuming no faults are present
+    pub fn new(policy: FaultPolicy, hook: H) -> Self {
+        FaultSupervisor {
+            policy,
+            hook,
+            monitor: FaultMonitor::new(),
+            saved_gain: None,
+        }
+    }
+
+    /// Read the faults from the device and act on the transitions
+    ///
+    /// Attenuations of several faults add up. When the last attenuating fault
+    /// clears the gain from before the first one is restored, so gain changes
+    /// made while attenuated are overwritten.
+    pub fn poll<I2C, E>(&mut self, amp: &mut Tpa2016d2<I2C>) -> Result<Faults, Error<E>>
+    where
+        I2C: RegisterInterface<Error = E>,
+    {
+        let faults = amp.get_faults()?;
+        let mut events = EventQueue::default();
+        self.monitor.update(faults, &mut events);
+
+        let mut attenuation_changed = false;
+        while let Some(event) = events.pop() {
+            match (self.policy.action(event.fault()), event) {
+                (FaultAction::Ignore, _) => (),
+                (FaultAction::Attenuate(_), _) => attenuation_changed = true,
+                (FaultAction::DisableChannel, FaultEvent::Raised(fault)) => {
+                    set_channels(amp, fault, false)?
+                }
+                (FaultAction::DisableChannel, FaultEvent::Cleared(fault)) => {
+                    set_channels(amp, fault, true)?
+                }
+                (FaultAction::Shutdown, FaultEvent::Raised(_)) => amp.disable_device()?,
+                (FaultAction::Shutdown, FaultEvent::Cleared(_)) => (),
+                (FaultAction::Hook, FaultEvent::Raised(fault)) => self.hook.on_fault_raised(fault),
+                (FaultAction::Hook, FaultEvent::Cleared(fault)) => {
+                    self.hook.on_fault_cleared(fault)
+                }
+            }
+        }
+
+        if attenuation_changed {
+            self.attenuate(amp, faults)?;
+        }
+
+        Ok(faults)
+    }
+
+    fn attenuate<I2C, E>(
+        &mut self,
+        amp: &mut Tpa2016d2<I2C>,
+        faults: Faults,
+    ) -> Result<(), Error<E>>
+    where
+        I2C: RegisterInterface<Error = E>,
+    {
+        let total: i16 = Fault::ALL
+            .iter()
+            .filter(|fault| fault.is_set(&faults))
+            .map(|&fault| match self.policy.action(fault) {
+                FaultAction::Attenuate(db) => db as i16,
+                _ => 0,
+            })
+            .sum();
+
+        let current = amp.agc_config().fixed_gain;
+        if total == 0 {
+            if let Some(saved) = self.saved_gain.take() {
+                amp.write_gain_raw(saved)?;
+            }
+            return Ok(());
+        }
+
+        let nominal = *self.saved_gain.get_or_insert(current);
+        // The gain range of the device is 58 dB
+        let ratio = amp.agc_config().compression_ratio;
+        let gain = derated_gain(nominal, total.min(58) as u8, ratio);
+        if gain != current {
+            amp.write_gain_raw(gain)?;
+        }
+        Ok(())
+    }
+}
+
+fn set_channels<I2C, E>(amp: &mut Tpa2016d2<I2C>, fault: Fault, on: bool) -> Result<(), Error<E>>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    let status = amp.status();
+    let (left, right) = match fault {
+        Fault::ShortCircuitLeft => (on, status.speaker_right),
+        Fault::ShortCircuitRight => (status.speaker_left, on),
+        Fault::Thermal => (on, on),
+    };
+    amp.speaker_enable(left, right)
+}
+
+#[cfg(all(test, feature = "hal"))]
+mod tests {
+    use super::*;
+    use crate::fault::FaultCallbacks;
+    use crate::mock::MockI2c;
+
+    const FAULT_L: u8 = 1 << 3;
+    const THERMAL: u8 = 1 << 2;
+
+    #[test]
+    fn attenuate_and_restore() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        let policy = FaultPolicy::uniform(FaultAction::Attenuate(4));
+        let mut supervisor = FaultSupervisor::new(policy, FaultCallbacks::new(|_| {}, |_| {}));
+
+        amp.bus_mut().regs[1] |= THERMAL;
+        supervisor.poll(&mut amp).unwrap();
+        amp.bus_mut().regs[1] |= FAULT_L;
+        supervisor.poll(&mut amp).unwrap();
+        amp.bus_mut().regs[1] &= !(THERMAL | FAULT_L);
+        supervisor.poll(&mut amp).unwrap();
+
+        // Not below 0 dB with compression
+        assert_eq!(amp.release().writes, [(5, 2), (5, 0), (5, 6)]);
+    }
+
+    #[test]
+    fn attenuate_without_compression() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        amp.compression_ratio(crate::CompressionRatio::Ratio1)
+            .unwrap();
+        let policy = FaultPolicy::uniform(FaultAction::Attenuate(10));
+        let mut supervisor = FaultSupervisor::new(policy, FaultCallbacks::new(|_| {}, |_| {}));
+
+        amp.bus_mut().regs[1] |= THERMAL;
+        supervisor.poll(&mut amp).unwrap();
+        assert_eq!(amp.agc_config().fixed_gain, crate::agc::fixed_gain_code(-4));
+    }
+
+    #[test]
+    fn per_fault_actions() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        let mut hooked = 0;
+        let policy = FaultPolicy {
+            short_circuit_left: FaultAction::DisableChannel,
+            short_circuit_right: FaultAction::Ignore,
+            thermal: FaultAction::Hook,
+        };
+        let mut supervisor =
+            FaultSupervisor::new(policy, FaultCallbacks::new(|_| hooked += 1, |_| {}));
+
+        amp.bus_mut().regs[1] |= FAULT_L | THERMAL;
+        supervisor.poll(&mut amp).unwrap();
+        amp.bus_mut().regs[1] &= !FAULT_L;
+        supervisor.poll(&mut amp).unwrap();
+
+        assert_eq!(hooked, 1);
+        // The fault flags read back are written along with the enable bits
+        assert_eq!(amp.release().writes, [(1, 0x8F), (1, 0xC7)]);
+    }
+}
@@ -0,0 +1,269 @@
+//! Supply current estimation
+//!
+//! A rough estimate of the average supply current for battery life budgeting.
+//! The output level is the program level amplified by the fixed gain, capped
+//! by the output limiter and by what the supply can deliver into the load.
+//! The output stage efficiency and the quiescent current are typical values,
+//! so expect the estimate to be within some tens of percent.
+//!
+//! [`recommended_limiter_level`] uses the same model to pick the output
+//! limiter level for a supply voltage and speaker, and
+//! [`sine_output_power_uw`] to check the power a speaker gets at full scale.
+
+use core::convert::TryFrom;
+
+use crate::agc::{fixed_gain_db, AgcConfig};
+use crate::limits::LIMITER_LEVEL_CODE_MAX;
+
+/// Typical supply current when active without signal, in µA
+pub const QUIESCENT_CURRENT_UA: u32 = 3500;
+/// Typical supply current in software shutdown, in µA
+pub const SHUTDOWN_CURRENT_UA: u32 = 20;
+/// Typical on resistance of the output stage, high and low side together, in mΩ
+pub const OUTPUT_RESISTANCE_MOHM: u32 = 450;
+/// Assumed efficiency of the class-D output stage, in percent
+pub const EFFICIENCY_PCT: u32 = 80;
+
+/// The conditions the amplifier is operated in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OperatingPoint {
+    /// Supply voltage in mV
+    pub supply_mv: u16,
+    /// Speaker impedance in ohms
+    pub load_ohms: u8,
+    /// Average input level in 0.1 dBV
+    pub program_level_dbv_x10: i16,
+}
+
+/// Nominal speaker impedance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpeakerImpedance {
+    Ohm4 = 4,
+    Ohm6 = 6,
+    Ohm8 = 8,
+}
+
+/// From the impedance in ohms, the rejected value is the error
+impl TryFrom<u8> for SpeakerImpedance {
+    type Error = u8;
+
+    fn try_from(ohms: u8) -> Result<Self, u8> {
+        match ohms {
+            4 => Ok(SpeakerImpedance::Ohm4),
+            6 => Ok(SpeakerImpedance::Ohm6),
+            8 => Ok(SpeakerImpedance::Ohm8),
+            _ => Err(ohms),
+        }
+    }
+}
+
+/// The highest output limiter level the output reaches without clipping
+///
+/// The bridge tied output swings the supply voltage `vdd_mv` less the drop
+/// over the output stage into the speaker. Returns the limiter level register
+/// value, `None` if even the lowest level clips.
+pub fn recommended_limiter_level(vdd_mv: u16, impedance: SpeakerImpedance) -> Option<u8> {
+    let max_mv2 = clipping_mv2(vdd_mv, impedance as u8);
+
+    (0..=LIMITER_LEVEL_CODE_MAX)
+        .rev()
+        .find(|&code| dbv_x10_to_mv2(-65 + 5 * code as i32) <= max_mv2)
+}
+
+/// Square of the highest sine output voltage before clipping, in mV²
+fn clipping_mv2(vdd_mv: u16, load_ohms: u8) -> u64 {
+    let load_mohm = load_ohms as u64 * 1000;
+    let peak_mv = vdd_mv as u64 * load_mohm / (load_mohm + OUTPUT_RESISTANCE_MOHM as u64);
+    peak_mv * peak_mv / 2
+}
+
+// 10^(n * 0.05) * 10000, powers for 0.5 dB steps over one decade
+pub(crate) const POW10_HALF_DB: [u32; 20] = [
+    10000, 11220, 12589, 14125, 15849, 17783, 19953, 22387, 25119, 28184, 31623, 35481, 39811,
+    44668, 50119, 56234, 63096, 70795, 79433, 89125,
+];
+
+/// Square of a voltage given in 0.1 dBV, in mV², rounded to 0.5 dB
+fn dbv_x10_to_mv2(dbv_x10: i32) -> u64 {
+    let steps = (dbv_x10 + if dbv_x10 < 0 { -2 } else { 2 }) / 5;
+    let decade = steps.div_euclid(20);
+    let mut v2 = POW10_HALF_DB[steps.rem_euclid(20) as usize] as u64 * 100;
+
+    if decade >= 0 {
+        for _ in 0..decade {
+            v2 *= 10;
+        }
+    } else {
+        for _ in decade..0 {
+            v2 /= 10;
+        }
+    }
+    v2
+}
+
+/// Square of the output voltage for an input at `input_dbv_x10`, in mV²
+///
+/// The input is amplified by the fixed gain and capped by the output limiter,
+/// when enabled, and by the clipping of the output stage.
+fn output_mv2(config: &AgcConfig, input_dbv_x10: i32, vdd_mv: u16, load_ohms: u8) -> u64 {
+    let gain = fixed_gain_db(config.fixed_gain).unwrap_or(0) as i32;
+    let mut level = input_dbv_x10 + gain * 10;
+    if config.output_limiter {
+        level = level.min(-65 + 5 * (config.output_limiter_level & 0b1_1111) as i32);
+    }
+
+    dbv_x10_to_mv2(level).min(clipping_mv2(vdd_mv, load_ohms))
+}
+
+/// Estimated average output power per channel in µW
+pub fn output_power_uw(config: &AgcConfig, op: &OperatingPoint) -> u32 {
+    if op.load_ohms == 0 {
+        return 0;
+    }
+
+    let v2 = output_mv2(
+        config,
+        op.program_level_dbv_x10 as i32,
+        op.supply_mv,
+        op.load_ohms,
+    );
+    (v2 / op.load_ohms as u64) as u32
+}
+
+/// Estimated output power per channel in µW for a sine input at `input_dbv_x10`
+///
+/// The input is amplified by the fixed gain and capped by the output limiter,
+/// when enabled, and by the clipping of the output stage, as for
+/// [`recommended_limiter_level`]. The clipping point matches the 1 % THD+N
+/// output power curves of the datasheet, at 10 % THD+N the device delivers
+/// about a quarter more. Use the full scale level of the source as input to
+/// check the highest power a speaker gets.
+pub fn sine_output_power_uw(
+    config: &AgcConfig,
+    vdd_mv: u16,
+    load_ohms: u8,
+    input_dbv_x10: i16,
+) -> u32 {
+    if load_ohms == 0 {
+        return 0;
+    }
+
+    let v2 = output_mv2(config, input_dbv_x10 as i32, vdd_mv, load_ohms);
+    (v2 / load_ohms as u64) as u32
+}
+
+/// Estimated average supply current in µA with `channels` speakers enabled
+pub fn estimate_current_ua(config: &AgcConfig, channels: u8, op: &OperatingPoint) -> u32 {
+    if op.supply_mv == 0 {
+        return 0;
+    }
+
+    let output_uw = output_power_uw(config, op) as u64 * channels as u64;
+    let input_uw = output_uw * 100 / EFFICIENCY_PCT as u64;
+
+    QUIESCENT_CURRENT_UA + (input_uw * 1000 / op.supply_mv as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CompressionRatio;
+
+    fn config(fixed_gain: u8, output_limiter_level: u8) -> AgcConfig {
+        AgcConfig {
+            attack_time: 5,
+            release_time: 11,
+            hold_time: 0,
+            fixed_gain,
+            max_gain: 12,
+            output_limiter_level,
+            output_limiter: true,
+            compression_ratio: CompressionRatio::Ratio4,
+        }
+    }
+
+    #[test]
+    fn dbv_conversion() {
+        assert_eq!(dbv_x10_to_mv2(0), 1_000_000);
+        assert_eq!(dbv_x10_to_mv2(60), 3_981_100);
+        assert_eq!(dbv_x10_to_mv2(-200), 10_000);
+        assert_eq!(dbv_x10_to_mv2(-65), 223_870);
+    }
+
+    #[test]
+    fn limited_output() {
+        let op = OperatingPoint {
+            supply_mv: 5000,
+            load_ohms: 8,
+            program_level_dbv_x10: 0,
+        };
+        // 0 dBV in, 6 dB gain, limiter at 0 dBV: 1 V into 8 ohm
+        assert_eq!(output_power_uw(&config(6, 13), &op), 125_000);
+        // Limiter at 9 dBV, 6 dBV out
+        assert_eq!(output_power_uw(&config(6, 31), &op), 497_637);
+
+        // Without the limiter, 6 dBV out
+        let mut unlimited = config(6, 13);
+        unlimited.output_limiter = false;
+        assert_eq!(output_power_uw(&unlimited, &op), 497_637);
+    }
+
+    #[test]
+    fn supply_limited_output() {
+        let op = OperatingPoint {
+            supply_mv: 3000,
+            load_ohms: 4,
+            program_level_dbv_x10: 0,
+        };
+        // Clipping at 2.70 V peak
+        assert_eq!(output_power_uw(&config(30, 31), &op), 908_552);
+    }
+
+    #[test]
+    fn limiter_recommendation() {
+        assert_eq!(
+            recommended_limiter_level(5000, SpeakerImpedance::Ohm8),
+            Some(31)
+        );
+        // 7.6 dBV
+        assert_eq!(
+            recommended_limiter_level(3600, SpeakerImpedance::Ohm8),
+            Some(28)
+        );
+        // 4.0 dBV
+        assert_eq!(
+            recommended_limiter_level(2500, SpeakerImpedance::Ohm4),
+            Some(21)
+        );
+        assert_eq!(recommended_limiter_level(0, SpeakerImpedance::Ohm4), None);
+    }
+
+    #[test]
+    fn sine_output() {
+        // Limiter at 0 dBV: 1 V into 8 ohm
+        assert_eq!(sine_output_power_uw(&config(6, 13), 5000, 8, 0), 125_000);
+        // -20 dBV in, 6 dB gain
+        assert_eq!(sine_output_power_uw(&config(6, 31), 5000, 8, -200), 4_976);
+        // Limiter at 9 dBV, below the clipping point
+        assert_eq!(sine_output_power_uw(&config(30, 31), 5000, 8, 0), 992_912);
+        // Clipping at 3.24 V peak
+        assert_eq!(sine_output_power_uw(&config(30, 31), 3600, 4, 0), 1_308_153);
+        // Without the limiter only the clipping caps the output
+        let mut unlimited = config(30, 13);
+        unlimited.output_limiter = false;
+        assert_eq!(sine_output_power_uw(&unlimited, 3600, 4, 0), 1_308_153);
+        assert_eq!(sine_output_power_uw(&config(30, 31), 5000, 0, 0), 0);
+    }
+
+    #[test]
+    fn current() {
+        let op = OperatingPoint {
+            supply_mv: 5000,
+            load_ohms: 8,
+            program_level_dbv_x10: 0,
+        };
+        // 250 mW out, 312.5 mW in
+        assert_eq!(estimate_current_ua(&config(6, 13), 2, &op), 3500 + 62_500);
+        assert_eq!(estimate_current_ua(&config(6, 13), 0, &op), 3500);
+    }
+}
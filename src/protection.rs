@@ -0,0 +1,158 @@
+//! Speaker protection limits
+//!
+//! With a [`ProtectionProfile`] installed through
+//! [`Tpa2016d2::set_protection`](crate::Tpa2016d2::set_protection), every
+//! register write is capped to the profile: a fixed gain or AGC max gain
+//! above the limit is lowered to it, and the output limiter is kept enabled
+//! at or below the limiter level. The capped values become the driver's view
+//! of the registers, so the getters report what the device got.
+//!
+//! ```ignore
+//! let mut amp = Tpa2016d2::new(i2c);
+//! amp.set_protection(Some(ProtectionProfile {
+//!     max_fixed_gain_db: 12,
+//!     max_agc_gain_db: 24,
+//!     max_limiter_dbv_x10: 0,
+//! }));
+//! amp.gain_db(20).unwrap();
+//! assert_eq!(amp.fixed_gain_db(), 12);
+//! ```
+
+use crate::agc;
+use crate::limits::{LIMITER_LEVEL_RANGE_DBV_X10, MAX_GAIN_DB_RANGE, MIN_FIXED_GAIN_DB_COMPRESSED};
+use crate::regmap::{Register, RegisterMap, U4, U5};
+
+/// Limits for a speaker, enforced on all register writes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProtectionProfile {
+    /// Highest fixed gain, in dB
+    pub max_fixed_gain_db: i8,
+    /// Highest max gain of the AGC, in dB
+    pub max_agc_gain_db: i8,
+    /// Highest output limiter level in 0.1 dBV, the limiter is kept enabled
+    pub max_limiter_dbv_x10: i16,
+}
+
+impl ProtectionProfile {
+    /// Cap the settings of register `reg`
+    pub(crate) fn apply(&self, regmap: &mut RegisterMap, reg: Register) {
+        match reg {
+            Register::FixedGain => {
+                let max = self.max_fixed_gain_db(regmap);
+                if agc::fixed_gain_to_db(regmap.fixed_gain()) > max {
+                    regmap.set_fixed_gain(agc::fixed_gain_code(max));
+                }
+            }
+            Register::Agc1 => {
+                regmap.set_output_limiter_disable(false);
+                let max = self.limiter_level_code();
+                if regmap.output_limiter_level().value() > max {
+                    regmap.set_output_limiter_level(U5::masked(max));
+                }
+            }
+            Register::Agc2 => {
+                let max = self.max_gain_code();
+                if regmap.max_gain().value() > max {
+                    regmap.set_max_gain(U4::masked(max));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// The highest fixed gain in dB, not below 0 dB with compression
+    fn max_fixed_gain_db(&self, regmap: &RegisterMap) -> i8 {
+        if regmap.compression_ratio().value() == 0 {
+            self.max_fixed_gain_db
+        } else {
+            self.max_fixed_gain_db.max(MIN_FIXED_GAIN_DB_COMPRESSED)
+        }
+    }
+
+    /// The highest limiter level register value, rounded down
+    fn limiter_level_code(&self) -> u8 {
+        let range = LIMITER_LEVEL_RANGE_DBV_X10;
+        let dbv_x10 = self.max_limiter_dbv_x10.clamp(*range.start(), *range.end());
+        ((dbv_x10 - range.start()) / 5) as u8
+    }
+
+    /// The highest max gain register value
+    fn max_gain_code(&self) -> u8 {
+        let range = MAX_GAIN_DB_RANGE;
+        (self.max_agc_gain_db.clamp(*range.start(), *range.end()) - range.start()) as u8
+    }
+}
+
+#[cfg(all(test, feature = "hal"))]
+mod tests {
+    use super::*;
+    use crate::mock::MockI2c;
+    use crate::{AgcPreset, Config, Tpa2016d2};
+
+    const PROFILE: ProtectionProfile = ProtectionProfile {
+        max_fixed_gain_db: 12,
+        max_agc_gain_db: 24,
+        max_limiter_dbv_x10: 2,
+    };
+
+    #[test]
+    fn compressed_gain_floor() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        amp.set_protection(Some(ProtectionProfile {
+            max_fixed_gain_db: -6,
+            ..PROFILE
+        }));
+
+        amp.gain_db(6).unwrap();
+        assert_eq!(amp.fixed_gain_db(), 0);
+        assert!(amp.config().validate().is_ok());
+
+        amp.compression_ratio(crate::CompressionRatio::Ratio1)
+            .unwrap();
+        amp.gain_db(6).unwrap();
+        assert_eq!(amp.fixed_gain_db(), -6);
+    }
+
+    #[test]
+    fn caps_setters() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        amp.set_protection(Some(PROFILE));
+
+        amp.gain_db(20).unwrap();
+        amp.gain_db(-10).unwrap();
+        amp.set_max_gain(12).unwrap();
+        amp.output_limiter_level(31).unwrap();
+        amp.compression_ratio(crate::CompressionRatio::Ratio1)
+            .unwrap();
+        amp.output_limiter(false).unwrap();
+        assert_eq!(
+            amp.bus_mut().writes,
+            [
+                (5, 12),
+                (5, 0x36),
+                (7, 0x62),
+                (6, 0x2D),
+                (7, 0x60),
+                (6, 0x2D)
+            ]
+        );
+
+        amp.set_agc_preset(AgcPreset::Rock).unwrap();
+        amp.apply_config(&Config::DEFAULT).unwrap();
+        let agc = amp.agc_config();
+        assert!(agc::fixed_gain_to_db(agc.fixed_gain) <= 12);
+        assert!(agc.max_gain <= 6);
+        assert!(agc.output_limiter_level <= 13);
+        assert!(!amp.core().registers().output_limiter_disable());
+
+        amp.gain_unchecked(agc::fixed_gain_code(30)).unwrap();
+        amp.output_limiter_level_unchecked(31).unwrap();
+        let writes = &amp.bus_mut().writes;
+        assert_eq!(writes[writes.len() - 2..], [(5, 12), (6, 0x2D)]);
+
+        amp.set_protection(None);
+        amp.gain_db(20).unwrap();
+        assert_eq!(amp.fixed_gain_db(), 20);
+    }
+}
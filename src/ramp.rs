@@ -0,0 +1,107 @@
+//! Non-blocking gain ramp
+//!
+//! [`GainRamp`] moves the fixed gain to a target in small steps, one register
+//! write per [`GainRamp::tick`], for cooperative schedulers where the
+//! blocking [`Tpa2016d2::fade_to`](crate::Tpa2016d2::fade_to) does not fit.
+//!
+//! ```
+//! # use tpa2016d2::control::FakeAmp;
+//! use tpa2016d2::ramp::GainRamp;
+//!
+//! # let mut amp = FakeAmp::new();
+//! let mut ramp = GainRamp::new(6);
+//! ramp.set_target(20, 1);
+//!
+//! // From a periodic timer
+//! while !ramp.tick(&mut amp)? {}
+//! # assert_eq!(amp.gain, 20);
+//! # Ok::<(), tpa2016d2::control::FakeError>(())
+//! ```
+
+use crate::agc::{fixed_gain_code, fixed_gain_to_db};
+use crate::Tpa2016Control;
+
+/// Fixed gain ramp advanced by a periodic tick
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GainRamp {
+    current_db: i8,
+    target_db: i8,
+    step_db: u8,
+}
+
+impl GainRamp {
+    /// A ramp at rest at the fixed gain register value `gain`
+    pub fn new(gain: u8) -> Self {
+        let db = fixed_gain_to_db(gain);
+        GainRamp {
+            current_db: db,
+            target_db: db,
+            step_db: 1,
+        }
+    }
+
+    /// Ramp to the fixed gain register value `gain` in steps of `step_db`
+    ///
+    /// A ramp in progress continues from the gain reached so far.
+    pub fn set_target(&mut self, gain: u8, step_db: u8) {
+        self.target_db = fixed_gain_to_db(gain);
+        self.step_db = step_db.max(1);
+    }
+
+    /// The gain last written, as register value
+    pub fn current(&self) -> u8 {
+        fixed_gain_code(self.current_db)
+    }
+
+    /// Returns true when the target has been reached
+    pub fn is_done(&self) -> bool {
+        self.current_db == self.target_db
+    }
+
+    /// Write the next step, if any
+    ///
+    /// Returns true when the target has been reached. A failed write is
+    /// retried on the next tick.
+    pub fn tick<A: Tpa2016Control>(&mut self, amp: &mut A) -> Result<bool, A::Error> {
+        if self.is_done() {
+            return Ok(true);
+        }
+        let remaining = self.target_db as i16 - self.current_db as i16;
+        let step = remaining.clamp(-(self.step_db as i16), self.step_db as i16);
+        let next = (self.current_db as i16 + step) as i8;
+
+        amp.gain(fixed_gain_code(next))?;
+        self.current_db = next;
+        Ok(self.is_done())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::FakeAmp;
+    use crate::ConfigChange;
+
+    #[test]
+    fn one_write_per_tick() {
+        let mut amp = FakeAmp::new();
+        let mut ramp = GainRamp::new(6);
+        ramp.set_target(fixed_gain_code(-1), 3);
+
+        assert!(!ramp.tick(&mut amp).unwrap());
+        amp.fail_next = true;
+        assert!(ramp.tick(&mut amp).is_err());
+        assert!(!ramp.tick(&mut amp).unwrap());
+        assert!(ramp.tick(&mut amp).unwrap());
+        assert!(ramp.tick(&mut amp).unwrap());
+
+        assert_eq!(
+            amp.calls(),
+            [
+                ConfigChange::Gain(3),
+                ConfigChange::Gain(0),
+                ConfigChange::Gain(fixed_gain_code(-1))
+            ]
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Low-level register access
+//!
+//! For settings the rest of the API does not cover. The driver's view of the
+//! registers is kept up to date, so the regular setters and getters can be
+//! used along with these.
+//!
+//! [`Tpa2016d2::dump_registers`] reads all registers for bring-up logs, see
+//! [`RegisterDump`](crate::regmap::RegisterDump) to print them.
+//!
+//! ```
+//! use tpa2016d2::{Register, Tpa2016d2};
+//!
+//! # fn run<I2C, E>(amp: &mut Tpa2016d2<I2C>) -> Result<(), tpa2016d2::Error<E>>
+//! # where
+//! #     I2C: tpa2016d2::RegisterInterface<Error = E>,
+//! # {
+//! // Disable the output limiter and select the 20 mV noise gate threshold
+//! amp.write_register(Register::Agc1, 0xFA)?;
+//! assert_eq!(amp.read_register(Register::Agc1)?, 0xFA);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Error, Register, RegisterInterface, Tpa2016d2};
+
+impl<I2C, E> Tpa2016d2<I2C>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    /// Read register `reg` from the device
+    pub fn read_register(&mut self, reg: Register) -> Result<u8, Error<E>> {
+        let value = self.read_reg(reg).map_err(Error::I2c)?;
+        self.receive(reg, value);
+        Ok(value)
+    }
+
+    /// Read registers 1 to 7 from the device, in one transaction
+    ///
+    /// The driver's view of the registers is left as is.
+    pub fn dump_registers(&mut self) -> Result<[u8; 7], Error<E>> {
+        let mut regs = [0; 7];
+        self.read_regs(Register::Control, &mut regs)
+            .map_err(Error::I2c)?;
+        Ok(regs)
+    }
+
+    /// Write `value` to register `reg`
+    ///
+    /// Reserved bits are written with their fixed values, and the fault flags
+    /// of register 1 are ignored by the device. Writes are deferred and
+    /// verified like those of the setters.
+    pub fn write_register(&mut self, reg: Register, value: u8) -> Result<(), Error<E>> {
+        self.core.regmap.update_map(reg, value);
+        let commands = self.core.write(reg);
+        self.execute(commands)
+    }
+}
+
+#[cfg(all(test, feature = "hal"))]
+mod tests {
+    use crate::mock::MockI2c;
+    use crate::{NoiseGateThreshold, Register, Tpa2016d2};
+
+    #[test]
+    fn raw_access() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        amp.write_register(Register::Agc1, 0xFA).unwrap();
+        assert!(!amp.output_limiter_enabled());
+        assert_eq!(amp.get_noise_gate_threshold(), NoiseGateThreshold::Ngt20mV);
+
+        // Reserved bit 1 always set
+        amp.write_register(Register::Control, 0x00).unwrap();
+        assert_eq!(amp.bus_mut().regs[1], 0x02);
+
+        amp.bus_mut().regs[5] = 0x10;
+        assert_eq!(amp.read_register(Register::FixedGain).unwrap(), 0x10);
+        assert_eq!(amp.fixed_gain(), 0x10);
+    }
+
+    #[test]
+    fn dump_registers() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        amp.bus_mut().regs[5] = 0x10;
+        assert_eq!(
+            amp.dump_registers().unwrap(),
+            [0xC3, 0x05, 0x0B, 0x00, 0x10, 0x3A, 0xC2]
+        );
+        assert_eq!(amp.fixed_gain(), 0x06);
+    }
+}
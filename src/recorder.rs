@@ -0,0 +1,216 @@
+//! Dry run transport recording the register accesses
+//!
+//! [`Recorder`] implements [`RegisterInterface`] without hardware: writes go
+//! to a register file holding the power-on values, reads are answered from
+//! it, and every transaction is recorded for inspection.
+//!
+//! ```
+//! use tpa2016d2::recorder::{Recorder, Transaction};
+//! use tpa2016d2::{Register, Tpa2016d2};
+//!
+//! let mut amp = Tpa2016d2::new(Recorder::<16>::new());
+//! amp.gain(12).unwrap();
+//! amp.mute().unwrap();
+//!
+//! let recorder = amp.release();
+//! assert_eq!(
+//!     recorder.transactions(),
+//!     [
+//!         Transaction::write(0x58, Register::FixedGain, &[12]),
+//!         Transaction::write(0x58, Register::Control, &[0x03]),
+//!     ]
+//! );
+//! ```
+//!
+//! Transactions beyond the capacity `N` are counted by
+//! [`Recorder::dropped`] but not stored.
+
+use core::convert::Infallible;
+
+use crate::regmap::RegisterMap;
+use crate::{Register, RegisterInterface};
+
+/// Direction of a [`Transaction`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A recorded transaction on consecutive registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Transaction {
+    pub access: Access,
+    /// Device address
+    pub addr: u8,
+    /// First register
+    pub reg: Register,
+    values: [u8; 7],
+    len: u8,
+}
+
+impl Transaction {
+    fn new(access: Access, addr: u8, reg: Register, values: &[u8]) -> Self {
+        let mut transaction = Transaction {
+            access,
+            addr,
+            reg,
+            values: [0; 7],
+            len: values.len() as u8,
+        };
+        transaction.values[..values.len()].copy_from_slice(values);
+        transaction
+    }
+
+    /// `values` read from the registers from `reg` on
+    pub fn read(addr: u8, reg: Register, values: &[u8]) -> Self {
+        Self::new(Access::Read, addr, reg, values)
+    }
+
+    /// `values` written to the registers from `reg` on
+    pub fn write(addr: u8, reg: Register, values: &[u8]) -> Self {
+        Self::new(Access::Write, addr, reg, values)
+    }
+
+    /// The values read or written
+    pub fn values(&self) -> &[u8] {
+        &self.values[..self.len as usize]
+    }
+}
+
+/// Transport recording up to `N` transactions instead of accessing a device
+#[derive(Clone, Debug)]
+pub struct Recorder<const N: usize> {
+    regs: RegisterMap,
+    transactions: [Transaction; N],
+    len: usize,
+    dropped: usize,
+}
+
+impl<const N: usize> Default for Recorder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Recorder<N> {
+    /// A recorder with the registers at their power-on values
+    pub fn new() -> Self {
+        Recorder {
+            regs: RegisterMap::default(),
+            transactions: [Transaction::read(0, Register::Control, &[]); N],
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// The recorded transactions, in order
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions[..self.len]
+    }
+
+    /// The number of transactions that did not fit
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Forget the recorded transactions, the registers keep their values
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.dropped = 0;
+    }
+
+    /// The value of register `reg` after the writes so far
+    pub fn register(&self, reg: Register) -> u8 {
+        self.regs.reg_as_byte(reg)
+    }
+
+    fn record(&mut self, transaction: Transaction) {
+        match self.transactions.get_mut(self.len) {
+            Some(slot) => {
+                *slot = transaction;
+                self.len += 1;
+            }
+            None => self.dropped += 1,
+        }
+    }
+}
+
+impl<const N: usize> RegisterInterface for Recorder<N> {
+    type Error = Infallible;
+
+    fn read_register(&mut self, addr: u8, reg: Register) -> Result<u8, Infallible> {
+        let mut value = [0];
+        self.read_registers(addr, reg, &mut value)?;
+        Ok(value[0])
+    }
+
+    fn read_registers(
+        &mut self,
+        addr: u8,
+        reg: Register,
+        buffer: &mut [u8],
+    ) -> Result<(), Infallible> {
+        let first = reg.addr() as usize - 1;
+        for (&reg, val) in Register::ALL[first..].iter().zip(buffer.iter_mut()) {
+            *val = self.regs.reg_as_byte(reg);
+        }
+        self.record(Transaction::read(addr, reg, buffer));
+        Ok(())
+    }
+
+    fn write_register(&mut self, addr: u8, reg: Register, value: u8) -> Result<(), Infallible> {
+        self.write_registers(addr, reg, &[value])
+    }
+
+    fn write_registers(
+        &mut self,
+        addr: u8,
+        reg: Register,
+        values: &[u8],
+    ) -> Result<(), Infallible> {
+        let first = reg.addr() as usize - 1;
+        for (&reg, &val) in Register::ALL[first..].iter().zip(values) {
+            self.regs.update_map(reg, val);
+        }
+        self.record(Transaction::write(addr, reg, values));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AgcPreset, Tpa2016d2};
+
+    #[test]
+    fn dry_run() {
+        let mut amp = Tpa2016d2::new(Recorder::<2>::new());
+        amp.set_agc_preset(AgcPreset::Jazz).unwrap();
+        amp.sync().unwrap();
+        assert_eq!(amp.agc_config(), AgcPreset::Jazz.config());
+        amp.gain(12).unwrap();
+
+        let mut recorder = amp.release();
+        let [write, read] = recorder.transactions() else {
+            panic!("{:?}", recorder.transactions());
+        };
+        assert_eq!(
+            *write,
+            Transaction::write(
+                0x58,
+                Register::AttackTime,
+                &[0x06, 0x14, 0x00, 0x06, 0x3D, 0xC1]
+            )
+        );
+        assert_eq!(read.access, Access::Read);
+        assert_eq!(read.values().len(), 7);
+        assert_eq!(recorder.dropped(), 1);
+        assert_eq!(recorder.register(Register::FixedGain), 12);
+
+        recorder.clear();
+        assert!(recorder.transactions().is_empty());
+    }
+}
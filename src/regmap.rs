@@ -1,9 +1,89 @@
+//! Decoded register values
+//!
+//! [`RegisterMap`] is the driver's view of the device registers, see
+//! [`Tpa2016d2::registers`](crate::Tpa2016d2::registers). Its `Debug` output
+//! shows the decoded state of all registers.
+
+use core::convert::TryFrom;
+use core::fmt;
+
+/// Register addresses of the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Register {
+    /// Speaker enables, software shutdown, fault flags and noise gate enable
+    Control = 1,
+    AttackTime = 2,
+    ReleaseTime = 3,
+    HoldTime = 4,
+    FixedGain = 5,
+    /// Output limiter and noise gate threshold
+    Agc1 = 6,
+    /// Max gain and compression ratio
+    Agc2 = 7,
+}
+
+impl Register {
+    /// All registers in address order
+    pub const ALL: [Register; 7] = [
+        Register::Control,
+        Register::AttackTime,
+        Register::ReleaseTime,
+        Register::HoldTime,
+        Register::FixedGain,
+        Register::Agc1,
+        Register::Agc2,
+    ];
+
+    /// The register address
+    pub const fn addr(self) -> u8 {
+        self as u8
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Register::Control => "Control",
+            Register::AttackTime => "AttackTime",
+            Register::ReleaseTime => "ReleaseTime",
+            Register::HoldTime => "HoldTime",
+            Register::FixedGain => "FixedGain",
+            Register::Agc1 => "Agc1",
+            Register::Agc2 => "Agc2",
+        }
+    }
+
+    /// The bit for this register in a bit mask of registers, bit n for register n
+    pub(crate) const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Number of registers from this one to the last, the longest burst
+    /// starting here
+    pub(crate) const fn remaining(self) -> usize {
+        Register::ALL.len() + 1 - self as usize
+    }
+}
+
+/// From the register address, the rejected value is the error
+impl TryFrom<u8> for Register {
+    type Error = u8;
+
+    fn try_from(addr: u8) -> Result<Self, u8> {
+        match addr {
+            1..=7 => Ok(Register::ALL[addr as usize - 1]),
+            _ => Err(addr),
+        }
+    }
+}
+
 pub trait RegisterMapRegister {
     fn as_byte(&self) -> u8;
     fn update(&mut self, val: u8);
 }
 
 #[allow(non_snake_case)]
+#[derive(Debug)]
 pub struct Register1 {
     pub SPK_EN_R: bool,
     pub SPK_EN_L: bool,
@@ -71,36 +151,47 @@ impl RegisterMapRegister for Register1 {
     }
 }
 
-pub struct U6Register(u8);
+/// Unsigned integer of `BITS` bits, larger values are unrepresentable
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct UInt<const BITS: u32>(u8);
 
-impl U6Register {
-    pub fn set(&mut self, value: u8) {
-        self.0 = value & 0x3F;
+pub type U2 = UInt<2>;
+pub type U4 = UInt<4>;
+pub type U5 = UInt<5>;
+pub type U6 = UInt<6>;
+
+impl<const BITS: u32> UInt<BITS> {
+    pub const MAX: u8 = ((1u16 << BITS) - 1) as u8;
+
+    /// The low `BITS` bits of `val`
+    pub const fn masked(val: u8) -> Self {
+        Self(val & Self::MAX)
     }
-}
 
-impl RegisterMapRegister for U6Register {
-    fn as_byte(&self) -> u8 {
-        self.0 & 0x3F
+    pub const fn value(self) -> u8 {
+        self.0
     }
+}
 
-    fn update(&mut self, val: u8) {
-        self.0 = val & 0x3F;
+impl<const BITS: u32> fmt::Debug for UInt<BITS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
     }
 }
 
+#[derive(Debug)]
 pub struct Register6 {
     pub output_limiter_disable: bool,
-    pub noise_gate_threshold: u8,
-    pub output_limiter_level: u8,
+    pub noise_gate_threshold: U2,
+    pub output_limiter_level: U5,
 }
 
 impl Default for Register6 {
     fn default() -> Self {
         Self {
             output_limiter_disable: false,
-            noise_gate_threshold: 0b01,
-            output_limiter_level: 0b11010,
+            noise_gate_threshold: U2::masked(0b01),
+            output_limiter_level: U5::masked(0b11010),
         }
     }
 }
@@ -113,28 +204,29 @@ impl RegisterMapRegister for Register6 {
             r |= 1 << 7
         }
 
-        r |= (self.noise_gate_threshold & 0b11) << 5;
-        r |= self.output_limiter_level & 0b11111;
+        r |= self.noise_gate_threshold.value() << 5;
+        r |= self.output_limiter_level.value();
         r
     }
 
     fn update(&mut self, val: u8) {
         self.output_limiter_disable = val & 1 << 7 != 0;
-        self.noise_gate_threshold = (val >> 5) & 0b11;
-        self.output_limiter_level = val & 0b11111;
+        self.noise_gate_threshold = U2::masked(val >> 5);
+        self.output_limiter_level = U5::masked(val);
     }
 }
 
+#[derive(Debug)]
 pub struct Register7 {
-    pub max_gain: u8,
-    pub compression_ratio: u8,
+    pub max_gain: U4,
+    pub compression_ratio: U2,
 }
 
 impl Default for Register7 {
     fn default() -> Self {
         Self {
-            max_gain: 0b1100,
-            compression_ratio: 0b10,
+            max_gain: U4::masked(0b1100),
+            compression_ratio: U2::masked(0b10),
         }
     }
 }
@@ -142,66 +234,399 @@ impl Default for Register7 {
 impl RegisterMapRegister for Register7 {
     fn as_byte(&self) -> u8 {
         // Gain
-        (self.max_gain & 0b1111) << 4 |
+        self.max_gain.value() << 4 |
         // Compression radio
-        (self.compression_ratio & 0b11)
+        self.compression_ratio.value()
     }
 
     fn update(&mut self, val: u8) {
-        self.max_gain = val >> 4;
-        self.compression_ratio = val & 0b11;
+        self.max_gain = U4::masked(val >> 4);
+        self.compression_ratio = U2::masked(val);
     }
 }
 
-#[allow(non_snake_case)]
+/// The values of registers 1 to 7, with accessors for the fields
+///
+/// Reserved bits always have their fixed values.
+#[derive(Clone, PartialEq, Eq)]
 pub struct RegisterMap {
-    pub reg1: Register1,
-    pub atk_time: U6Register,  // reg2
-    pub rel_time: U6Register,  // reg3
-    pub hold_time: U6Register, // reg4
-    pub fixedGain: U6Register, // reg5
-    pub reg6: Register6,
-    pub reg7: Register7,
+    regs: [u8; 7],
 }
 
+/// Power-on values of registers 1 to 7
+pub(crate) const POWER_ON: [u8; 7] = [0xC3, 0x05, 0x0B, 0x00, 0x06, 0x3A, 0xC2];
+
 impl Default for RegisterMap {
     fn default() -> Self {
-        Self {
-            reg1: Register1::default(),
-            atk_time: U6Register(0x05),
-            rel_time: U6Register(0x0B),
-            hold_time: U6Register(0x00),
-            fixedGain: U6Register(0x06),
-            reg6: Register6::default(),
-            reg7: Register7::default(),
+        Self { regs: POWER_ON }
+    }
+}
+
+impl fmt::Debug for RegisterMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegisterMap")
+            .field("reg1", &self.reg1())
+            .field("atk_time", &self.attack_time())
+            .field("rel_time", &self.release_time())
+            .field("hold_time", &self.hold_time())
+            .field("fixedGain", &self.fixed_gain())
+            .field("reg6", &self.reg6())
+            .field("reg7", &self.reg7())
+            .finish()
+    }
+}
+
+macro_rules! flag {
+    ($reg:ident, $bit:expr, $get:ident, $set:ident) => {
+        pub fn $get(&self) -> bool {
+            self.regs[Register::$reg as usize - 1] & 1 << $bit != 0
+        }
+
+        pub fn $set(&mut self, on: bool) {
+            let reg = &mut self.regs[Register::$reg as usize - 1];
+            if on {
+                *reg |= 1 << $bit;
+            } else {
+                *reg &= !(1 << $bit);
+            }
+        }
+    };
+}
+
+macro_rules! field {
+    ($reg:ident, $ty:ident, $shift:expr, $get:ident, $set:ident) => {
+        pub fn $get(&self) -> $ty {
+            $ty::masked(self.regs[Register::$reg as usize - 1] >> $shift)
         }
+
+        pub fn $set(&mut self, val: $ty) {
+            let reg = &mut self.regs[Register::$reg as usize - 1];
+            *reg = *reg & !($ty::MAX << $shift) | val.value() << $shift;
+        }
+    };
+}
+
+macro_rules! time_field {
+    ($reg:ident, $get:ident, $set:ident) => {
+        pub fn $get(&self) -> u8 {
+            self.regs[Register::$reg as usize - 1]
+        }
+
+        /// Set the 6 bit value, higher bits are dropped
+        pub fn $set(&mut self, val: u8) {
+            self.regs[Register::$reg as usize - 1] = U6::masked(val).value();
+        }
+    };
+}
+
+/// Field accessors
+impl RegisterMap {
+    flag!(Control, 7, spk_en_r, set_spk_en_r);
+    flag!(Control, 6, spk_en_l, set_spk_en_l);
+    flag!(Control, 5, sws, set_sws);
+    flag!(Control, 4, fault_r, set_fault_r);
+    flag!(Control, 3, fault_l, set_fault_l);
+    flag!(Control, 2, thermal, set_thermal);
+    flag!(Control, 0, ng_en, set_ng_en);
+
+    time_field!(AttackTime, attack_time, set_attack_time);
+    time_field!(ReleaseTime, release_time, set_release_time);
+    time_field!(HoldTime, hold_time, set_hold_time);
+    time_field!(FixedGain, fixed_gain, set_fixed_gain);
+
+    flag!(Agc1, 7, output_limiter_disable, set_output_limiter_disable);
+    field!(Agc1, U2, 5, noise_gate_threshold, set_noise_gate_threshold);
+    field!(Agc1, U5, 0, output_limiter_level, set_output_limiter_level);
+
+    field!(Agc2, U4, 4, max_gain, set_max_gain);
+    field!(Agc2, U2, 0, compression_ratio, set_compression_ratio);
+
+    /// Register 1 decoded
+    pub fn reg1(&self) -> Register1 {
+        let mut reg = Register1::default();
+        reg.update(self.reg_as_byte(Register::Control));
+        reg
+    }
+
+    /// Register 6 decoded
+    pub fn reg6(&self) -> Register6 {
+        let mut reg = Register6::default();
+        reg.update(self.reg_as_byte(Register::Agc1));
+        reg
+    }
+
+    /// Register 7 decoded
+    pub fn reg7(&self) -> Register7 {
+        let mut reg = Register7::default();
+        reg.update(self.reg_as_byte(Register::Agc2));
+        reg
     }
 }
 
+/// A set of registers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RegisterSet(u8);
+
+impl RegisterSet {
+    pub const fn new() -> Self {
+        RegisterSet(0)
+    }
+
+    pub fn insert(&mut self, reg: Register) {
+        self.0 |= reg.bit();
+    }
+
+    pub const fn contains(self, reg: Register) -> bool {
+        self.0 & reg.bit() != 0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The registers in the set, in address order
+    pub fn iter(self) -> impl Iterator<Item = Register> {
+        Register::ALL
+            .iter()
+            .copied()
+            .filter(move |&reg| self.contains(reg))
+    }
+}
+
+/// Formats the values of registers 1 to 7, one register per line with its
+/// decoded fields
+///
+/// ```
+/// use tpa2016d2::regmap::RegisterDump;
+///
+/// let dump = RegisterDump([0xC3, 0x05, 0x0B, 0x00, 0x06, 0x3A, 0xC2]);
+/// assert!(dump.to_string().starts_with("0x01 Control     0xc3 Register1 {"));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterDump(pub [u8; 7]);
+
+impl fmt::Display for RegisterDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = RegisterMap::default();
+        for (&val, reg) in self.0.iter().zip(Register::ALL) {
+            map.update_map(reg, val);
+        }
+
+        for (&val, reg) in self.0.iter().zip(Register::ALL) {
+            write!(f, "{:#04x} {:<11} {:#04x} ", reg.addr(), reg.name(), val)?;
+            match reg {
+                Register::Control => writeln!(f, "{:?}", map.reg1())?,
+                Register::AttackTime => writeln!(f, "{:?}", map.attack_time())?,
+                Register::ReleaseTime => writeln!(f, "{:?}", map.release_time())?,
+                Register::HoldTime => writeln!(f, "{:?}", map.hold_time())?,
+                Register::FixedGain => writeln!(f, "{:?}", map.fixed_gain())?,
+                Register::Agc1 => writeln!(f, "{:?}", map.reg6())?,
+                Register::Agc2 => writeln!(f, "{:?}", map.reg7())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the device changes register `reg` on its own
+///
+/// Register 1 holds the fault flags, a cached copy of it is only as recent
+/// as the last read.
+pub const fn is_volatile(reg: Register) -> bool {
+    matches!(reg, Register::Control)
+}
+
 impl RegisterMap {
-    pub fn reg_as_byte(&self, idx: u8) -> u8 {
-        match idx {
-            1 => self.reg1.as_byte(),
-            2 => self.atk_time.as_byte(),
-            3 => self.rel_time.as_byte(),
-            4 => self.hold_time.as_byte(),
-            5 => self.fixedGain.as_byte(),
-            6 => self.reg6.as_byte(),
-            7 => self.reg7.as_byte(),
-            _ => 0,
-        }
-    }
-
-    pub fn update_map(&mut self, idx: u8, val: u8) {
-        match idx {
-            1 => self.reg1.update(val),
-            2 => self.atk_time.update(val),
-            3 => self.rel_time.update(val),
-            4 => self.hold_time.update(val),
-            5 => self.fixedGain.update(val),
-            6 => self.reg6.update(val),
-            7 => self.reg7.update(val),
-            _ => (),
+    pub fn reg_as_byte(&self, reg: Register) -> u8 {
+        self.regs[reg as usize - 1]
+    }
+
+    pub fn update_map(&mut self, reg: Register, val: u8) {
+        self.regs[reg as usize - 1] = val & writable_mask(reg) | reserved_set(reg);
+    }
+}
+
+/// Bits of register `reg` that read back as written, other bits are reserved
+pub(crate) const fn writable_mask(reg: Register) -> u8 {
+    match reg {
+        Register::Control => !(1 << 1),
+        Register::AttackTime | Register::ReleaseTime | Register::HoldTime | Register::FixedGain => {
+            0x3F
+        }
+        Register::Agc1 => 0xFF,
+        Register::Agc2 => 0xF3,
+    }
+}
+
+/// Fault flags of register 1, set by the device only
+pub(crate) const FAULT_BITS: u8 = 0b1_1100;
+
+/// Bits of register `reg` to compare when verifying a write
+///
+/// The fault flags of register 1 are set by the device and are not compared.
+pub const fn verify_mask(reg: Register) -> u8 {
+    match reg {
+        Register::Control => writable_mask(reg) & !FAULT_BITS,
+        _ => writable_mask(reg),
+    }
+}
+
+/// Reserved bits that are always set when encoding register `reg`
+const fn reserved_set(reg: Register) -> u8 {
+    match reg {
+        Register::Control => 1 << 1,
+        _ => 0,
+    }
+}
+
+/// Decoding and encoding `val` keeps the writable bits and the reserved bits
+/// have their fixed values
+#[cfg(any(test, kani))]
+fn check_round_trip(reg: Register, val: u8) {
+    let mut map = RegisterMap::default();
+    map.update_map(reg, val);
+    let encoded = map.reg_as_byte(reg);
+
+    assert_eq!(encoded & writable_mask(reg), val & writable_mask(reg));
+    assert_eq!(encoded & !writable_mask(reg), reserved_set(reg));
+}
+
+// Proof harnesses, run with `cargo kani`
+#[cfg(kani)]
+mod proofs {
+    use super::*;
+    use crate::agc::{fixed_gain_db, AgcConfig};
+    use crate::compression_ratio_from_bits;
+
+    #[kani::proof]
+    fn register_round_trip() {
+        let reg = Register::try_from(kani::any::<u8>());
+        kani::assume(reg.is_ok());
+        check_round_trip(reg.unwrap(), kani::any());
+    }
+
+    #[kani::proof]
+    #[kani::unwind(8)]
+    fn reserved_bits_from_fields() {
+        let mut map = RegisterMap::default();
+        map.set_spk_en_r(kani::any());
+        map.set_spk_en_l(kani::any());
+        map.set_sws(kani::any());
+        map.set_fault_r(kani::any());
+        map.set_fault_l(kani::any());
+        map.set_thermal(kani::any());
+        map.set_ng_en(kani::any());
+        map.set_attack_time(kani::any());
+        map.set_release_time(kani::any());
+        map.set_hold_time(kani::any());
+        map.set_fixed_gain(kani::any());
+        map.set_output_limiter_disable(kani::any());
+        map.set_noise_gate_threshold(U2::masked(kani::any()));
+        map.set_output_limiter_level(U5::masked(kani::any()));
+        map.set_max_gain(U4::masked(kani::any()));
+        map.set_compression_ratio(U2::masked(kani::any()));
+
+        for reg in Register::ALL {
+            assert_eq!(
+                map.reg_as_byte(reg) & !writable_mask(reg),
+                reserved_set(reg)
+            );
         }
     }
+
+    #[kani::proof]
+    fn validation_admits_only_datasheet_configs() {
+        let config = AgcConfig {
+            attack_time: kani::any(),
+            release_time: kani::any(),
+            hold_time: kani::any(),
+            fixed_gain: kani::any(),
+            max_gain: kani::any(),
+            output_limiter_level: kani::any(),
+            output_limiter: kani::any(),
+            compression_ratio: compression_ratio_from_bits(kani::any()),
+        };
+
+        assert_eq!(config.validate().is_ok(), config.validate_all().is_empty());
+
+        if config.validate().is_ok() {
+            assert!(config.attack_time <= 0x3F);
+            assert!(config.release_time <= 0x3F);
+            assert!(config.hold_time <= 0x3F);
+            assert!(config.max_gain <= 12);
+            assert!(config.output_limiter_level <= 0b1_1111);
+
+            let gain = fixed_gain_db(config.fixed_gain).unwrap();
+            assert!((-28..=30).contains(&gain));
+            if config.compression_ratio != crate::CompressionRatio::Ratio1 {
+                assert!(gain >= 0 && gain <= 18 + config.max_gain as i8);
+                assert!(config.output_limiter);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_round_trip() {
+        for reg in Register::ALL {
+            for val in 0..=u8::MAX {
+                check_round_trip(reg, val);
+            }
+        }
+    }
+
+    #[test]
+    fn packed() {
+        assert_eq!(core::mem::size_of::<RegisterMap>(), 7);
+
+        let mut map = RegisterMap::default();
+        map.set_output_limiter_level(U5::masked(0));
+        map.set_noise_gate_threshold(U2::masked(0b11));
+        map.set_max_gain(U4::masked(3));
+        assert_eq!(map.reg_as_byte(Register::Agc1), 0x60);
+        assert_eq!(map.reg_as_byte(Register::Agc2), 0x32);
+        assert!(map.ng_en());
+    }
+
+    #[test]
+    fn dump() {
+        extern crate std;
+        use std::string::ToString;
+
+        let dump = RegisterDump([0xC3, 0x05, 0x0B, 0x00, 0x06, 0x3A, 0xC2]).to_string();
+        let mut lines = dump.lines();
+        assert_eq!(lines.nth(1), Some("0x02 AttackTime  0x05 5"));
+        assert_eq!(
+            lines.last(),
+            Some("0x07 Agc2        0xc2 Register7 { max_gain: 12, compression_ratio: 2 }")
+        );
+    }
+
+    #[test]
+    fn register_addr() {
+        for reg in Register::ALL {
+            assert_eq!(Register::try_from(reg.addr()), Ok(reg));
+        }
+        assert_eq!(Register::try_from(0), Err(0));
+        assert_eq!(Register::try_from(8), Err(8));
+    }
+
+    #[test]
+    fn debug() {
+        extern crate std;
+        use std::format;
+
+        let map = RegisterMap::default();
+        assert_eq!(
+            format!("{:?}", map.reg6()),
+            "Register6 { output_limiter_disable: false, noise_gate_threshold: 1, output_limiter_level: 26 }"
+        );
+        assert!(format!("{:?}", map).starts_with("RegisterMap { reg1: Register1 { SPK_EN_R: true,"));
+        assert!(format!("{:?}", map).contains("atk_time: 5, rel_time: 11,"));
+    }
 }
@@ -0,0 +1,143 @@
+//! Driver shared between RTIC tasks
+//!
+//! Requires the `rtic` feature. The driver is an RTIC shared resource, each
+//! task wraps its resource proxy in the handle for what it does with the
+//! amplifier: [`FaultReader`] only reads the fault flags and status, keeping
+//! the critical sections of a high priority monitor short, [`Configurator`]
+//! changes the settings. Both work with any [`rtic_core::Mutex`].
+//!
+//! ```ignore
+//! #[shared]
+//! struct Shared {
+//!     amp: Tpa2016d2<I2c>,
+//! }
+//!
+//! #[task(shared = [amp])]
+//! fn fault_poll(cx: fault_poll::Context) {
+//!     let mut faults = FaultReader::new(cx.shared.amp);
+//!     if faults.get_faults().unwrap().any() {
+//!         // ...
+//!     }
+//! }
+//!
+//! #[idle(shared = [amp])]
+//! fn idle(cx: idle::Context) -> ! {
+//!     let mut config = Configurator::new(cx.shared.amp);
+//!     config.apply_config(&Config::DEFAULT).unwrap();
+//!     config.lock(|amp| amp.gain(12)).unwrap();
+//!     loop {}
+//! }
+//! ```
+//!
+//! [`Tpa2016d2`] is `Send` when the bus is, as RTIC requires of resources
+//! initialized in `init`.
+
+use rtic_core::Mutex;
+
+use crate::fault::{EventQueue, FaultMonitor};
+use crate::{Config, ConfigPatch, Error, Faults, RegisterInterface, Status, Tpa2016d2};
+
+/// The fault read path of a shared driver
+pub struct FaultReader<M> {
+    amp: M,
+}
+
+impl<M, I2C, E> FaultReader<M>
+where
+    M: Mutex<T = Tpa2016d2<I2C>>,
+    I2C: RegisterInterface<Error = E>,
+{
+    pub fn new(amp: M) -> Self {
+        FaultReader { amp }
+    }
+
+    /// Read the fault flags, see [`Tpa2016d2::get_faults`]
+    pub fn get_faults(&mut self) -> Result<Faults, Error<E>> {
+        self.amp.lock(|amp| amp.get_faults())
+    }
+
+    /// Read the status, see [`Tpa2016d2::read_status`]
+    pub fn read_status(&mut self) -> Result<Status, Error<E>> {
+        self.amp.lock(|amp| amp.read_status())
+    }
+
+    /// Read the fault flags and return the transitions seen by `monitor`
+    ///
+    /// The lock is released before the transitions are computed.
+    pub fn poll_events(&mut self, monitor: &mut FaultMonitor) -> Result<EventQueue, Error<E>> {
+        let faults = self.get_faults()?;
+        Ok(monitor.update_events(faults))
+    }
+}
+
+/// The configuration path of a shared driver
+pub struct Configurator<M> {
+    amp: M,
+}
+
+impl<M, I2C, E> Configurator<M>
+where
+    M: Mutex<T = Tpa2016d2<I2C>>,
+    I2C: RegisterInterface<Error = E>,
+{
+    pub fn new(amp: M) -> Self {
+        Configurator { amp }
+    }
+
+    /// Run `f` with exclusive access to the driver
+    pub fn lock<R>(&mut self, f: impl FnOnce(&mut Tpa2016d2<I2C>) -> R) -> R {
+        self.amp.lock(f)
+    }
+
+    /// See [`Tpa2016d2::apply_config`]
+    pub fn apply_config(&mut self, config: &Config) -> Result<(), Error<E>> {
+        self.amp.lock(|amp| amp.apply_config(config))
+    }
+
+    /// See [`Tpa2016d2::apply_patch`]
+    pub fn apply_patch(&mut self, patch: &ConfigPatch) -> Result<(), Error<E>> {
+        self.amp.lock(|amp| amp.apply_patch(patch))
+    }
+
+    /// The current configuration, without accessing the bus
+    pub fn config(&mut self) -> Config {
+        self.amp.lock(|amp| amp.config())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rtic_core::Exclusive;
+
+    use super::*;
+    use crate::fault::{Fault, FaultEvent};
+    use crate::mock::MockI2c;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn split_paths() {
+        assert_send::<Tpa2016d2<MockI2c>>();
+
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        let mut monitor = FaultMonitor::new();
+
+        Configurator::new(Exclusive(&mut amp))
+            .lock(|amp| amp.gain(12))
+            .unwrap();
+        amp.bus_mut().regs[1] |= 1 << 2;
+
+        let mut reader = FaultReader::new(Exclusive(&mut amp));
+        assert!(reader.get_faults().unwrap().thermal);
+        let mut events = reader.poll_events(&mut monitor).unwrap();
+        assert_eq!(events.next(), Some(FaultEvent::Raised(Fault::Thermal)));
+
+        assert_eq!(
+            Configurator::new(Exclusive(&mut amp))
+                .config()
+                .agc
+                .fixed_gain,
+            12
+        );
+    }
+}
@@ -0,0 +1,665 @@
+//! Protocol core without I/O
+//!
+//! [`Core`] holds the driver's view of the registers and turns state changes
+//! into the [`Command`]s to send to the device, and received register values
+//! back into state. It does no I/O itself, so async, DMA or simulation front
+//! ends only need to carry out the commands. [`Tpa2016d2`](crate::Tpa2016d2)
+//! is the blocking I2C front end, and the async one with the `async` feature.
+//!
+//! ```
+//! use tpa2016d2::regmap::Register;
+//! use tpa2016d2::sansio::{Command, Core};
+//!
+//! let mut core = Core::new();
+//! for command in core.gain(12) {
+//!     match command {
+//!         // Send [reg, value] to the device
+//!         Command::Write { reg, value } => assert_eq!((reg, value), (Register::FixedGain, 12)),
+//!         // Send [reg], read one byte and pass it to core.receive(reg, byte)
+//!         Command::Read { reg } => (),
+//!     }
+//! }
+//! ```
+
+use crate::agc::{self, AgcConfig};
+use crate::compression_ratio_from_bits;
+use crate::protection::ProtectionProfile;
+use crate::regmap::{self, Register, RegisterMap, U2, U4, U5};
+use crate::{
+    AgcPreset, Channel, ChannelState, CompressionRatio, Config, ConfigPatch, Faults,
+    NoiseGateThreshold, QuietReconfig, Status,
+};
+
+/// A bus transaction for the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Write `value` to register `reg`
+    Write { reg: Register, value: u8 },
+    /// Read register `reg`, the value is passed to [`Core::receive`]
+    Read { reg: Register },
+}
+
+// The longest sequence is a silenced bulk write of all registers
+const MAX_COMMANDS: usize = 8;
+
+/// The commands for one state change, in the order to send them
+#[derive(Clone, Debug)]
+pub struct Commands {
+    commands: [Command; MAX_COMMANDS],
+    len: usize,
+    pos: usize,
+}
+
+impl Commands {
+    fn new() -> Self {
+        Commands {
+            commands: [Command::Read {
+                reg: Register::Control,
+            }; MAX_COMMANDS],
+            len: 0,
+            pos: 0,
+        }
+    }
+
+    fn push(&mut self, command: Command) {
+        self.commands[self.len] = command;
+        self.len += 1;
+    }
+
+    /// The remaining commands
+    pub fn as_slice(&self) -> &[Command] {
+        &self.commands[self.pos..self.len]
+    }
+}
+
+impl Iterator for Commands {
+    type Item = Command;
+
+    fn next(&mut self) -> Option<Command> {
+        if self.pos == self.len {
+            return None;
+        }
+        self.pos += 1;
+        Some(self.commands[self.pos - 1])
+    }
+}
+
+/// Register state and protocol logic of the device
+pub struct Core {
+    pub(crate) regmap: RegisterMap,
+    gain_trim: i8,
+    quiet: QuietReconfig,
+    pub(crate) preset: Option<AgcPreset>,
+    // Channels turned off by the device, left and right
+    fault_disabled: [bool; 2],
+    // Speakers enabled before muting, left and right
+    muted: Option<[bool; 2]>,
+    protection: Option<ProtectionProfile>,
+}
+
+impl Default for Core {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Core {
+    /// Creates a core assuming the device has its power-on register values
+    pub fn new() -> Self {
+        Core {
+            regmap: RegisterMap::default(),
+            gain_trim: 0,
+            quiet: QuietReconfig::Off,
+            preset: None,
+            fault_disabled: [false; 2],
+            muted: None,
+            protection: None,
+        }
+    }
+
+    /// Update the state from a register value read from the device
+    ///
+    /// A channel found disabled with a fault flag set is marked as disabled by
+    /// the fault, see [`Core::channel_state`].
+    pub fn receive(&mut self, reg: Register, value: u8) {
+        let enabled = self.speakers();
+        self.regmap.update_map(reg, value);
+        if reg != Register::Control {
+            return;
+        }
+
+        let now = self.speakers();
+        let faults = self.faults();
+        let flagged = [faults.fault_l, faults.fault_r];
+        for ch in 0..2 {
+            if now[ch] {
+                self.fault_disabled[ch] = false;
+            } else if enabled[ch] && (flagged[ch] || faults.thermal) {
+                self.fault_disabled[ch] = true;
+            }
+        }
+    }
+
+    /// The state of `channel`
+    ///
+    /// Channels turned off by the device are detected when register 1 is read,
+    /// e.g. while polling the faults.
+    pub fn channel_state(&self, channel: Channel) -> ChannelState {
+        let ch = channel as usize;
+        if self.speakers()[ch] {
+            ChannelState::Enabled
+        } else if self.fault_disabled[ch] {
+            ChannelState::DisabledByFault
+        } else {
+            ChannelState::DisabledByUser
+        }
+    }
+
+    fn speakers(&self) -> [bool; 2] {
+        [self.regmap.spk_en_l(), self.regmap.spk_en_r()]
+    }
+
+    /// The decoded registers
+    pub fn registers(&self) -> &RegisterMap {
+        &self.regmap
+    }
+
+    /// The assumed value of register `reg`
+    pub fn register(&self, reg: Register) -> u8 {
+        self.regmap.reg_as_byte(reg)
+    }
+
+    /// The device status, see [`Status`]
+    pub fn status(&self) -> Status {
+        Status {
+            speaker_left: self.regmap.spk_en_l(),
+            speaker_right: self.regmap.spk_en_r(),
+            shutdown: self.regmap.sws(),
+            faults: self.faults(),
+            fixed_gain_db: agc::fixed_gain_to_db(self.regmap.fixed_gain()),
+            preset: self.preset,
+        }
+    }
+
+    /// The fault flags as last read
+    ///
+    /// Register 1 is volatile, the flags are potentially stale. Use
+    /// [`Core::read_volatile`] to refresh them.
+    pub fn faults(&self) -> Faults {
+        Faults {
+            fault_r: self.regmap.fault_r(),
+            fault_l: self.regmap.fault_l(),
+            thermal: self.regmap.thermal(),
+        }
+    }
+
+    /// The complete configuration
+    pub fn config(&self) -> Config {
+        let mut regs = [0; 7];
+        for (val, reg) in regs.iter_mut().zip(Register::ALL) {
+            *val = self.register(reg);
+        }
+        Config::from_registers(&regs)
+    }
+
+    /// The AGC settings, as register values
+    pub fn agc_config(&self) -> AgcConfig {
+        AgcConfig {
+            attack_time: self.regmap.attack_time(),
+            release_time: self.regmap.release_time(),
+            hold_time: self.regmap.hold_time(),
+            fixed_gain: self.regmap.fixed_gain(),
+            max_gain: self.regmap.max_gain().value(),
+            output_limiter_level: self.regmap.output_limiter_level().value(),
+            output_limiter: !self.regmap.output_limiter_disable(),
+            compression_ratio: compression_ratio_from_bits(self.regmap.compression_ratio().value()),
+        }
+    }
+
+    /// Read all registers
+    pub fn sync(&self) -> Commands {
+        let mut commands = Commands::new();
+        for reg in Register::ALL {
+            commands.push(Command::Read { reg });
+        }
+        commands
+    }
+
+    /// Read the fault flags, see [`Core::faults`]
+    pub fn read_faults(&self) -> Commands {
+        self.read_volatile()
+    }
+
+    /// Read the registers the device changes on its own
+    pub fn read_volatile(&self) -> Commands {
+        let mut commands = Commands::new();
+        for reg in Register::ALL
+            .iter()
+            .copied()
+            .filter(|&reg| regmap::is_volatile(reg))
+        {
+            commands.push(Command::Read { reg });
+        }
+        commands
+    }
+
+    /// Returns true if register `reg` is changed by the device, its value from
+    /// [`Core::register`] is potentially stale
+    pub fn is_volatile(&self, reg: Register) -> bool {
+        regmap::is_volatile(reg)
+    }
+
+    /// Enable or disable speakers
+    pub fn speaker_enable(&mut self, le: bool, re: bool) -> Commands {
+        self.regmap.set_spk_en_l(le);
+        self.regmap.set_spk_en_r(re);
+        self.fault_disabled = [false; 2];
+        self.muted = None;
+        self.write(Register::Control)
+    }
+
+    /// Turn off both speakers, remembering which were enabled
+    ///
+    /// The other settings, including the fixed gain, are not changed.
+    pub fn mute(&mut self) -> Commands {
+        if self.muted.is_none() {
+            self.muted = Some(self.speakers());
+        }
+        self.regmap.set_spk_en_l(false);
+        self.regmap.set_spk_en_r(false);
+        self.write(Register::Control)
+    }
+
+    /// Re-enable the speakers enabled before [`Core::mute`]
+    ///
+    /// Does nothing if not muted, or if the speakers were changed since.
+    pub fn unmute(&mut self) -> Commands {
+        match self.muted.take() {
+            Some([left, right]) => {
+                self.regmap.set_spk_en_l(left);
+                self.regmap.set_spk_en_r(right);
+                self.write(Register::Control)
+            }
+            None => Commands::new(),
+        }
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.is_some()
+    }
+
+    /// Shutdown the device
+    pub fn disable_device(&mut self) -> Commands {
+        self.set_software_shutdown(true)
+    }
+
+    /// Wake the device from software shutdown
+    pub fn enable_device(&mut self) -> Commands {
+        self.set_software_shutdown(false)
+    }
+
+    /// Set or clear the software shutdown bit
+    pub fn set_software_shutdown(&mut self, shutdown: bool) -> Commands {
+        self.regmap.set_sws(shutdown);
+        self.write(Register::Control)
+    }
+
+    /// Enable or disable the noise gate
+    pub fn set_noise_gate(&mut self, enable: bool) -> Commands {
+        self.regmap.set_ng_en(enable);
+        self.write(Register::Control)
+    }
+
+    pub fn set_attack_time(&mut self, val: u8) -> Commands {
+        self.regmap.set_attack_time(val);
+        self.write(Register::AttackTime)
+    }
+
+    pub fn set_release_time(&mut self, val: u8) -> Commands {
+        self.regmap.set_release_time(val);
+        self.write(Register::ReleaseTime)
+    }
+
+    pub fn set_hold_time(&mut self, val: u8) -> Commands {
+        self.regmap.set_hold_time(val);
+        self.write(Register::HoldTime)
+    }
+
+    /// Set the gain, the gain trim is added
+    pub fn gain(&mut self, gain: u8) -> Commands {
+        let gain = self.trimmed_gain(gain);
+        self.gain_raw(gain)
+    }
+
+    /// Set the gain without applying the trim
+    pub fn gain_raw(&mut self, gain: u8) -> Commands {
+        self.regmap.set_fixed_gain(gain);
+        self.write(Register::FixedGain)
+    }
+
+    /// Set the calibration offset in 0.5 dB added to the fixed gain
+    pub fn set_gain_trim(&mut self, half_db: i8) {
+        self.gain_trim = half_db;
+    }
+
+    /// The calibration offset in 0.5 dB
+    pub fn gain_trim(&self) -> i8 {
+        self.gain_trim
+    }
+
+    /// Cap the settings of all following writes, see [`ProtectionProfile`]
+    pub fn set_protection(&mut self, profile: Option<ProtectionProfile>) {
+        self.protection = profile;
+    }
+
+    /// The installed protection profile
+    pub fn protection(&self) -> Option<ProtectionProfile> {
+        self.protection
+    }
+
+    /// Cap the settings of register `reg` to the protection profile
+    pub(crate) fn protect(&mut self, reg: Register) {
+        if let Some(profile) = self.protection {
+            profile.apply(&mut self.regmap, reg);
+        }
+    }
+
+    pub fn noise_gate_threshold(&mut self, val: NoiseGateThreshold) -> Commands {
+        self.regmap.set_noise_gate_threshold(U2::masked(val as u8));
+        self.write(Register::Agc1)
+    }
+
+    pub fn output_limiter_level(&mut self, val: u8) -> Commands {
+        self.regmap.set_output_limiter_level(U5::masked(val));
+        self.write(Register::Agc1)
+    }
+
+    pub fn compression_ratio(&mut self, ratio: CompressionRatio) -> Commands {
+        self.regmap.set_compression_ratio(U2::masked(ratio as u8));
+        self.write(Register::Agc2)
+    }
+
+    /// Enable or disable the output limiter
+    pub fn output_limiter(&mut self, enable: bool) -> Commands {
+        self.regmap.set_output_limiter_disable(!enable);
+        self.write(Register::Agc1)
+    }
+
+    /// Set the max gain, 18 dB + `val`
+    pub fn set_max_gain(&mut self, val: u8) -> Commands {
+        self.regmap.set_max_gain(U4::masked(val));
+        self.write(Register::Agc2)
+    }
+
+    pub fn set_agc_preset(&mut self, preset: AgcPreset) -> Commands {
+        let (cr, atk, rel_time, hold_time, fixed_gain, limiter_level) = preset.register_values();
+
+        self.regmap.set_attack_time(atk);
+        self.regmap.set_release_time(rel_time);
+        self.regmap.set_hold_time(hold_time);
+        self.regmap.set_fixed_gain(self.trimmed_gain(fixed_gain));
+        self.regmap
+            .set_output_limiter_level(U5::masked(limiter_level));
+        self.regmap.set_compression_ratio(U2::masked(cr as u8));
+        // All presets compress, which needs the limiter
+        self.regmap.set_output_limiter_disable(false);
+
+        let commands = self.bulk(false);
+        self.preset = Some(preset);
+        commands
+    }
+
+    /// Write a complete AGC configuration, registers 2 to 7
+    pub fn set_agc_config(&mut self, config: &AgcConfig) -> Commands {
+        self.set_agc_fields(config);
+        self.bulk(false)
+    }
+
+    /// Write a complete configuration, registers 1 to 7
+    ///
+    /// The software shutdown bit is left as is.
+    pub fn apply_config(&mut self, config: &Config) -> Commands {
+        self.regmap.set_spk_en_l(config.speaker_left);
+        self.regmap.set_spk_en_r(config.speaker_right);
+        self.fault_disabled = [false; 2];
+        self.muted = None;
+        self.regmap.set_ng_en(config.noise_gate);
+        self.regmap
+            .set_noise_gate_threshold(U2::masked(config.noise_gate_threshold as u8));
+        self.set_agc_fields(&config.agc);
+        self.bulk(true)
+    }
+
+    /// Silence the outputs while presets and configurations are written
+    pub fn set_quiet_reconfig(&mut self, mode: QuietReconfig) {
+        self.quiet = mode;
+    }
+
+    pub fn quiet_reconfig(&self) -> QuietReconfig {
+        self.quiet
+    }
+
+    /// Change only the settings present in `patch`
+    ///
+    /// Only the registers holding the changed settings are written.
+    pub fn apply_patch(&mut self, patch: &ConfigPatch) -> Commands {
+        // Bit n set when register n needs to be written
+        let mut dirty = 0u8;
+
+        if let Some(on) = patch.speaker_left {
+            self.regmap.set_spk_en_l(on);
+            self.fault_disabled[Channel::Left as usize] = false;
+            self.muted = None;
+            dirty |= Register::Control.bit();
+        }
+        if let Some(on) = patch.speaker_right {
+            self.regmap.set_spk_en_r(on);
+            self.fault_disabled[Channel::Right as usize] = false;
+            self.muted = None;
+            dirty |= Register::Control.bit();
+        }
+        if let Some(on) = patch.noise_gate {
+            self.regmap.set_ng_en(on);
+            dirty |= Register::Control.bit();
+        }
+        if let Some(val) = patch.attack_time {
+            self.regmap.set_attack_time(val);
+            dirty |= Register::AttackTime.bit();
+        }
+        if let Some(val) = patch.release_time {
+            self.regmap.set_release_time(val);
+            dirty |= Register::ReleaseTime.bit();
+        }
+        if let Some(val) = patch.hold_time {
+            self.regmap.set_hold_time(val);
+            dirty |= Register::HoldTime.bit();
+        }
+        if let Some(val) = patch.fixed_gain {
+            self.regmap.set_fixed_gain(self.trimmed_gain(val));
+            dirty |= Register::FixedGain.bit();
+        }
+        if let Some(val) = patch.noise_gate_threshold {
+            self.regmap.set_noise_gate_threshold(U2::masked(val as u8));
+            dirty |= Register::Agc1.bit();
+        }
+        if let Some(val) = patch.output_limiter_level {
+            self.regmap.set_output_limiter_level(U5::masked(val));
+            dirty |= Register::Agc1.bit();
+        }
+        if let Some(on) = patch.output_limiter {
+            self.regmap.set_output_limiter_disable(!on);
+            dirty |= Register::Agc1.bit();
+        }
+        if let Some(val) = patch.max_gain {
+            self.regmap.set_max_gain(U4::masked(val));
+            dirty |= Register::Agc2.bit();
+        }
+        if let Some(val) = patch.compression_ratio {
+            self.regmap.set_compression_ratio(U2::masked(val as u8));
+            dirty |= Register::Agc2.bit();
+        }
+
+        self.write_registers(dirty)
+    }
+
+    /// Write the registers set in the bit mask `regs`, bit n for register n
+    pub(crate) fn write_registers(&mut self, regs: u8) -> Commands {
+        let mut commands = Commands::new();
+        for reg in self.write_order() {
+            if regs & reg.bit() != 0 {
+                commands.push(self.write_command(reg));
+            }
+        }
+        commands
+    }
+
+    /// Registers 1 to 7 in the order they are written
+    ///
+    /// The output limiter is only disabled at a compression ratio of 1:1, so
+    /// with the limiter disabled register 7 is written before register 6.
+    fn write_order(&self) -> [Register; 7] {
+        let mut order = Register::ALL;
+        if self.regmap.output_limiter_disable() {
+            order.swap(5, 6);
+        }
+        order
+    }
+
+    /// Write register `reg` from the state
+    pub(crate) fn write(&mut self, reg: Register) -> Commands {
+        let mut commands = Commands::new();
+        commands.push(self.write_command(reg));
+        commands
+    }
+
+    /// Apply the gain trim to a fixed gain register value
+    pub(crate) fn trimmed_gain(&self, gain: u8) -> u8 {
+        if self.gain_trim == 0 {
+            return gain;
+        }
+        // Sign extend the 6 bit value
+        let nominal = ((gain << 2) as i8 >> 2) as i16;
+        let half_db = nominal * 2 + self.gain_trim as i16;
+        agc::fixed_gain_code((half_db + 1).div_euclid(2).clamp(-28, 30) as i8)
+    }
+
+    fn set_agc_fields(&mut self, config: &AgcConfig) {
+        self.regmap.set_attack_time(config.attack_time);
+        self.regmap.set_release_time(config.release_time);
+        self.regmap.set_hold_time(config.hold_time);
+        self.regmap
+            .set_fixed_gain(self.trimmed_gain(config.fixed_gain));
+        self.regmap
+            .set_output_limiter_level(U5::masked(config.output_limiter_level));
+        self.regmap.set_max_gain(U4::masked(config.max_gain));
+        self.regmap
+            .set_compression_ratio(U2::masked(config.compression_ratio as u8));
+        self.regmap
+            .set_output_limiter_disable(!config.output_limiter);
+    }
+
+    /// Write registers 2 to 7, and register 1 if `reg1` is set
+    fn bulk(&mut self, reg1: bool) -> Commands {
+        let current = self.regmap.reg_as_byte(Register::Control);
+        let silenced = match self.quiet {
+            QuietReconfig::Off => None,
+            QuietReconfig::Mute => Some(current & !(1 << 7 | 1 << 6)),
+            QuietReconfig::Shutdown => Some(current | 1 << 5),
+        };
+
+        let mut commands = Commands::new();
+        if let Some(value) = silenced {
+            commands.push(Command::Write {
+                reg: Register::Control,
+                value,
+            });
+        }
+        for &reg in &self.write_order()[1..] {
+            commands.push(self.write_command(reg));
+        }
+        if reg1 || silenced.is_some() {
+            commands.push(self.write_command(Register::Control));
+        }
+        commands
+    }
+
+    fn write_command(&mut self, reg: Register) -> Command {
+        if reg != Register::Control {
+            // The AGC settings no longer match a preset
+            self.preset = None;
+        }
+        self.protect(reg);
+        Command::Write {
+            reg,
+            value: self.regmap.reg_as_byte(reg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        let mut core = Core::new();
+
+        assert_eq!(
+            core.speaker_enable(false, true).as_slice(),
+            [Command::Write {
+                reg: Register::Control,
+                value: 0x83
+            }]
+        );
+        assert_eq!(core.sync().count(), 7);
+
+        core.receive(Register::Control, 0x83 | 1 << 2);
+        assert!(core.faults().thermal);
+
+        core.set_quiet_reconfig(QuietReconfig::Mute);
+        let commands = core.set_agc_preset(AgcPreset::Jazz);
+        assert_eq!(commands.len, 8);
+        assert_eq!(
+            commands.as_slice()[0],
+            Command::Write {
+                reg: Register::Control,
+                value: 0x07
+            }
+        );
+        assert_eq!(core.status().preset, Some(AgcPreset::Jazz));
+    }
+
+    #[test]
+    fn channel_state() {
+        let mut core = Core::new();
+
+        // Left turned off by the device with a short circuit
+        core.receive(Register::Control, 0x83 | 1 << 3);
+        assert_eq!(
+            core.channel_state(Channel::Left),
+            ChannelState::DisabledByFault
+        );
+        assert_eq!(core.channel_state(Channel::Right), ChannelState::Enabled);
+
+        // Still off by the fault after the flag is cleared
+        core.receive(Register::Control, 0x83);
+        assert_eq!(
+            core.channel_state(Channel::Left),
+            ChannelState::DisabledByFault
+        );
+
+        core.speaker_enable(true, false);
+        assert_eq!(core.channel_state(Channel::Left), ChannelState::Enabled);
+        assert_eq!(
+            core.channel_state(Channel::Right),
+            ChannelState::DisabledByUser
+        );
+
+        // Without a fault flag the device did not turn it off
+        core.receive(Register::Control, 0x03);
+        assert_eq!(
+            core.channel_state(Channel::Left),
+            ChannelState::DisabledByUser
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! Scheduled configuration changes
+//!
+//! Changes are queued with a due time and applied by calling
+//! [`Scheduler::poll`] periodically, e.g. from the main loop.
+
+use crate::{Clock, ConfigChange, Tpa2016Control};
+
+#[derive(Clone, Copy)]
+struct Entry {
+    due: u32,
+    change: ConfigChange,
+}
+
+/// Queue of up to `N` configuration changes waiting to be applied
+pub struct Scheduler<const N: usize> {
+    entries: [Option<Entry>; N],
+    len: usize,
+}
+
+impl<const N: usize> Default for Scheduler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Scheduler<N> {
+    /// Creates an empty scheduler
+    pub const fn new() -> Self {
+        Scheduler {
+            entries: [None; N],
+            len: 0,
+        }
+    }
+
+    /// Schedule `change` to be applied at time `at_ms`
+    ///
+    /// Changes due at the same time are applied in the order they were scheduled.
+    /// Returns the change back if the queue is full.
+    pub fn schedule_at(&mut self, at_ms: u32, change: ConfigChange) -> Result<(), ConfigChange> {
+        if self.len == N {
+            return Err(change);
+        }
+
+        // Keep the queue sorted on due time
+        let mut pos = self.len;
+        while pos > 0 && is_before(at_ms, self.entry(pos - 1).due) {
+            self.entries[pos] = self.entries[pos - 1];
+            pos -= 1;
+        }
+
+        self.entries[pos] = Some(Entry { due: at_ms, change });
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Schedule `change` to be applied `delay_ms` from now
+    pub fn schedule_in<C: Clock>(
+        &mut self,
+        clock: &mut C,
+        delay_ms: u32,
+        change: ConfigChange,
+    ) -> Result<(), ConfigChange> {
+        let at = clock.now_ms().wrapping_add(delay_ms);
+        self.schedule_at(at, change)
+    }
+
+    /// Number of changes waiting to be applied
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+
+    /// Drop all pending changes
+    pub fn clear(&mut self) {
+        self.entries = [None; N];
+        self.len = 0;
+    }
+
+    /// Apply all changes that are due
+    ///
+    /// Returns the number of changes applied. If writing a change fails it
+    /// stays in the queue and is retried on the next poll.
+    pub fn poll<A, C>(&mut self, amp: &mut A, clock: &mut C) -> Result<usize, A::Error>
+    where
+        A: Tpa2016Control,
+        C: Clock,
+    {
+        let now = clock.now_ms();
+        let mut applied = 0;
+
+        while self.len > 0 && !is_before(now, self.entry(0).due) {
+            amp.apply_change(self.entry(0).change)?;
+            self.remove_first();
+            applied += 1;
+        }
+
+        Ok(applied)
+    }
+
+    fn entry(&self, idx: usize) -> Entry {
+        self.entries[idx].unwrap()
+    }
+
+    fn remove_first(&mut self) {
+        for i in 1..self.len {
+            self.entries[i - 1] = self.entries[i];
+        }
+        self.len -= 1;
+        self.entries[self.len] = None;
+    }
+}
+
+/// Wrap-around safe comparison of two timestamps
+fn is_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+#[cfg(all(test, feature = "hal"))]
+mod tests {
+    use super::*;
+    use crate::mock::{MockI2c, TestClock};
+    use crate::Tpa2016d2;
+
+    #[test]
+    fn applies_due_changes_in_order() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        let mut clock = TestClock(100);
+        let mut sched: Scheduler<4> = Scheduler::new();
+
+        sched
+            .schedule_in(&mut clock, 50, ConfigChange::Gain(20))
+            .unwrap();
+        sched.schedule_at(120, ConfigChange::Gain(10)).unwrap();
+        sched
+            .schedule_at(120, ConfigChange::NoiseGate(false))
+            .unwrap();
+
+        assert_eq!(sched.poll(&mut amp, &mut clock), Ok(0));
+
+        clock.0 = 130;
+        assert_eq!(sched.poll(&mut amp, &mut clock), Ok(2));
+        assert_eq!(sched.pending(), 1);
+
+        clock.0 = 150;
+        assert_eq!(sched.poll(&mut amp, &mut clock), Ok(1));
+
+        let i2c = amp.release();
+        assert_eq!(i2c.writes, [(5, 10), (1, 0xC2), (5, 20)]);
+    }
+
+    #[test]
+    fn queue_full() {
+        let mut sched: Scheduler<1> = Scheduler::new();
+        assert!(sched.schedule_at(0, ConfigChange::Gain(1)).is_ok());
+        assert_eq!(
+            sched.schedule_at(0, ConfigChange::Gain(2)),
+            Err(ConfigChange::Gain(2))
+        );
+    }
+
+    #[test]
+    fn wrapping_time() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        let mut clock = TestClock(u32::MAX - 10);
+        let mut sched: Scheduler<2> = Scheduler::new();
+
+        sched
+            .schedule_in(&mut clock, 20, ConfigChange::Gain(1))
+            .unwrap();
+        assert_eq!(sched.poll(&mut amp, &mut clock), Ok(0));
+
+        clock.0 = 10;
+        assert_eq!(sched.poll(&mut amp, &mut clock), Ok(1));
+    }
+}
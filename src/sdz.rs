@@ -0,0 +1,157 @@
+//! Hardware shutdown pin
+//!
+//! [`SdzTpa2016d2`] owns the GPIO connected to the SDZ pin of the device along
+//! with the driver, and sequences it with the software shutdown bit. All other
+//! methods of [`Tpa2016d2`] are available through `Deref`.
+//!
+//! ```
+//! # use embedded_hal::blocking::delay::DelayMs;
+//! # use embedded_hal::digital::v2::OutputPin;
+//! use tpa2016d2::Tpa2016d2;
+//!
+//! # fn run<I2C, E, P, D>(i2c: I2C, sdz: P, delay: &mut D) -> Result<(), tpa2016d2::Error<E>>
+//! # where
+//! #     I2C: tpa2016d2::RegisterInterface<Error = E>,
+//! #     P: OutputPin,
+//! #     D: DelayMs<u32>,
+//! # {
+//! let mut amp = Tpa2016d2::new_with_sdz(i2c, sdz);
+//! amp.enable_device(delay)?;
+//! amp.gain(12)?;
+//! amp.disable_device()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use core::ops::{Deref, DerefMut};
+
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::{timings, Error, RegisterInterface, Tpa2016d2};
+
+/// A [`Tpa2016d2`] together with its hardware shutdown pin
+pub struct SdzTpa2016d2<I2C, P> {
+    amp: Tpa2016d2<I2C>,
+    sdz: P,
+}
+
+impl<I2C> Tpa2016d2<I2C> {
+    /// Creates a driver controlling the hardware shutdown pin `sdz`, see [`sdz`](crate::sdz)
+    ///
+    /// The device is assumed to be held in shutdown, does not access the bus.
+    pub fn new_with_sdz<P: OutputPin>(i2c: I2C, sdz: P) -> SdzTpa2016d2<I2C, P> {
+        SdzTpa2016d2 {
+            amp: Tpa2016d2::new(i2c),
+            sdz,
+        }
+    }
+}
+
+impl<I2C, E, P> SdzTpa2016d2<I2C, P>
+where
+    I2C: RegisterInterface<Error = E>,
+    P: OutputPin,
+{
+    /// Release the SDZ pin and wake the device from software shutdown
+    ///
+    /// Waits [`timings::POWER_UP_MS`] for the device to start, then writes all
+    /// registers from the driver's view, since the device starts with its
+    /// power-on values, with the software shutdown bit cleared last.
+    pub fn enable_device<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<(), Error<E>> {
+        self.sdz.set_high().map_err(|_| Error::Pin)?;
+        delay.delay_ms(timings::POWER_UP_MS);
+        self.amp.core.set_software_shutdown(false);
+        self.amp.rewrite_registers()
+    }
+
+    /// Enter software shutdown, then pull the SDZ pin low
+    ///
+    /// The pin is pulled low even if the software shutdown fails.
+    pub fn disable_device(&mut self) -> Result<(), Error<E>> {
+        let result = self.amp.disable_device();
+        self.sdz.set_low().map_err(|_| Error::Pin)?;
+        result
+    }
+}
+
+impl<I2C, P> SdzTpa2016d2<I2C, P> {
+    /// Release the i2c device and the SDZ pin
+    pub fn release(self) -> (I2C, P) {
+        (self.amp.release(), self.sdz)
+    }
+}
+
+impl<I2C, P> Deref for SdzTpa2016d2<I2C, P> {
+    type Target = Tpa2016d2<I2C>;
+
+    fn deref(&self) -> &Tpa2016d2<I2C> {
+        &self.amp
+    }
+}
+
+impl<I2C, P> DerefMut for SdzTpa2016d2<I2C, P> {
+    fn deref_mut(&mut self) -> &mut Tpa2016d2<I2C> {
+        &mut self.amp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use super::*;
+    use crate::mock::MockI2c;
+
+    struct Pin(bool);
+
+    impl OutputPin for Pin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0 = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0 = true;
+            Ok(())
+        }
+    }
+
+    struct Delay(u32);
+
+    impl DelayMs<u32> for Delay {
+        fn delay_ms(&mut self, ms: u32) {
+            self.0 += ms;
+        }
+    }
+
+    #[test]
+    fn sequencing() {
+        let mut amp = Tpa2016d2::new_with_sdz(MockI2c::new(), Pin(false));
+        let mut delay = Delay(0);
+
+        amp.gain(12).unwrap();
+        amp.enable_device(&mut delay).unwrap();
+        assert_eq!(delay.0, timings::POWER_UP_MS);
+        amp.disable_device().unwrap();
+
+        let (i2c, pin) = amp.release();
+        assert!(!pin.0);
+        assert_eq!(
+            i2c.writes,
+            [
+                (5, 12),
+                (2, 0x05),
+                (3, 0x0B),
+                (4, 0x00),
+                (5, 12),
+                (6, 0x3A),
+                (7, 0xC2),
+                (1, 0xC3),
+                (1, 0xE3)
+            ]
+        );
+    }
+}
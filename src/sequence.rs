@@ -0,0 +1,180 @@
+//! Configuration sequences described as data
+//!
+//! A sequence is a slice of [`Step`]s, so power-up or recovery sequences can
+//! be stored in a `static`:
+//!
+//! ```
+//! use tpa2016d2::{sequence::Step, timings, ConfigChange};
+//!
+//! static POWER_UP: &[Step] = &[
+//!     Step::WaitMs(timings::POWER_UP_MS),
+//!     Step::Write(ConfigChange::SpeakerEnable { left: false, right: false }),
+//!     Step::Write(ConfigChange::Gain(6)),
+//!     Step::WaitFaultClear,
+//!     Step::Write(ConfigChange::SpeakerEnable { left: true, right: true }),
+//! ];
+//! ```
+
+#[cfg(feature = "hal")]
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::ConfigChange;
+#[cfg(feature = "hal")]
+use crate::Tpa2016Control;
+
+/// One step of a sequence
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Apply a configuration change
+    Write(ConfigChange),
+    /// Wait a number of milliseconds
+    WaitMs(u32),
+    /// Wait until no fault is reported by the device
+    WaitFaultClear,
+}
+
+/// Sequence errors
+#[derive(Debug, PartialEq, Eq)]
+pub enum SequenceError<E> {
+    /// Bus error
+    I2c(E),
+    /// The faults did not clear in time, `step` is the index of the step
+    FaultTimeout { step: usize },
+}
+
+/// Executor for a sequence of steps
+pub struct Sequence<'a> {
+    steps: &'a [Step],
+    fault_timeout_ms: u32,
+    fault_poll_ms: u32,
+}
+
+impl<'a> Sequence<'a> {
+    /// Creates a new sequence, waiting at most 1 s for faults to clear
+    pub const fn new(steps: &'a [Step]) -> Self {
+        Sequence {
+            steps,
+            fault_timeout_ms: 1000,
+            fault_poll_ms: 10,
+        }
+    }
+
+    /// Set the maximum time to wait in a [`Step::WaitFaultClear`] step
+    pub const fn fault_timeout_ms(mut self, ms: u32) -> Self {
+        self.fault_timeout_ms = ms;
+        self
+    }
+
+    /// Set the interval the faults are polled with in a [`Step::WaitFaultClear`] step
+    ///
+    /// An interval of 0 is taken as 1 ms, so the timeout is still reached.
+    pub const fn fault_poll_ms(mut self, ms: u32) -> Self {
+        self.fault_poll_ms = if ms == 0 { 1 } else { ms };
+        self
+    }
+
+    /// The steps of this sequence
+    pub fn steps(&self) -> &'a [Step] {
+        self.steps
+    }
+
+    /// Run all steps, blocking until done
+    #[cfg(feature = "hal")]
+    pub fn run<A, D>(&self, amp: &mut A, delay: &mut D) -> Result<(), SequenceError<A::Error>>
+    where
+        A: Tpa2016Control,
+        D: DelayMs<u32>,
+    {
+        for (idx, step) in self.steps.iter().enumerate() {
+            match *step {
+                Step::Write(change) => amp.apply_change(change).map_err(SequenceError::I2c)?,
+                Step::WaitMs(ms) => delay.delay_ms(ms),
+                Step::WaitFaultClear => self.wait_fault_clear(amp, delay, idx)?,
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "hal")]
+    fn wait_fault_clear<A, D>(
+        &self,
+        amp: &mut A,
+        delay: &mut D,
+        step: usize,
+    ) -> Result<(), SequenceError<A::Error>>
+    where
+        A: Tpa2016Control,
+        D: DelayMs<u32>,
+    {
+        let mut waited = 0;
+        loop {
+            if !amp.get_faults().map_err(SequenceError::I2c)?.any() {
+                return Ok(());
+            }
+            if waited >= self.fault_timeout_ms {
+                return Err(SequenceError::FaultTimeout { step });
+            }
+            delay.delay_ms(self.fault_poll_ms);
+            waited = waited.saturating_add(self.fault_poll_ms);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "hal"))]
+mod tests {
+    use super::*;
+    use crate::mock::{MockI2c, TestDelay};
+    use crate::Tpa2016d2;
+
+    #[test]
+    fn run_steps() {
+        let steps = [
+            Step::Write(ConfigChange::Gain(12)),
+            Step::WaitMs(5),
+            Step::WaitFaultClear,
+            Step::Write(ConfigChange::NoiseGate(false)),
+        ];
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        let mut delay = TestDelay(0);
+
+        Sequence::new(&steps).run(&mut amp, &mut delay).unwrap();
+
+        assert_eq!(delay.0, 5);
+        assert_eq!(amp.release().writes, [(5, 12), (1, 0xC2)]);
+    }
+
+    #[test]
+    fn fault_timeout() {
+        let steps = [Step::WaitFaultClear, Step::Write(ConfigChange::Gain(12))];
+        let mut i2c = MockI2c::new();
+        // Thermal fault
+        i2c.regs[1] |= 1 << 2;
+        let mut amp = Tpa2016d2::new(i2c);
+        let mut delay = TestDelay(0);
+
+        let res = Sequence::new(&steps)
+            .fault_timeout_ms(50)
+            .run(&mut amp, &mut delay);
+
+        assert_eq!(res, Err(SequenceError::FaultTimeout { step: 0 }));
+        assert_eq!(delay.0, 50);
+        assert!(amp.release().writes.is_empty());
+    }
+
+    #[test]
+    fn fault_poll_zero() {
+        let steps = [Step::WaitFaultClear];
+        let mut i2c = MockI2c::new();
+        i2c.regs[1] |= 1 << 2;
+        let mut amp = Tpa2016d2::new(i2c);
+        let mut delay = TestDelay(0);
+
+        let res = Sequence::new(&steps)
+            .fault_timeout_ms(3)
+            .fault_poll_ms(0)
+            .run(&mut amp, &mut delay);
+
+        assert_eq!(res, Err(SequenceError::FaultTimeout { step: 0 }));
+        assert_eq!(delay.0, 3);
+    }
+}
@@ -0,0 +1,117 @@
+//! serde support
+//!
+//! Requires the `serde` feature. [`Config`], [`AgcConfig`](crate::AgcConfig),
+//! [`ConfigPatch`](crate::ConfigPatch) and the setting enums implement
+//! `Serialize` and `Deserialize`. [`RawConfig`] serializes a [`Config`] as the
+//! contents of the seven registers, so saved files can be compared bit for bit
+//! with logic analyzer captures and the tables in the datasheet.
+//!
+//! ```
+//! # use tpa2016d2::{serde::RawConfig, Config};
+//! let json = serde_json::to_string(&RawConfig(Config::DEFAULT)).unwrap();
+//! assert_eq!(
+//!     json,
+//!     r#"{"control":195,"attack_time":5,"release_time":11,"hold_time":0,"fixed_gain":6,"agc_control_1":58,"agc_control_2":194}"#
+//! );
+//! ```
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Config;
+
+/// A [`Config`] serialized as raw register values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawConfig(pub Config);
+
+#[derive(Serialize, Deserialize)]
+struct Registers {
+    control: u8,
+    attack_time: u8,
+    release_time: u8,
+    hold_time: u8,
+    fixed_gain: u8,
+    agc_control_1: u8,
+    agc_control_2: u8,
+}
+
+impl Serialize for RawConfig {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let r = self.0.to_registers();
+        Registers {
+            control: r[0],
+            attack_time: r[1],
+            release_time: r[2],
+            hold_time: r[3],
+            fixed_gain: r[4],
+            agc_control_1: r[5],
+            agc_control_2: r[6],
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let r = Registers::deserialize(deserializer)?;
+        let regs = [
+            r.control,
+            r.attack_time,
+            r.release_time,
+            r.hold_time,
+            r.fixed_gain,
+            r.agc_control_1,
+            r.agc_control_2,
+        ];
+        Ok(RawConfig(Config::from_registers(&regs)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tpa2016_config;
+
+    #[test]
+    fn patch_from_json() {
+        let patch: crate::ConfigPatch =
+            serde_json::from_str(r#"{"output_limiter_level": 20, "compression_ratio": "Ratio2"}"#)
+                .unwrap();
+        assert_eq!(patch.output_limiter_level, Some(20));
+        assert_eq!(
+            patch.compression_ratio,
+            Some(crate::CompressionRatio::Ratio2)
+        );
+        assert_eq!(patch.fixed_gain, None);
+
+        assert!(serde_json::from_str::<crate::ConfigPatch>(r#"{"limiter": 20}"#).is_err());
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let config = tpa2016_config! { gain: 12 dB, compression: 2:1, limiter: 3 dBV };
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""compression_ratio":"Ratio2""#));
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let config = tpa2016_config! { gain: 12 dB, compression: 2:1, limiter: 3 dBV };
+        let json = serde_json::to_string(&RawConfig(config)).unwrap();
+        let back: RawConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.0, config);
+    }
+
+    #[test]
+    fn output_limiter_round_trip() {
+        let config = tpa2016_config! { compression: 1:1, limiter: off };
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""output_limiter":false"#));
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+
+        let json = serde_json::to_string(&RawConfig(config)).unwrap();
+        assert!(json.contains(r#""agc_control_1":186"#));
+        let back: RawConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.0, config);
+    }
+}
@@ -0,0 +1,150 @@
+//! Driver shared between embassy tasks
+//!
+//! Requires the `embassy` feature.
+//!
+//! ```ignore
+//! static AMP: StaticCell<SharedTpa2016d2<CriticalSectionRawMutex, I2c>> = StaticCell::new();
+//!
+//! let amp = AMP.init(SharedTpa2016d2::new(Tpa2016d2::new(i2c)));
+//!
+//! // In the volume task
+//! amp.lock().await.gain(20)?;
+//!
+//! // In the fault monitor task
+//! let mut events = FaultEvents::new(amp, Delay, 100);
+//! loop {
+//!     match events.next_event().await? {
+//!         FaultEvent::Raised(fault) => warn!("{:?}", fault),
+//!         FaultEvent::Cleared(fault) => info!("{:?} cleared", fault),
+//!     }
+//! }
+//! ```
+
+use embassy_sync::{
+    blocking_mutex::raw::RawMutex,
+    mutex::{Mutex, MutexGuard},
+};
+use embedded_hal_async::delay::DelayNs;
+
+use crate::fault::{EventQueue, FaultEvent, FaultMonitor};
+use crate::{Error, RegisterInterface, Tpa2016d2};
+
+/// A [`Tpa2016d2`] protected by an async mutex
+pub struct SharedTpa2016d2<M: RawMutex, I2C> {
+    inner: Mutex<M, Tpa2016d2<I2C>>,
+}
+
+impl<M: RawMutex, I2C> SharedTpa2016d2<M, I2C> {
+    /// Wrap the driver
+    pub const fn new(amp: Tpa2016d2<I2C>) -> Self {
+        SharedTpa2016d2 {
+            inner: Mutex::new(amp),
+        }
+    }
+
+    /// Wait for exclusive access to the driver
+    pub async fn lock(&self) -> MutexGuard<'_, M, Tpa2016d2<I2C>> {
+        self.inner.lock().await
+    }
+
+    /// Get exclusive access to the driver if it isn't used by another task
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, M, Tpa2016d2<I2C>>> {
+        self.inner.try_lock().ok()
+    }
+
+    /// Run `f` with exclusive access to the driver
+    pub async fn with<R>(&self, f: impl FnOnce(&mut Tpa2016d2<I2C>) -> R) -> R {
+        let mut amp = self.inner.lock().await;
+        f(&mut amp)
+    }
+
+    /// Consume the wrapper and return the driver
+    pub fn into_inner(self) -> Tpa2016d2<I2C> {
+        self.inner.into_inner()
+    }
+}
+
+/// Fault transitions of a shared driver, see [`FaultEvents::next_event`]
+pub struct FaultEvents<'a, M: RawMutex, I2C, D> {
+    amp: &'a SharedTpa2016d2<M, I2C>,
+    delay: D,
+    interval_ms: u32,
+    monitor: FaultMonitor,
+    pending: EventQueue,
+}
+
+impl<'a, M, I2C, D, E> FaultEvents<'a, M, I2C, D>
+where
+    M: RawMutex,
+    I2C: RegisterInterface<Error = E>,
+    D: DelayNs,
+{
+    /// Poll the faults of `amp` every `interval_ms`, assuming no faults are present
+    pub fn new(amp: &'a SharedTpa2016d2<M, I2C>, delay: D, interval_ms: u32) -> Self {
+        FaultEvents {
+            amp,
+            delay,
+            interval_ms,
+            monitor: FaultMonitor::new(),
+            pending: EventQueue::default(),
+        }
+    }
+
+    /// Wait for the next fault to be raised or cleared
+    ///
+    /// The driver is only locked while the faults are read. Transitions seen
+    /// in the same read are returned in the order of [`Fault::ALL`].
+    pub async fn next_event(&mut self) -> Result<FaultEvent, Error<E>> {
+        loop {
+            if let Some(event) = self.pending.pop() {
+                return Ok(event);
+            }
+
+            let faults = self.amp.lock().await.get_faults()?;
+            self.monitor.update(faults, &mut self.pending);
+
+            if self.pending.is_empty() {
+                self.delay.delay_ms(self.interval_ms).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::fault::Fault;
+    use crate::mock::{block_on, MockI2c};
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn fault_events() {
+        let mut i2c = MockI2c::new();
+        // Thermal and left short circuit
+        i2c.regs[1] |= 1 << 2 | 1 << 3;
+        let amp: SharedTpa2016d2<NoopRawMutex, _> = SharedTpa2016d2::new(Tpa2016d2::new(i2c));
+        let mut events = FaultEvents::new(&amp, NoDelay, 10);
+
+        assert_eq!(
+            block_on(events.next_event()),
+            Ok(FaultEvent::Raised(Fault::ShortCircuitLeft))
+        );
+        assert_eq!(
+            block_on(events.next_event()),
+            Ok(FaultEvent::Raised(Fault::Thermal))
+        );
+
+        amp.try_lock().unwrap().bus_mut().regs[1] &= !(1 << 2);
+        assert_eq!(
+            block_on(events.next_event()),
+            Ok(FaultEvent::Cleared(Fault::Thermal))
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Time-domain model of the AGC
+//!
+//! Requires the `simulator` feature. [`AgcSimulator`] runs an input envelope
+//! through a [`Config`] and produces the gain and output level over time, for
+//! tuning attack, release and compression on a host before flashing.
+//!
+//! ```
+//! use tpa2016d2::simulator::AgcSimulator;
+//! use tpa2016d2::Config;
+//!
+//! // 100 ms of -20 dBV followed by 100 ms of 0 dBV, in 1 ms steps
+//! let envelope = (0..200).map(|t| if t < 100 { -20.0 } else { 0.0 });
+//! let mut sim = AgcSimulator::new(&Config::DEFAULT, 1.0);
+//! for sample in sim.run(envelope) {
+//!     assert!(sample.output_dbv <= 6.5 + 6.0);
+//! }
+//! ```
+//!
+//! The model follows the description in the datasheet, not the analog
+//! details of the device:
+//!
+//! - The gain settles at the static transfer curve, see [`curve`](crate::curve).
+//! - Gain reductions move at 6 dB per attack time, increases at 6 dB per
+//!   release time once the input has been below the compression curve for the hold time.
+//! - While the noise gate is enabled and the input is below the threshold the
+//!   gain is not increased.
+//!
+//! [`SimulatedDevice`] models the register file of the device instead, behind
+//! the embedded-hal I2C traits, so application code using the driver can be
+//! tested on the host:
+//!
+//! ```
+//! use tpa2016d2::fault::Fault;
+//! use tpa2016d2::regmap::Register;
+//! use tpa2016d2::simulator::SimulatedDevice;
+//! use tpa2016d2::Tpa2016d2;
+//!
+//! let mut amp = Tpa2016d2::new(SimulatedDevice::new());
+//! amp.gain(12).unwrap();
+//! amp.bus_mut().set_fault(Fault::Thermal, true);
+//! assert!(amp.get_faults().unwrap().thermal);
+//! assert_eq!(amp.release().register(Register::FixedGain), 12);
+//! ```
+
+use libm::log10f;
+
+#[cfg(feature = "hal")]
+mod device;
+#[cfg(feature = "hal")]
+pub use device::{SimError, SimulatedDevice};
+
+use crate::curve;
+use crate::float::{ATTACK_STEP_MS, HOLD_STEP_MS, RELEASE_STEP_MS};
+use crate::{Config, NoiseGateThreshold};
+
+/// One step of the simulation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// Time since the start in ms
+    pub time_ms: f32,
+    /// Input level in dBV
+    pub input_dbv: f32,
+    /// Gain applied by the AGC in dB
+    pub gain_db: f32,
+    /// Output level in dBV
+    pub output_dbv: f32,
+}
+
+/// AGC model driven one envelope step at a time
+#[derive(Clone, Copy, Debug)]
+pub struct AgcSimulator {
+    step_ms: f32,
+    config: Config,
+    attack_ms: f32,
+    release_ms: f32,
+    hold_ms: f32,
+    noise_gate_dbv: Option<f32>,
+    time_ms: f32,
+    gain_db: f32,
+    hold_left_ms: f32,
+}
+
+impl AgcSimulator {
+    /// A simulator for `config`, advancing `step_ms` per input value
+    ///
+    /// The gain starts at the fixed gain.
+    pub fn new(config: &Config, step_ms: f32) -> Self {
+        let agc = &config.agc;
+        let noise_gate_dbv = if config.noise_gate {
+            Some(20.0 * log10f(noise_gate_mv(config.noise_gate_threshold) / 1000.0))
+        } else {
+            None
+        };
+
+        AgcSimulator {
+            step_ms,
+            config: *config,
+            attack_ms: agc.attack_time as f32 * ATTACK_STEP_MS,
+            release_ms: agc.release_time as f32 * RELEASE_STEP_MS,
+            hold_ms: agc.hold_time as f32 * HOLD_STEP_MS,
+            noise_gate_dbv,
+            time_ms: 0.0,
+            gain_db: crate::agc::fixed_gain_to_db(agc.fixed_gain) as f32,
+            hold_left_ms: 0.0,
+        }
+    }
+
+    /// Advance the simulation by one step with the input at `input_dbv`
+    pub fn step(&mut self, input_dbv: f32) -> Sample {
+        let target = curve::gain_db(input_dbv, &self.config);
+        let gated = matches!(self.noise_gate_dbv, Some(threshold) if input_dbv < threshold);
+
+        if target <= self.gain_db {
+            self.gain_db = slew(self.gain_db, target, self.attack_ms, self.step_ms);
+            self.hold_left_ms = self.hold_ms;
+        } else if self.hold_left_ms > 0.0 {
+            self.hold_left_ms = (self.hold_left_ms - self.step_ms).max(0.0);
+        } else if target > self.gain_db && !gated {
+            self.gain_db = slew(self.gain_db, target, self.release_ms, self.step_ms);
+        }
+
+        let sample = Sample {
+            time_ms: self.time_ms,
+            input_dbv,
+            gain_db: self.gain_db,
+            output_dbv: input_dbv + self.gain_db,
+        };
+        self.time_ms += self.step_ms;
+        sample
+    }
+
+    /// Run the simulation over an input envelope in dBV, one value per step
+    pub fn run<'a, I>(&'a mut self, envelope: I) -> impl Iterator<Item = Sample> + 'a
+    where
+        I: IntoIterator<Item = f32>,
+        I::IntoIter: 'a,
+    {
+        envelope
+            .into_iter()
+            .map(move |input_dbv| self.step(input_dbv))
+    }
+}
+
+/// Move `from` towards `to` at 6 dB per `per_6db_ms`
+fn slew(from: f32, to: f32, per_6db_ms: f32, step_ms: f32) -> f32 {
+    if per_6db_ms <= 0.0 {
+        return to;
+    }
+    let max = 6.0 * step_ms / per_6db_ms;
+    from + (to - from).clamp(-max, max)
+}
+
+fn noise_gate_mv(threshold: NoiseGateThreshold) -> f32 {
+    match threshold {
+        NoiseGateThreshold::Ngt1mV => 1.0,
+        NoiseGateThreshold::Ngt4mV => 4.0,
+        NoiseGateThreshold::Ngt10mV => 10.0,
+        NoiseGateThreshold::Ngt20mV => 20.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attack_hold_release() {
+        let mut config = Config::DEFAULT;
+        config.noise_gate = false;
+        config.agc.attack_time = 10; // 1.067 ms per 6 dB
+        config.agc.release_time = 1; // 164.4 ms per 6 dB
+        config.agc.hold_time = 2; // 27.4 ms
+        let mut sim = AgcSimulator::new(&config, 1.0);
+
+        // Loud input, settles at 0.5 dB in about 1 ms
+        let loud = sim.run(core::iter::repeat_n(6.0, 5)).last().unwrap();
+        assert_eq!(loud.gain_db, 0.5);
+
+        // Held during the hold time
+        let held = sim.run(core::iter::repeat_n(0.5, 27)).last().unwrap();
+        assert_eq!(held.gain_db, 0.5);
+
+        // Then released towards 6 dB
+        let released = sim.run(core::iter::repeat_n(0.5, 100)).last().unwrap();
+        assert!(released.gain_db > 3.5 && released.gain_db < 4.5);
+        assert_eq!(released.time_ms, 131.0);
+    }
+}
@@ -0,0 +1,159 @@
+//! Register level model of the device
+
+use embedded_hal::blocking::i2c;
+
+use crate::fault::Fault;
+use crate::regmap::{self, Register, FAULT_BITS, POWER_ON};
+use crate::TPA2016_I2C_ADDR;
+
+/// Bus error of [`SimulatedDevice`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimError {
+    /// No device at the address
+    NoAcknowledge,
+    /// Access to a register outside 1 to 7
+    InvalidRegister,
+}
+
+/// A TPA2016D2 on an I2C bus, for testing applications on the host
+///
+/// Implements the embedded-hal I2C traits with the register semantics of the
+/// device: power-on values, auto-increment, reserved bits with fixed values
+/// and fault flags that are only changed by the device, see
+/// [`SimulatedDevice::set_fault`].
+#[derive(Clone, Debug)]
+pub struct SimulatedDevice {
+    regs: [u8; 7],
+}
+
+impl Default for SimulatedDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedDevice {
+    /// A device with its power-on register values
+    pub fn new() -> Self {
+        SimulatedDevice { regs: POWER_ON }
+    }
+
+    /// The value of register `reg`
+    pub fn register(&self, reg: Register) -> u8 {
+        self.regs[reg.addr() as usize - 1]
+    }
+
+    /// Raise or clear a fault
+    ///
+    /// Like the device, a short circuit turns off its channel and a thermal
+    /// fault both channels.
+    pub fn set_fault(&mut self, fault: Fault, active: bool) {
+        let (flag, channels) = match fault {
+            Fault::ShortCircuitRight => (1 << 4, 1 << 7),
+            Fault::ShortCircuitLeft => (1 << 3, 1 << 6),
+            Fault::Thermal => (1 << 2, 1 << 7 | 1 << 6),
+        };
+        if active {
+            self.regs[0] = (self.regs[0] | flag) & !channels;
+        } else {
+            self.regs[0] &= !flag;
+        }
+    }
+
+    /// Reset to the power-on values, as after toggling the supply or SDZ
+    pub fn reset(&mut self) {
+        self.regs = POWER_ON;
+    }
+
+    fn write_register(&mut self, reg: Register, value: u8) {
+        let fault_bits = if reg == Register::Control {
+            FAULT_BITS
+        } else {
+            0
+        };
+        let mask = regmap::writable_mask(reg) & !fault_bits;
+        let stored = &mut self.regs[reg.addr() as usize - 1];
+        *stored = *stored & !mask | value & mask;
+    }
+
+    /// The `len` registers accessed from address `first` on
+    fn check(addr: u8, first: u8, len: usize) -> Result<&'static [Register], SimError> {
+        if addr != TPA2016_I2C_ADDR {
+            return Err(SimError::NoAcknowledge);
+        }
+        if first == 0 || first as usize + len > 8 {
+            return Err(SimError::InvalidRegister);
+        }
+        let first = first as usize - 1;
+        Ok(&Register::ALL[first..first + len])
+    }
+}
+
+impl i2c::Write for SimulatedDevice {
+    type Error = SimError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), SimError> {
+        let (&first, values) = bytes.split_first().ok_or(SimError::InvalidRegister)?;
+        let regs = Self::check(addr, first, values.len())?;
+        for (&reg, &value) in regs.iter().zip(values) {
+            self.write_register(reg, value);
+        }
+        Ok(())
+    }
+}
+
+impl i2c::WriteRead for SimulatedDevice {
+    type Error = SimError;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), SimError> {
+        let &first = bytes.first().ok_or(SimError::InvalidRegister)?;
+        let regs = Self::check(addr, first, buffer.len())?;
+        for (&reg, b) in regs.iter().zip(buffer.iter_mut()) {
+            *b = self.register(reg);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Channel, ChannelState, Tpa2016d2};
+
+    #[test]
+    fn register_semantics() {
+        let mut amp = Tpa2016d2::new(SimulatedDevice::new());
+        amp.sync().unwrap();
+        assert_eq!(amp.config(), crate::Config::DEFAULT);
+
+        amp.set_write_verify(true);
+        amp.gain(12).unwrap();
+        amp.set_max_gain(0).unwrap();
+        assert_eq!(amp.bus_mut().register(Register::FixedGain), 12);
+        assert_eq!(amp.bus_mut().register(Register::Agc2), 0x02);
+
+        // Fault flags and reserved bits can not be written
+        i2c::Write::write(amp.bus_mut(), TPA2016_I2C_ADDR, &[1, 0xFF]).unwrap();
+        assert_eq!(amp.bus_mut().register(Register::Control), 0xE3);
+
+        amp.enable_device().unwrap();
+        amp.bus_mut().set_fault(Fault::ShortCircuitLeft, true);
+        assert!(amp.get_faults().unwrap().fault_l);
+        assert_eq!(
+            amp.channel_state(Channel::Left),
+            ChannelState::DisabledByFault
+        );
+        assert_eq!(amp.channel_state(Channel::Right), ChannelState::Enabled);
+
+        let mut bus = amp.release();
+        assert_eq!(
+            i2c::Write::write(&mut bus, 0x10, &[5, 0]),
+            Err(SimError::NoAcknowledge)
+        );
+        assert_eq!(
+            i2c::Write::write(&mut bus, TPA2016_I2C_ADDR, &[7, 0, 0]),
+            Err(SimError::InvalidRegister)
+        );
+        assert_eq!(Tpa2016d2::new(bus).probe(), Ok(()));
+    }
+}
@@ -0,0 +1,146 @@
+//! Sound pressure level setpoints
+//!
+//! After measuring the SPL a product produces at a known fixed gain, the gain
+//! for a target SPL follows from the difference in dB. With an SPL limit set
+//! in the calibration, targets above the limit are clamped, which helps with
+//! regulatory loudness limits:
+//!
+//! ```
+//! use tpa2016d2::spl::SplCalibration;
+//!
+//! // 82 dB SPL measured at 1 m with the fixed gain at 6 dB, never exceed 85 dB SPL
+//! let cal = SplCalibration::new(82, 6, 100).max_spl_db(85);
+//!
+//! assert_eq!(cal.gain_db(80), 4);
+//! assert_eq!(cal.gain_db(90), 9);
+//! // 6 dB less gain at half the distance
+//! assert_eq!(cal.gain_db_at(80, 50), -2);
+//! ```
+//!
+//! The calibration is only valid for the signal level it was measured with and
+//! as long as the output limiter does not kick in.
+
+use crate::power::POW10_HALF_DB;
+
+/// Measured SPL at a reference gain and distance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SplCalibration {
+    spl_db: u8,
+    gain_db: i8,
+    distance_cm: u16,
+    max_spl_db: Option<u8>,
+}
+
+impl SplCalibration {
+    /// `spl_db` measured at `distance_cm` with the fixed gain at `gain_db`
+    pub const fn new(spl_db: u8, gain_db: i8, distance_cm: u16) -> Self {
+        SplCalibration {
+            spl_db,
+            gain_db,
+            distance_cm,
+            max_spl_db: None,
+        }
+    }
+
+    /// Limit the SPL at the reference distance to `spl_db`
+    pub const fn max_spl_db(mut self, spl_db: u8) -> Self {
+        self.max_spl_db = Some(spl_db);
+        self
+    }
+
+    /// The calibration as (SPL, gain, distance, SPL limit)
+    pub(crate) fn parts(&self) -> (u8, i8, u16, Option<u8>) {
+        (self.spl_db, self.gain_db, self.distance_cm, self.max_spl_db)
+    }
+
+    /// The reference distance in cm
+    pub fn distance_cm(&self) -> u16 {
+        self.distance_cm
+    }
+
+    /// Fixed gain in dB for `spl_db` at the reference distance
+    ///
+    /// Clamped to the SPL limit and to the -28 to 30 dB range of the device.
+    pub fn gain_db(&self, spl_db: u8) -> i8 {
+        let spl_db = match self.max_spl_db {
+            Some(max) => spl_db.min(max),
+            None => spl_db,
+        };
+        let gain = self.gain_db as i16 + spl_db as i16 - self.spl_db as i16;
+        gain.clamp(-28, 30) as i8
+    }
+
+    /// Fixed gain in dB for `spl_db` at `distance_cm`
+    ///
+    /// The SPL drops 6 dB per doubling of the distance. The SPL limit still
+    /// applies at the reference distance.
+    pub fn gain_db_at(&self, spl_db: u8, distance_cm: u16) -> i8 {
+        let spl_db = match self.max_spl_db {
+            Some(max) => spl_db.min(max),
+            None => spl_db,
+        };
+        let half_db = distance_half_db(distance_cm, self.distance_cm);
+        let gain = self.gain_db as i16 + spl_db as i16 - self.spl_db as i16;
+        let gain = (gain * 2 + half_db + 1).div_euclid(2);
+
+        let gain = match self.max_spl_db {
+            Some(_) => gain.min(self.gain_db(u8::MAX) as i16),
+            None => gain,
+        };
+        gain.clamp(-28, 30) as i8
+    }
+}
+
+/// 20 * log10(distance / reference) in 0.5 dB, rounded to the nearest step
+fn distance_half_db(distance_cm: u16, reference_cm: u16) -> i16 {
+    if distance_cm == 0 || reference_cm == 0 {
+        return 0;
+    }
+
+    // The level ratio is the power ratio of the squared distances
+    let mut num = distance_cm as u64 * distance_cm as u64;
+    let mut den = reference_cm as u64 * reference_cm as u64;
+    let mut steps = 0;
+    while num >= den * 10 {
+        den *= 10;
+        steps += 20;
+    }
+    while num < den {
+        num *= 10;
+        steps -= 20;
+    }
+
+    let ratio = (num * 10000 / den) as u32;
+    let idx = POW10_HALF_DB
+        .iter()
+        .chain(core::iter::once(&100000))
+        .enumerate()
+        .min_by_key(|(_, &pow)| (pow as i64 - ratio as i64).abs())
+        .map_or(0, |(idx, _)| idx);
+
+    steps + idx as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        assert_eq!(distance_half_db(100, 100), 0);
+        assert_eq!(distance_half_db(200, 100), 12);
+        assert_eq!(distance_half_db(50, 100), -12);
+        assert_eq!(distance_half_db(1000, 100), 40);
+        assert_eq!(distance_half_db(10, 100), -40);
+    }
+
+    #[test]
+    fn limits() {
+        let cal = SplCalibration::new(80, 0, 100).max_spl_db(85);
+        assert_eq!(cal.gain_db(100), 5);
+        assert_eq!(cal.gain_db(20), -28);
+        assert_eq!(cal.gain_db_at(85, 400), 5);
+        assert_eq!(cal.gain_db_at(75, 200), 1);
+    }
+}
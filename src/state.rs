@@ -0,0 +1,162 @@
+//! Driver state export and import
+//!
+//! The complete driver state, the cached registers, gain trim, quiet
+//! reconfiguration mode, active preset and SPL calibration, can be stored as
+//! a few bytes, e.g. in retained RAM over a soft reboot or a firmware update.
+//! A driver created from it resumes without reading back or rewriting the
+//! device, so there is no audible reconfiguration:
+//!
+//! ```ignore
+//! // Before the reboot
+//! RETAINED.copy_from_slice(&amp.export_state().to_bytes());
+//!
+//! // After the reboot
+//! let amp = match DriverState::from_bytes(&RETAINED) {
+//!     Some(state) => Tpa2016d2::from_state(i2c, &state),
+//!     None => Tpa2016d2::new(i2c),
+//! };
+//! ```
+//!
+//! The state is only valid as long as the device kept its supply.
+
+use core::convert::TryFrom;
+
+use crate::regmap::Register;
+use crate::sansio::Core;
+use crate::spl::SplCalibration;
+use crate::{AgcPreset, QuietReconfig};
+
+/// Length of the serialized state
+pub const STATE_LEN: usize = 17;
+
+// Increased when the layout changes
+const VERSION: u8 = 1;
+
+/// Snapshot of the driver state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DriverState {
+    /// Cached values of registers 1 to 7
+    pub registers: [u8; 7],
+    pub gain_trim: i8,
+    pub quiet: QuietReconfig,
+    pub preset: Option<AgcPreset>,
+    pub spl: Option<SplCalibration>,
+}
+
+impl DriverState {
+    /// Serialize the state
+    pub fn to_bytes(&self) -> [u8; STATE_LEN] {
+        let mut buf = [0; STATE_LEN];
+        buf[0] = VERSION;
+        buf[1..8].copy_from_slice(&self.registers);
+        buf[8] = self.gain_trim as u8;
+        buf[9] = self.quiet as u8;
+        buf[10] = self.preset.map_or(0, |preset| preset as u8 + 1);
+
+        if let Some(spl) = self.spl {
+            let (spl_db, gain_db, distance_cm, max_spl_db) = spl.parts();
+            buf[11] = 1 | (max_spl_db.is_some() as u8) << 1;
+            buf[12] = spl_db;
+            buf[13] = gain_db as u8;
+            buf[14..16].copy_from_slice(&distance_cm.to_le_bytes());
+            buf[16] = max_spl_db.unwrap_or(0);
+        }
+
+        buf
+    }
+
+    /// Deserialize a state, `None` if `buf` does not hold a valid state
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() != STATE_LEN || buf[0] != VERSION {
+            return None;
+        }
+
+        let mut registers = [0; 7];
+        registers.copy_from_slice(&buf[1..8]);
+
+        let quiet = QuietReconfig::try_from(buf[9]).ok()?;
+        let preset = match buf[10] {
+            0 => None,
+            n => Some(AgcPreset::try_from(n - 1).ok()?),
+        };
+
+        let spl = match buf[11] {
+            0 => None,
+            flags => {
+                let distance_cm = u16::from_le_bytes([buf[14], buf[15]]);
+                let cal = SplCalibration::new(buf[12], buf[13] as i8, distance_cm);
+                Some(if flags & 1 << 1 != 0 {
+                    cal.max_spl_db(buf[16])
+                } else {
+                    cal
+                })
+            }
+        };
+
+        Some(DriverState {
+            registers,
+            gain_trim: buf[8] as i8,
+            quiet,
+            preset,
+            spl,
+        })
+    }
+}
+
+impl Core {
+    /// The state of the core, the SPL calibration is not part of it
+    pub fn export_state(&self) -> DriverState {
+        let mut registers = [0; 7];
+        for (val, reg) in registers.iter_mut().zip(Register::ALL) {
+            *val = self.register(reg);
+        }
+
+        DriverState {
+            registers,
+            gain_trim: self.gain_trim(),
+            quiet: self.quiet_reconfig(),
+            preset: self.preset,
+            spl: None,
+        }
+    }
+
+    /// Restore the state, without any commands for the device
+    pub fn import_state(&mut self, state: &DriverState) {
+        for (&val, reg) in state.registers.iter().zip(Register::ALL) {
+            self.receive(reg, val);
+        }
+        self.set_gain_trim(state.gain_trim);
+        self.set_quiet_reconfig(state.quiet);
+        self.preset = state.preset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut core = Core::new();
+        core.set_gain_trim(-3);
+        core.set_quiet_reconfig(QuietReconfig::Mute);
+        let _ = core.set_agc_preset(AgcPreset::Rock);
+
+        let mut state = core.export_state();
+        state.spl = Some(SplCalibration::new(82, 6, 100).max_spl_db(85));
+        let bytes = state.to_bytes();
+        assert_eq!(DriverState::from_bytes(&bytes), Some(state));
+
+        let mut restored = Core::new();
+        restored.import_state(&state);
+        assert_eq!(restored.export_state().registers, state.registers);
+        assert_eq!(restored.status(), core.status());
+        assert_eq!(restored.gain_trim(), -3);
+
+        let mut bad = bytes;
+        bad[0] = 0;
+        assert_eq!(DriverState::from_bytes(&bad), None);
+        assert_eq!(DriverState::from_bytes(&bytes[1..]), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Bus operation latency statistics
+//!
+//! Requires the `stats` feature. With a time source set through
+//! [`Tpa2016d2::set_stats_clock`](crate::Tpa2016d2::set_stats_clock) the
+//! driver records how long each register read and write takes on the bus,
+//! e.g. to quantify the delays caused by a congested shared bus:
+//!
+//! ```ignore
+//! amp.set_stats_clock(|| timer.now_us());
+//!
+//! let stats = amp.stats();
+//! for (upper_us, count) in stats.writes.buckets() {
+//!     info!("< {} µs: {}", upper_us, count);
+//! }
+//! ```
+
+/// Number of histogram buckets
+pub const BUCKETS: usize = 16;
+
+/// Histogram of latencies in power of two buckets
+///
+/// Bucket `n` counts latencies below `2^n` µs and at least `2^(n-1)` µs, the
+/// last bucket also holds everything longer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: [u32; BUCKETS],
+    max_us: u32,
+}
+
+impl LatencyHistogram {
+    /// Add one measurement
+    pub fn record(&mut self, latency_us: u32) {
+        let bucket = (32 - latency_us.leading_zeros() as usize).min(BUCKETS - 1);
+        self.counts[bucket] = self.counts[bucket].saturating_add(1);
+        self.max_us = self.max_us.max(latency_us);
+    }
+
+    /// The counts per bucket
+    pub fn counts(&self) -> &[u32; BUCKETS] {
+        &self.counts
+    }
+
+    /// The buckets as `(upper bound in µs, count)`, the last bound is open
+    pub fn buckets(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(n, &count)| (1 << n, count))
+    }
+
+    /// Number of measurements
+    pub fn total(&self) -> u32 {
+        self.counts.iter().fold(0, |sum, &c| sum.saturating_add(c))
+    }
+
+    /// The longest latency seen, in µs
+    pub fn max_us(&self) -> u32 {
+        self.max_us
+    }
+}
+
+/// Latencies of the bus operations of a driver
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BusStats {
+    /// Register reads, the write of the register address and the read
+    pub reads: LatencyHistogram,
+    /// Register writes
+    pub writes: LatencyHistogram,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        let mut hist = LatencyHistogram::default();
+        hist.record(0);
+        hist.record(1);
+        hist.record(3);
+        hist.record(300);
+        hist.record(u32::MAX);
+
+        assert_eq!(hist.counts()[..4], [1, 1, 1, 0]);
+        assert_eq!(hist.counts()[9], 1);
+        assert_eq!(hist.counts()[BUCKETS - 1], 1);
+        assert_eq!(hist.total(), 5);
+        assert_eq!(hist.max_us(), u32::MAX);
+    }
+
+    #[cfg(feature = "hal")]
+    #[test]
+    fn driver_records_latencies() {
+        use core::sync::atomic::{AtomicU32, Ordering};
+
+        // Every reading of the clock advances it by 100 µs
+        static NOW: AtomicU32 = AtomicU32::new(0);
+        fn now_us() -> u32 {
+            NOW.fetch_add(100, Ordering::Relaxed)
+        }
+
+        let mut amp = crate::Tpa2016d2::new(crate::mock::MockI2c::new());
+        amp.gain(10).unwrap();
+        amp.set_stats_clock(now_us);
+        amp.gain(12).unwrap();
+        amp.get_faults().unwrap();
+
+        let stats = amp.stats();
+        assert_eq!(stats.writes.total(), 1);
+        assert_eq!(stats.writes.counts()[7], 1);
+        assert_eq!(stats.reads.total(), 1);
+
+        amp.reset_stats();
+        assert_eq!(amp.stats(), &BusStats::default());
+    }
+}
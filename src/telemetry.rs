@@ -0,0 +1,57 @@
+//! CBOR telemetry encoding
+//!
+//! Requires the `minicbor` feature. [`Status`] is encoded as a CBOR map with
+//! integer keys, so new fields can be added without breaking existing
+//! decoders. A status encodes to less than 32 bytes, small enough for a
+//! single LoRa or BLE packet.
+
+use minicbor::encode::write::{Cursor, EndOfSlice};
+
+use crate::Status;
+
+/// Encode `status` into `buf`, returns the number of bytes written
+pub fn encode_status(
+    status: &Status,
+    buf: &mut [u8],
+) -> Result<usize, minicbor::encode::Error<EndOfSlice>> {
+    let mut cursor = Cursor::new(buf);
+    minicbor::encode(status, &mut cursor)?;
+    Ok(cursor.position())
+}
+
+/// Decode a status encoded by [`encode_status`]
+pub fn decode_status(buf: &[u8]) -> Result<Status, minicbor::decode::Error> {
+    minicbor::decode(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AgcPreset, Faults};
+
+    #[test]
+    fn round_trip() {
+        let status = Status {
+            speaker_left: true,
+            speaker_right: false,
+            shutdown: false,
+            faults: Faults {
+                fault_r: true,
+                fault_l: false,
+                thermal: false,
+            },
+            fixed_gain_db: -12,
+            preset: Some(AgcPreset::Rock),
+        };
+
+        let mut buf = [0; 32];
+        let len = encode_status(&status, &mut buf).unwrap();
+        assert_eq!(decode_status(&buf[..len]).unwrap(), status);
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let status = crate::sansio::Core::new().status();
+        assert!(encode_status(&status, &mut [0; 4]).is_err());
+    }
+}
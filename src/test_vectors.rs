@@ -0,0 +1,221 @@
+//! Expected register contents and I2C write sequences
+//!
+//! Reference data for validating a HAL or board support integration, e.g. by
+//! capturing the bus traffic of the driver and comparing it with these
+//! sequences. Writes are given as `(register, value)` and are sent as two
+//! byte writes to [`I2C_ADDRESS`].
+//!
+//! The sequences assume a newly created driver without gain trim and with
+//! [`QuietReconfig::Off`](crate::QuietReconfig::Off).
+
+use crate::{tpa2016_config, AgcPreset, Config};
+
+/// 7 bit I2C address of the device
+pub const I2C_ADDRESS: u8 = 0x58;
+
+/// Power-on values of registers 1 to 7
+pub const DEFAULT_REGISTERS: [u8; 7] = [0xC3, 0x05, 0x0B, 0x00, 0x06, 0x3A, 0xC2];
+
+/// Writes performed by [`Tpa2016d2::set_agc_preset`](crate::Tpa2016d2::set_agc_preset)
+pub struct PresetVector {
+    pub preset: AgcPreset,
+    pub writes: &'static [(u8, u8)],
+}
+
+/// Register contents and writes performed by
+/// [`Tpa2016d2::apply_config`](crate::Tpa2016d2::apply_config)
+pub struct ConfigVector {
+    pub name: &'static str,
+    pub config: Config,
+    /// Values of registers 1 to 7, as returned by [`Config::to_registers`]
+    pub registers: [u8; 7],
+    pub writes: &'static [(u8, u8)],
+}
+
+/// Every AGC preset
+pub const PRESETS: &[PresetVector] = &[
+    PresetVector {
+        preset: AgcPreset::Pop,
+        writes: &[
+            (2, 0x02),
+            (3, 0x06),
+            (4, 0x0A),
+            (5, 0x06),
+            (6, 0x3C),
+            (7, 0xC2),
+        ],
+    },
+    PresetVector {
+        preset: AgcPreset::Classical,
+        writes: &[
+            (2, 0x02),
+            (3, 0x07),
+            (4, 0x0A),
+            (5, 0x06),
+            (6, 0x3D),
+            (7, 0xC1),
+        ],
+    },
+    PresetVector {
+        preset: AgcPreset::Jazz,
+        writes: &[
+            (2, 0x06),
+            (3, 0x14),
+            (4, 0x00),
+            (5, 0x06),
+            (6, 0x3D),
+            (7, 0xC1),
+        ],
+    },
+    PresetVector {
+        preset: AgcPreset::Rap,
+        writes: &[
+            (2, 0x02),
+            (3, 0x0A),
+            (4, 0x00),
+            (5, 0x06),
+            (6, 0x3C),
+            (7, 0xC2),
+        ],
+    },
+    PresetVector {
+        preset: AgcPreset::Rock,
+        writes: &[
+            (2, 0x03),
+            (3, 0x19),
+            (4, 0x00),
+            (5, 0x06),
+            (6, 0x3D),
+            (7, 0xC1),
+        ],
+    },
+    PresetVector {
+        preset: AgcPreset::Voice,
+        writes: &[
+            (2, 0x02),
+            (3, 0x0A),
+            (4, 0x00),
+            (5, 0x06),
+            (6, 0x3E),
+            (7, 0xC2),
+        ],
+    },
+];
+
+/// The power-on configuration and a few representative ones
+pub const CONFIGS: &[ConfigVector] = &[
+    ConfigVector {
+        name: "default",
+        config: Config::DEFAULT,
+        registers: DEFAULT_REGISTERS,
+        writes: &[
+            (2, 0x05),
+            (3, 0x0B),
+            (4, 0x00),
+            (5, 0x06),
+            (6, 0x3A),
+            (7, 0xC2),
+            (1, 0xC3),
+        ],
+    },
+    ConfigVector {
+        name: "fast attack",
+        config: tpa2016_config! {
+            gain: 6 dB,
+            compression: 4:1,
+            attack: 2.56 ms,
+            limiter: 6.5 dBV,
+            noise_gate: 4 mV,
+        },
+        registers: [0xC3, 0x18, 0x0B, 0x00, 0x06, 0x3A, 0xC2],
+        writes: &[
+            (2, 0x18),
+            (3, 0x0B),
+            (4, 0x00),
+            (5, 0x06),
+            (6, 0x3A),
+            (7, 0xC2),
+            (1, 0xC3),
+        ],
+    },
+    ConfigVector {
+        name: "right only, no compression",
+        config: tpa2016_config! {
+            left: off,
+            gain: -6 dB,
+            compression: 1:1,
+            limiter: 9 dBV,
+            noise_gate: off,
+        },
+        registers: [0x82, 0x05, 0x0B, 0x00, 0x3A, 0x3F, 0xC0],
+        writes: &[
+            (2, 0x05),
+            (3, 0x0B),
+            (4, 0x00),
+            (5, 0x3A),
+            (6, 0x3F),
+            (7, 0xC0),
+            (1, 0x82),
+        ],
+    },
+    ConfigVector {
+        name: "no compression, limiter off",
+        config: tpa2016_config! {
+            compression: 1:1,
+            limiter: off,
+        },
+        registers: [0xC3, 0x05, 0x0B, 0x00, 0x06, 0xBA, 0xC0],
+        // Compression is turned off before the limiter
+        writes: &[
+            (2, 0x05),
+            (3, 0x0B),
+            (4, 0x00),
+            (5, 0x06),
+            (7, 0xC0),
+            (6, 0xBA),
+            (1, 0xC3),
+        ],
+    },
+];
+
+#[cfg(all(test, feature = "hal"))]
+mod tests {
+    use super::*;
+    use crate::mock::MockI2c;
+    use crate::regmap::{Register, RegisterMap};
+    use crate::Tpa2016d2;
+
+    #[test]
+    fn defaults() {
+        let regmap = RegisterMap::default();
+        for (idx, &val) in DEFAULT_REGISTERS.iter().enumerate() {
+            assert_eq!(regmap.reg_as_byte(Register::ALL[idx]), val);
+        }
+        assert_eq!(I2C_ADDRESS, crate::TPA2016_I2C_ADDR);
+    }
+
+    #[test]
+    fn presets() {
+        for vector in PRESETS {
+            let mut amp = Tpa2016d2::new(MockI2c::new());
+            amp.set_agc_preset(vector.preset).unwrap();
+            assert_eq!(amp.release().writes, vector.writes, "{:?}", vector.preset);
+        }
+    }
+
+    #[test]
+    fn configs() {
+        for vector in CONFIGS {
+            assert_eq!(
+                vector.config.to_registers(),
+                vector.registers,
+                "{}",
+                vector.name
+            );
+
+            let mut amp = Tpa2016d2::new(MockI2c::new());
+            amp.apply_config(&vector.config).unwrap();
+            assert_eq!(amp.release().writes, vector.writes, "{}", vector.name);
+        }
+    }
+}
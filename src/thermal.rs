@@ -0,0 +1,409 @@
+//! Predictive thermal derating
+//!
+//! [`ThermalModel`] estimates the die temperature from the output power
+//! implied by the current configuration, see [`power`](crate::power), using a
+//! first order thermal model. When the estimate gets close to the thermal
+//! shutdown temperature the fixed gain is reduced, before the hardware
+//! protection trips and mutes the output.
+//!
+//! [`ThermalBackoff`] instead reacts to the thermal fault flag, stepping the
+//! gain down while the fault is present and back up once it has cleared.
+
+#[cfg(driver)]
+use crate::agc::{fixed_gain_code, fixed_gain_db};
+use crate::power::OperatingPoint;
+#[cfg(driver)]
+use crate::power::{output_power_uw, EFFICIENCY_PCT, QUIESCENT_CURRENT_UA};
+#[cfg(driver)]
+use crate::{Clock, CompressionRatio, Error, RegisterInterface, Tpa2016d2};
+
+/// Thermal model parameters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThermalParams {
+    /// Junction to ambient thermal resistance in °C/W
+    pub theta_ja: u16,
+    /// Ambient temperature in °C
+    pub ambient_c: i16,
+    /// Thermal time constant of the package and board in ms, with 0 the die
+    /// follows the dissipation at once
+    pub time_constant_ms: u32,
+    /// Temperature where derating starts, in °C
+    pub derate_start_c: i16,
+    /// Temperature where the gain is reduced by `max_derate_db`, in °C
+    pub derate_full_c: i16,
+    /// Maximum gain reduction in dB
+    pub max_derate_db: u8,
+    pub operating_point: OperatingPoint,
+}
+
+impl ThermalParams {
+    /// Parameters with derating from 120 °C up to 12 dB at 140 °C
+    pub const fn new(theta_ja: u16, ambient_c: i16, operating_point: OperatingPoint) -> Self {
+        ThermalParams {
+            theta_ja,
+            ambient_c,
+            time_constant_ms: 10_000,
+            derate_start_c: 120,
+            derate_full_c: 140,
+            max_derate_db: 12,
+            operating_point,
+        }
+    }
+}
+
+/// Die temperature estimator and gain derating controller
+pub struct ThermalModel {
+    params: ThermalParams,
+    /// Estimated die temperature in m°C
+    die_mc: i32,
+    derate_db: u8,
+    /// Gain set by the application and the gain we wrote, as register values
+    nominal_gain: u8,
+    written_gain: Option<u8>,
+    last_ms: Option<u32>,
+}
+
+impl ThermalModel {
+    /// Creates a new model, the die starts at the ambient temperature
+    pub fn new(params: ThermalParams) -> Self {
+        ThermalModel {
+            params,
+            die_mc: params.ambient_c as i32 * 1000,
+            derate_db: 0,
+            nominal_gain: 0,
+            written_gain: None,
+            last_ms: None,
+        }
+    }
+
+    /// Estimated die temperature in °C
+    pub fn die_temp_c(&self) -> i16 {
+        (self.die_mc / 1000) as i16
+    }
+
+    /// Current gain reduction in dB
+    pub fn derating_db(&self) -> u8 {
+        self.derate_db
+    }
+
+    /// Update the ambient temperature, e.g. from a board sensor
+    pub fn set_ambient_c(&mut self, ambient_c: i16) {
+        self.params.ambient_c = ambient_c;
+    }
+
+    /// Advance the model by `elapsed_ms` with `dissipation_mw` in the die
+    ///
+    /// Returns the derating in dB.
+    pub fn update(&mut self, dissipation_mw: u32, elapsed_ms: u32) -> u8 {
+        let p = &self.params;
+        let target_mc =
+            p.ambient_c as i32 * 1000 + (dissipation_mw as i64 * p.theta_ja as i64) as i32;
+
+        // First order step response, without elapsed time there is no step
+        if elapsed_ms > 0 {
+            let dt = elapsed_ms as i64;
+            let delta = (target_mc - self.die_mc) as i64 * dt / (p.time_constant_ms as i64 + dt);
+            self.die_mc += delta as i32;
+        }
+
+        self.derate_db = self.derating_at(self.die_mc);
+        self.derate_db
+    }
+
+    fn derating_at(&self, die_mc: i32) -> u8 {
+        let p = &self.params;
+        let start = p.derate_start_c as i32 * 1000;
+        let full = p.derate_full_c as i32 * 1000;
+
+        if die_mc <= start {
+            0
+        } else if die_mc >= full || full <= start {
+            p.max_derate_db
+        } else {
+            // Round up so any excess gives at least 1 dB
+            let span = full - start;
+            (((die_mc - start) * p.max_derate_db as i32 + span - 1) / span) as u8
+        }
+    }
+
+    /// Update the model from the amplifier configuration and apply the derating
+    ///
+    /// Call periodically, the time since the last call is taken from `clock`.
+    /// Gain changes made by the application are picked up as the new nominal
+    /// gain. The derating is applied on top of the gain trim.
+    #[cfg(driver)]
+    pub fn poll<I2C, E, C>(
+        &mut self,
+        amp: &mut Tpa2016d2<I2C>,
+        clock: &mut C,
+    ) -> Result<u8, Error<E>>
+    where
+        I2C: RegisterInterface<Error = E>,
+        C: Clock,
+    {
+        let now = clock.now_ms();
+        let elapsed = self.last_ms.map_or(0, |last| now.wrapping_sub(last));
+        self.last_ms = Some(now);
+
+        let config = amp.agc_config();
+        if self.written_gain != Some(config.fixed_gain) {
+            self.nominal_gain = config.fixed_gain;
+        }
+
+        let dissipation_mw = if amp.core.regmap.sws() {
+            0
+        } else {
+            let op = &self.params.operating_point;
+            let channels = amp.core.regmap.spk_en_l() as u32 + amp.core.regmap.spk_en_r() as u32;
+            let output_uw = output_power_uw(&config, op) as u64 * channels as u64;
+            let loss_uw = output_uw * (100 - EFFICIENCY_PCT as u64) / EFFICIENCY_PCT as u64;
+            let quiescent_uw = QUIESCENT_CURRENT_UA as u64 * op.supply_mv as u64 / 1000;
+            ((loss_uw + quiescent_uw) / 1000) as u32
+        };
+
+        let derate = self.update(dissipation_mw, elapsed);
+
+        let gain = derated_gain(self.nominal_gain, derate, config.compression_ratio);
+        if gain != config.fixed_gain {
+            amp.write_gain_raw(gain)?;
+        }
+        self.written_gain = Some(gain);
+
+        Ok(derate)
+    }
+}
+
+/// Thermal backoff parameters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackoffParams {
+    /// Gain reduction for each thermal fault seen, in dB
+    pub step_db: u8,
+    /// Maximum gain reduction in dB
+    pub max_backoff_db: u8,
+    /// Time between reductions while the fault persists, and from the fault
+    /// clearing to the first restore step, in ms
+    pub settle_ms: u32,
+    /// Gain restored per step once the fault has cleared, in dB
+    pub restore_step_db: u8,
+    /// Time between restore steps in ms
+    pub restore_interval_ms: u32,
+}
+
+impl BackoffParams {
+    /// Parameters backing off `step_db` per fault, up to 24 dB, restoring
+    /// 1 dB every 500 ms starting 2 s after the fault cleared
+    pub const fn new(step_db: u8) -> Self {
+        BackoffParams {
+            step_db,
+            max_backoff_db: 24,
+            settle_ms: 2000,
+            restore_step_db: 1,
+            restore_interval_ms: 500,
+        }
+    }
+}
+
+/// Gain backoff controller driven by the thermal fault flag
+pub struct ThermalBackoff {
+    params: BackoffParams,
+    backoff_db: u8,
+    /// Gain set by the application and the gain we wrote, as register values
+    nominal_gain: u8,
+    written_gain: Option<u8>,
+    /// Time of the last gain change, and whether it was a restore step
+    last_step: Option<(u32, bool)>,
+}
+
+impl ThermalBackoff {
+    pub fn new(params: BackoffParams) -> Self {
+        ThermalBackoff {
+            params,
+            backoff_db: 0,
+            nominal_gain: 0,
+            written_gain: None,
+            last_step: None,
+        }
+    }
+
+    /// Current gain reduction in dB
+    pub fn backoff_db(&self) -> u8 {
+        self.backoff_db
+    }
+
+    /// Update the backoff from the thermal fault flag
+    ///
+    /// Returns the backoff in dB. Does not access the bus.
+    pub fn update(&mut self, thermal: bool, now_ms: u32) -> u8 {
+        let p = self.params;
+        let (elapsed, restoring) = match self.last_step {
+            Some((last, restoring)) => (now_ms.wrapping_sub(last), restoring),
+            None => (u32::MAX, false),
+        };
+
+        if thermal {
+            // A new fault backs off at once, a persisting one every settle_ms
+            let due = self.backoff_db == 0 || restoring || elapsed >= p.settle_ms;
+            if due && self.backoff_db < p.max_backoff_db {
+                self.backoff_db = self
+                    .backoff_db
+                    .saturating_add(p.step_db)
+                    .min(p.max_backoff_db);
+                self.last_step = Some((now_ms, false));
+            }
+        } else if self.backoff_db > 0 {
+            let interval = if restoring {
+                p.restore_interval_ms
+            } else {
+                p.settle_ms
+            };
+            if elapsed >= interval {
+                self.backoff_db = self.backoff_db.saturating_sub(p.restore_step_db);
+                self.last_step = Some((now_ms, true));
+            }
+        }
+        self.backoff_db
+    }
+
+    /// Read the fault flags and apply the backoff to the fixed gain
+    ///
+    /// Call periodically. Gain changes made by the application are picked up
+    /// as the new nominal gain. The backoff is applied on top of the gain trim.
+    #[cfg(driver)]
+    pub fn poll<I2C, E, C>(
+        &mut self,
+        amp: &mut Tpa2016d2<I2C>,
+        clock: &mut C,
+    ) -> Result<u8, Error<E>>
+    where
+        I2C: RegisterInterface<Error = E>,
+        C: Clock,
+    {
+        let faults = amp.get_faults()?;
+        let backoff = self.update(faults.thermal, clock.now_ms());
+
+        let config = amp.agc_config();
+        if self.written_gain != Some(config.fixed_gain) {
+            self.nominal_gain = config.fixed_gain;
+        }
+
+        let gain = derated_gain(self.nominal_gain, backoff, config.compression_ratio);
+        if gain != config.fixed_gain {
+            amp.write_gain_raw(gain)?;
+        }
+        self.written_gain = Some(gain);
+
+        Ok(backoff)
+    }
+}
+
+/// The fixed gain register value `nominal` reduced by `derate_db`
+///
+/// The gain is not reduced below 0 dB when compression is in use.
+#[cfg(driver)]
+pub(crate) fn derated_gain(nominal: u8, derate_db: u8, ratio: CompressionRatio) -> u8 {
+    if derate_db == 0 {
+        return nominal;
+    }
+    let min_gain = if ratio == CompressionRatio::Ratio1 {
+        -28
+    } else {
+        0
+    };
+    let nominal_db = fixed_gain_db(nominal).unwrap_or(0);
+    fixed_gain_code((nominal_db - derate_db as i8).max(min_gain.min(nominal_db)))
+}
+
+#[cfg(all(test, feature = "hal"))]
+mod tests {
+    use super::*;
+    use crate::mock::{MockI2c, TestClock};
+
+    fn params() -> ThermalParams {
+        let op = OperatingPoint {
+            supply_mv: 5000,
+            load_ohms: 4,
+            program_level_dbv_x10: 0,
+        };
+        let mut p = ThermalParams::new(200, 25, op);
+        p.time_constant_ms = 1000;
+        p
+    }
+
+    #[test]
+    fn heats_towards_steady_state() {
+        let mut model = ThermalModel::new(params());
+        // 500 mW * 200 °C/W = 100 °C over ambient
+        model.update(500, 1000);
+        assert_eq!(model.die_temp_c(), 75);
+        for _ in 0..20 {
+            model.update(500, 1000);
+        }
+        assert_eq!(model.die_temp_c(), 124);
+        assert_eq!(model.derating_db(), 3);
+    }
+
+    #[test]
+    fn zero_time_constant() {
+        let mut p = params();
+        p.time_constant_ms = 0;
+        let mut model = ThermalModel::new(p);
+
+        model.update(500, 0);
+        assert_eq!(model.die_temp_c(), 25);
+        model.update(500, 1);
+        assert_eq!(model.die_temp_c(), 125);
+    }
+
+    #[test]
+    fn derates_and_restores_gain() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        amp.gain(24).unwrap();
+        let mut model = ThermalModel::new(params());
+        let mut clock = TestClock(0);
+
+        model.poll(&mut amp, &mut clock).unwrap();
+        for _ in 0..10 {
+            clock.0 += 1000;
+            model.poll(&mut amp, &mut clock).unwrap();
+        }
+        assert!(model.derating_db() > 0);
+        assert!(amp.agc_config().fixed_gain < 24);
+
+        // Cool down with the speakers off
+        amp.speaker_enable(false, false).unwrap();
+        for _ in 0..20 {
+            clock.0 += 1000;
+            model.poll(&mut amp, &mut clock).unwrap();
+        }
+        assert_eq!(model.derating_db(), 0);
+        assert_eq!(amp.agc_config().fixed_gain, 24);
+    }
+
+    #[test]
+    fn backs_off_on_thermal_fault() {
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        amp.gain(24).unwrap();
+        let mut backoff = ThermalBackoff::new(BackoffParams::new(6));
+        let mut clock = TestClock(0);
+
+        amp.bus_mut().regs[1] |= 1 << 2;
+        assert_eq!(backoff.poll(&mut amp, &mut clock).unwrap(), 6);
+        clock.0 = 1000;
+        assert_eq!(backoff.poll(&mut amp, &mut clock).unwrap(), 6);
+        clock.0 = 2000;
+        assert_eq!(backoff.poll(&mut amp, &mut clock).unwrap(), 12);
+        assert_eq!(amp.agc_config().fixed_gain, 12);
+
+        amp.bus_mut().regs[1] &= !(1 << 2);
+        clock.0 = 3000;
+        assert_eq!(backoff.poll(&mut amp, &mut clock).unwrap(), 12);
+        clock.0 = 4000;
+        assert_eq!(backoff.poll(&mut amp, &mut clock).unwrap(), 11);
+        for _ in 0..11 {
+            clock.0 += 500;
+            backoff.poll(&mut amp, &mut clock).unwrap();
+        }
+        assert_eq!(backoff.backoff_db(), 0);
+        assert_eq!(amp.agc_config().fixed_gain, 24);
+    }
+}
@@ -0,0 +1,123 @@
+//! Bus bandwidth throttling
+//!
+//! On a bus shared with time critical devices, background work such as fault
+//! supervision should not take more than its share of the bus. A
+//! [`TokenBucket`] allows bursts of up to `capacity` transactions and refills
+//! one token per `interval_ms`:
+//!
+//! ```
+//! # use tpa2016d2::control::FakeAmp;
+//! # struct Ticks(u32);
+//! # impl tpa2016d2::Clock for Ticks { fn now_ms(&mut self) -> u32 { self.0 } }
+//! use tpa2016d2::fault::{FaultCallbacks, FaultMonitor};
+//! use tpa2016d2::throttle::TokenBucket;
+//!
+//! # let mut amp = FakeAmp::new();
+//! # let mut clock = Ticks(0);
+//! // At most 2 transactions in a burst and 10 per second on average
+//! let mut bucket = TokenBucket::new(2, 100);
+//! let mut monitor = FaultMonitor::new();
+//! let mut callbacks = FaultCallbacks::new(|_| {}, |_| {});
+//!
+//! // From a tight loop, skipped while out of tokens
+//! monitor.poll_throttled(&mut amp, &mut callbacks, &mut bucket, &mut clock)?;
+//! # Ok::<(), tpa2016d2::control::FakeError>(())
+//! ```
+
+use crate::Clock;
+
+/// Token bucket rate limiter for bus transactions
+#[derive(Clone, Copy, Debug)]
+pub struct TokenBucket {
+    capacity: u16,
+    tokens: u16,
+    interval_ms: u32,
+    last_ms: Option<u32>,
+}
+
+impl TokenBucket {
+    /// A full bucket of `capacity` tokens, refilled with one token every `interval_ms`
+    pub const fn new(capacity: u16, interval_ms: u32) -> Self {
+        TokenBucket {
+            capacity,
+            tokens: capacity,
+            interval_ms,
+            last_ms: None,
+        }
+    }
+
+    /// Tokens available at `now_ms`
+    pub fn available(&mut self, now_ms: u32) -> u16 {
+        self.refill(now_ms);
+        self.tokens
+    }
+
+    /// Take `count` tokens if available at `now_ms`
+    pub fn try_take(&mut self, now_ms: u32, count: u16) -> bool {
+        self.refill(now_ms);
+        if self.tokens < count {
+            return false;
+        }
+        self.tokens -= count;
+        true
+    }
+
+    /// Take `count` tokens if available now
+    pub fn try_take_now<C: Clock>(&mut self, clock: &mut C, count: u16) -> bool {
+        let now = clock.now_ms();
+        self.try_take(now, count)
+    }
+
+    fn refill(&mut self, now_ms: u32) {
+        let last = match self.last_ms {
+            Some(last) => last,
+            None => {
+                self.last_ms = Some(now_ms);
+                return;
+            }
+        };
+        if self.interval_ms == 0 {
+            self.tokens = self.capacity;
+            self.last_ms = Some(now_ms);
+            return;
+        }
+
+        let new_tokens = now_ms.wrapping_sub(last) / self.interval_ms;
+        if new_tokens > 0 {
+            let tokens = (self.tokens as u32 + new_tokens).min(self.capacity as u32);
+            self.tokens = tokens as u16;
+            // Keep the remainder so the average rate is exact
+            self.last_ms = Some(last.wrapping_add(new_tokens * self.interval_ms));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_and_refill() {
+        let mut bucket = TokenBucket::new(2, 100);
+
+        assert!(bucket.try_take(0, 1));
+        assert!(bucket.try_take(10, 1));
+        assert!(!bucket.try_take(20, 1));
+
+        assert!(!bucket.try_take(99, 1));
+        assert!(bucket.try_take(150, 1));
+        assert!(!bucket.try_take(199, 1));
+        assert!(bucket.try_take(200, 1));
+
+        assert_eq!(bucket.available(10_000), 2);
+        assert!(!bucket.try_take(10_000, 3));
+    }
+
+    #[test]
+    fn wrapping_time() {
+        let mut bucket = TokenBucket::new(1, 100);
+        assert!(bucket.try_take(u32::MAX - 50, 1));
+        assert!(!bucket.try_take(u32::MAX, 1));
+        assert!(bucket.try_take(60, 1));
+    }
+}
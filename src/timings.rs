@@ -0,0 +1,24 @@
+//! Timing figures from the datasheet
+//!
+//! Named delays for power-up and enable sequencing. The values are the
+//! datasheet figures rounded up to whole milliseconds.
+//!
+//! ```
+//! use tpa2016d2::{sequence::Step, timings, ConfigChange};
+//!
+//! static WAKE_UP: &[Step] = &[
+//!     Step::Write(ConfigChange::SpeakerEnable { left: true, right: true }),
+//!     Step::WaitMs(timings::ENABLE_SETTLING_MS),
+//!     Step::WaitFaultClear,
+//! ];
+//! ```
+
+/// Start-up time of the device after the supply is applied or SDZ is
+/// released, before it responds on the I2C bus, in ms
+pub const POWER_UP_MS: u32 = 5;
+
+/// Wake-up time when leaving software shutdown, in ms
+pub const SWS_WAKE_UP_MS: u32 = 5;
+
+/// Time for the outputs to settle after the speakers are enabled, in ms
+pub const ENABLE_SETTLING_MS: u32 = 5;
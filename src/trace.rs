@@ -0,0 +1,101 @@
+//! Tracing of the register accesses
+//!
+//! Requires the `trace` feature. The driver logs every register it reads or
+//! writes at trace level, with the register name, the value last seen on the
+//! bus and the new value:
+//!
+//! ```text
+//! write FixedGain 0x06 -> 0x0c
+//! read Control 0xc3 -> 0xd3
+//! ```
+//!
+//! The records go to `defmt` with the `defmt` feature, to `log` otherwise.
+
+use crate::regmap::{self, Register};
+
+/// The register values last seen on the bus
+#[derive(Clone, Debug)]
+pub(crate) struct Tracer {
+    regs: [u8; 7],
+}
+
+impl Tracer {
+    pub(crate) fn new() -> Self {
+        Tracer {
+            regs: regmap::POWER_ON,
+        }
+    }
+
+    /// `values` were read from the registers from `reg` on
+    pub(crate) fn read(&mut self, reg: Register, values: &[u8]) {
+        self.record("read", reg, values);
+    }
+
+    /// `values` were written to the registers from `reg` on
+    pub(crate) fn write(&mut self, reg: Register, values: &[u8]) {
+        self.record("write", reg, values);
+    }
+
+    fn record(&mut self, op: &'static str, first: Register, values: &[u8]) {
+        let first = first.addr() as usize - 1;
+        for (&reg, &new) in Register::ALL[first..].iter().zip(values) {
+            let old = core::mem::replace(&mut self.regs[reg.addr() as usize - 1], new);
+            #[cfg(feature = "defmt")]
+            defmt::trace!(
+                "{=str} {=str} {=u8:#04x} -> {=u8:#04x}",
+                op,
+                reg.name(),
+                old,
+                new
+            );
+            #[cfg(not(feature = "defmt"))]
+            log::trace!("{} {} {:#04x} -> {:#04x}", op, reg.name(), old, new);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "hal", not(feature = "defmt")))]
+mod tests {
+    extern crate std;
+    use std::string::{String, ToString};
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    use crate::mock::MockI2c;
+    use crate::Tpa2016d2;
+
+    static RECORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Logger;
+
+    impl log::Log for Logger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            RECORDS.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn records() {
+        log::set_logger(&Logger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut amp = Tpa2016d2::new(MockI2c::new());
+        amp.gain(12).unwrap();
+        amp.gain(6).unwrap();
+        amp.get_faults().unwrap();
+        assert_eq!(
+            *RECORDS.lock().unwrap(),
+            [
+                "write FixedGain 0x06 -> 0x0c",
+                "write FixedGain 0x0c -> 0x06",
+                "read Control 0xc3 -> 0xc3",
+            ]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! Driver with the software shutdown state in the type
+//!
+//! [`Amplifier`] wraps [`Tpa2016d2`] and only offers the audio path setters
+//! while the device is [`Enabled`]. A device in [`Shutdown`] can read its
+//! faults and be enabled, nothing else, so configuring a device that is
+//! still in software shutdown is caught at compile time.
+//!
+//! ```
+//! use tpa2016d2::typestate::{Amplifier, Shutdown};
+//! use tpa2016d2::Tpa2016d2;
+//!
+//! # fn run<I2C, E>(amp: Tpa2016d2<I2C>) -> Result<(), tpa2016d2::Error<E>>
+//! # where
+//! #     I2C: tpa2016d2::RegisterInterface<Error = E>,
+//! # {
+//! let amp: Amplifier<_, Shutdown> = Amplifier::new(amp)?;
+//! let mut amp = amp.enable().map_err(|(_, err)| err)?;
+//! amp.gain(12)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The transitions return the amplifier in its previous state along with the
+//! error when the write fails.
+
+use core::marker::PhantomData;
+
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::{
+    AgcConfig, AgcPreset, CompressionRatio, Config, ConfigPatch, Error, Faults, NoiseGateThreshold,
+    RegisterInterface, Tpa2016d2,
+};
+
+/// The device is in software shutdown
+pub struct Shutdown;
+
+/// The device is powered and outputs audio
+pub struct Enabled;
+
+/// A [`Tpa2016d2`] in the software shutdown state `S`
+pub struct Amplifier<I2C, S> {
+    amp: Tpa2016d2<I2C>,
+    _state: PhantomData<S>,
+}
+
+impl<I2C, S> Amplifier<I2C, S> {
+    fn wrap(amp: Tpa2016d2<I2C>) -> Self {
+        Amplifier {
+            amp,
+            _state: PhantomData,
+        }
+    }
+
+    /// The wrapped driver, for the getters
+    pub fn driver(&self) -> &Tpa2016d2<I2C> {
+        &self.amp
+    }
+
+    /// Release the wrapped driver
+    pub fn into_inner(self) -> Tpa2016d2<I2C> {
+        self.amp
+    }
+}
+
+impl<I2C, E, S> Amplifier<I2C, S>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    pub fn get_faults(&mut self) -> Result<Faults, Error<E>> {
+        self.amp.get_faults()
+    }
+}
+
+impl<I2C, E> Amplifier<I2C, Shutdown>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    /// Put the device in software shutdown
+    pub fn new(mut amp: Tpa2016d2<I2C>) -> Result<Self, Error<E>> {
+        amp.disable_device()?;
+        Ok(Self::wrap(amp))
+    }
+
+    /// Wake the device from software shutdown
+    ///
+    /// The device needs [`timings::SWS_WAKE_UP_MS`](crate::timings::SWS_WAKE_UP_MS)
+    /// before it outputs audio.
+    // The amplifier is returned by value, there is no allocator to box it
+    #[allow(clippy::result_large_err)]
+    pub fn enable(mut self) -> Result<Amplifier<I2C, Enabled>, (Self, Error<E>)> {
+        match self.amp.enable_device() {
+            Ok(()) => Ok(Amplifier::wrap(self.amp)),
+            Err(err) => Err((self, err)),
+        }
+    }
+}
+
+macro_rules! forward {
+    ($($(#[$attr:meta])* fn $name:ident(&mut self $(, $arg:ident: $ty:ty)*);)*) => {
+        $(
+            $(#[$attr])*
+            pub fn $name(&mut self $(, $arg: $ty)*) -> Result<(), Error<E>> {
+                self.amp.$name($($arg),*)
+            }
+        )*
+    };
+}
+
+impl<I2C, E> Amplifier<I2C, Enabled>
+where
+    I2C: RegisterInterface<Error = E>,
+{
+    /// Take a driver for a device known to be enabled, without accessing it
+    pub fn assume_enabled(amp: Tpa2016d2<I2C>) -> Self {
+        Self::wrap(amp)
+    }
+
+    /// Put the device in software shutdown
+    #[allow(clippy::result_large_err)]
+    pub fn shutdown(mut self) -> Result<Amplifier<I2C, Shutdown>, (Self, Error<E>)> {
+        match self.amp.disable_device() {
+            Ok(()) => Ok(Amplifier::wrap(self.amp)),
+            Err(err) => Err((self, err)),
+        }
+    }
+
+    forward! {
+        /// See [`Tpa2016d2::speaker_enable`]
+        fn speaker_enable(&mut self, left: bool, right: bool);
+        /// See [`Tpa2016d2::mute`]
+        fn mute(&mut self);
+        /// See [`Tpa2016d2::unmute`]
+        fn unmute(&mut self);
+        /// See [`Tpa2016d2::gain`]
+        fn gain(&mut self, gain: u8);
+        /// See [`Tpa2016d2::gain_db`]
+        fn gain_db(&mut self, db: i8);
+        /// See [`Tpa2016d2::set_noise_gate`]
+        fn set_noise_gate(&mut self, enable: bool);
+        /// See [`Tpa2016d2::noise_gate_threshold`]
+        fn noise_gate_threshold(&mut self, val: NoiseGateThreshold);
+        /// See [`Tpa2016d2::set_attack_time`]
+        fn set_attack_time(&mut self, val: u8);
+        /// See [`Tpa2016d2::set_release_time`]
+        fn set_release_time(&mut self, val: u8);
+        /// See [`Tpa2016d2::set_hold_time`]
+        fn set_hold_time(&mut self, val: u8);
+        /// See [`Tpa2016d2::output_limiter_level`]
+        fn output_limiter_level(&mut self, val: u8);
+        /// See [`Tpa2016d2::output_limiter`]
+        fn output_limiter(&mut self, enable: bool);
+        /// See [`Tpa2016d2::compression_ratio`]
+        fn compression_ratio(&mut self, ratio: CompressionRatio);
+        /// See [`Tpa2016d2::set_max_gain`]
+        fn set_max_gain(&mut self, val: u8);
+        /// See [`Tpa2016d2::set_agc_preset`]
+        fn set_agc_preset(&mut self, preset: AgcPreset);
+        /// See [`Tpa2016d2::set_agc_config`]
+        fn set_agc_config(&mut self, config: &AgcConfig);
+        /// See [`Tpa2016d2::apply_config`]
+        fn apply_config(&mut self, config: &Config);
+        /// See [`Tpa2016d2::apply_patch`]
+        fn apply_patch(&mut self, patch: &ConfigPatch);
+    }
+
+    /// See [`Tpa2016d2::fade_to`]
+    pub fn fade_to<D: DelayMs<u32>>(
+        &mut self,
+        gain: u8,
+        step_ms: u32,
+        delay: &mut D,
+    ) -> Result<(), Error<E>> {
+        self.amp.fade_to(gain, step_ms, delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockI2c;
+
+    #[test]
+    fn transitions() {
+        let amp = Amplifier::new(Tpa2016d2::new(MockI2c::new())).unwrap();
+        assert!(amp.driver().is_shutdown());
+
+        let mut amp = amp.enable().map_err(|(_, err)| err).unwrap();
+        amp.gain(12).unwrap();
+        let amp = amp.shutdown().map_err(|(_, err)| err).unwrap();
+
+        let mut i2c = amp.into_inner().release();
+        assert_eq!(i2c.writes, [(1, 0xE3), (1, 0xC3), (5, 12), (1, 0xE3)]);
+
+        i2c.fail = true;
+        let amp = Amplifier::assume_enabled(Tpa2016d2::new(i2c));
+        let (_, err) = amp.shutdown().err().unwrap();
+        assert_eq!(err, Error::I2c(crate::mock::MockError));
+    }
+}
@@ -0,0 +1,203 @@
+//! User volume mapping
+//!
+//! [`Volume`] maps a user volume of 0 to a number of steps, e.g. 0 to 100
+//! percent, onto the fixed gain in a dB range, and optionally the max gain
+//! in a dB range of its own. Volume 0 is the mute position:
+//!
+//! ```
+//! # use tpa2016d2::control::FakeAmp;
+//! use tpa2016d2::volume::Volume;
+//!
+//! # let mut amp = FakeAmp::new();
+//! let mut volume = Volume::percent(0, 24).max_gain(18, 30);
+//!
+//! // From the volume knob
+//! volume.set(&mut amp, 75)?;
+//! # Ok::<(), tpa2016d2::control::FakeError>(())
+//! ```
+//!
+//! Tracking the max gain keeps the AGC from boosting quiet passages back up
+//! at low volumes. With compression enabled the fixed gain must be at least
+//! 0 dB, so use a non-negative minimum gain in that case.
+
+use crate::agc::fixed_gain_code;
+use crate::avrcp::div_round;
+use crate::limits::{MAX_FIXED_GAIN_DB, MAX_GAIN_DB_RANGE, MIN_FIXED_GAIN_DB};
+use crate::Tpa2016Control;
+
+/// Mapping from a user volume to the fixed gain and max gain
+#[derive(Clone, Copy, Debug)]
+pub struct Volume {
+    steps: u8,
+    min_db: i8,
+    max_db: i8,
+    max_gain: Option<(i8, i8)>,
+    mute_at_zero: bool,
+    last: Option<u8>,
+}
+
+impl Volume {
+    /// Map volume 1 to `min_db` and `steps` to `max_db`, clamped to -28 to 30 dB
+    pub fn new(steps: u8, min_db: i8, max_db: i8) -> Self {
+        Volume {
+            steps: steps.max(1),
+            min_db: min_db.clamp(MIN_FIXED_GAIN_DB, MAX_FIXED_GAIN_DB),
+            max_db: max_db.clamp(MIN_FIXED_GAIN_DB, MAX_FIXED_GAIN_DB),
+            max_gain: None,
+            mute_at_zero: true,
+            last: None,
+        }
+    }
+
+    /// A volume in percent, 0 to 100
+    pub fn percent(min_db: i8, max_db: i8) -> Self {
+        Self::new(100, min_db, max_db)
+    }
+
+    /// Also map the volume onto the max gain, from `min_db` to `max_db`
+    ///
+    /// Clamped to 18 to 30 dB. The max gain is left as is by default.
+    pub fn max_gain(mut self, min_db: i8, max_db: i8) -> Self {
+        let range = MAX_GAIN_DB_RANGE;
+        self.max_gain = Some((
+            min_db.clamp(*range.start(), *range.end()),
+            max_db.clamp(*range.start(), *range.end()),
+        ));
+        self
+    }
+
+    /// Disable the speakers at volume 0 instead of using the minimum gain
+    ///
+    /// Enabled by default. Both speakers are enabled again on the next non-zero
+    /// volume.
+    pub fn mute_at_zero(mut self, mute: bool) -> Self {
+        self.mute_at_zero = mute;
+        self
+    }
+
+    /// The highest volume
+    pub fn steps(&self) -> u8 {
+        self.steps
+    }
+
+    /// The last applied volume
+    pub fn volume(&self) -> Option<u8> {
+        self.last
+    }
+
+    /// The fixed gain in dB for `volume`
+    pub fn gain_db(&self, volume: u8) -> i8 {
+        self.map(volume, self.min_db, self.max_db)
+    }
+
+    /// The max gain in dB for `volume`, if mapped
+    pub fn max_gain_db(&self, volume: u8) -> Option<i8> {
+        self.max_gain
+            .map(|(min_db, max_db)| self.map(volume, min_db, max_db))
+    }
+
+    /// Apply `volume` to the amplifier
+    ///
+    /// Returns false if the volume is unchanged and nothing was written.
+    pub fn set<A: Tpa2016Control>(&mut self, amp: &mut A, volume: u8) -> Result<bool, A::Error> {
+        let volume = volume.min(self.steps);
+        if self.last == Some(volume) {
+            return Ok(false);
+        }
+
+        if self.mute_at_zero && volume == 0 {
+            amp.speaker_enable(false, false)?;
+        } else {
+            if self.mute_at_zero && self.last == Some(0) {
+                amp.speaker_enable(true, true)?;
+            }
+
+            let gain = fixed_gain_code(self.gain_db(volume));
+            let max_gain = self
+                .max_gain_db(volume)
+                .map(|db| (db - *MAX_GAIN_DB_RANGE.start()) as u8);
+            // Keep the fixed gain below the max gain in between
+            let louder = !matches!(self.last, Some(last) if volume < last);
+            if !louder {
+                amp.gain(gain)?;
+            }
+            if let Some(max_gain) = max_gain {
+                amp.set_max_gain(max_gain)?;
+            }
+            if louder {
+                amp.gain(gain)?;
+            }
+        }
+
+        self.last = Some(volume);
+        Ok(true)
+    }
+
+    /// Linear in dB from `min_db` at volume 1 to `max_db` at the last step
+    ///
+    /// Volume 0 is `min_db` as well.
+    fn map(&self, volume: u8, min_db: i8, max_db: i8) -> i8 {
+        let volume = volume.clamp(1, self.steps) as i32;
+        if self.steps == 1 {
+            return max_db;
+        }
+        let span = max_db as i32 - min_db as i32;
+        (min_db as i32 + div_round(span * (volume - 1), self.steps as i32 - 1)) as i8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::FakeAmp;
+    use crate::ConfigChange;
+
+    #[test]
+    fn mapping() {
+        let volume = Volume::percent(-28, 30).max_gain(18, 30);
+        assert_eq!(volume.gain_db(0), -28);
+        assert_eq!(volume.gain_db(1), -28);
+        assert_eq!(volume.gain_db(50), 1);
+        assert_eq!(volume.gain_db(100), 30);
+        assert_eq!(volume.gain_db(200), 30);
+        assert_eq!(volume.max_gain_db(1), Some(18));
+        assert_eq!(volume.max_gain_db(100), Some(30));
+
+        assert_eq!(Volume::new(10, 0, 100).gain_db(10), 30);
+        assert_eq!(Volume::new(0, 0, 20).gain_db(5), 20);
+        assert_eq!(Volume::new(4, 0, 12).max_gain_db(4), None);
+    }
+
+    #[test]
+    fn set() {
+        let mut amp = FakeAmp::new();
+        let mut volume = Volume::new(4, 0, 12).max_gain(18, 30);
+
+        assert_eq!(volume.set(&mut amp, 4), Ok(true));
+        assert_eq!(volume.set(&mut amp, 4), Ok(false));
+        assert_eq!(volume.set(&mut amp, 1), Ok(true));
+        assert_eq!(volume.set(&mut amp, 0), Ok(true));
+        assert_eq!(volume.set(&mut amp, 2), Ok(true));
+        assert_eq!(volume.volume(), Some(2));
+
+        assert_eq!(
+            amp.calls(),
+            [
+                ConfigChange::MaxGain(12),
+                ConfigChange::Gain(12),
+                ConfigChange::Gain(0),
+                ConfigChange::MaxGain(0),
+                ConfigChange::SpeakerEnable {
+                    left: false,
+                    right: false
+                },
+                ConfigChange::SpeakerEnable {
+                    left: true,
+                    right: true
+                },
+                ConfigChange::MaxGain(4),
+                ConfigChange::Gain(4),
+            ]
+        );
+    }
+}
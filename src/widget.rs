@@ -0,0 +1,167 @@
+//! embedded-graphics status widget
+//!
+//! Requires the `embedded-graphics` feature. [`StatusWidget`] draws the
+//! [`Status`] of the amplifier: the preset name and fault icons on the first
+//! line and a gain bar, or `MUTE`, on the second. It needs at least 64x24
+//! pixels.
+//!
+//! ```ignore
+//! let widget = StatusWidget::new(amp.status(), Point::zero(), Size::new(128, 24),
+//!     BinaryColor::On, BinaryColor::Off);
+//! widget.draw(&mut display)?;
+//! ```
+
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+
+use crate::fault::Fault;
+use crate::Status;
+
+const LINE_HEIGHT: u32 = 10;
+const ICON_WIDTH: u32 = 8;
+const BAR_HEIGHT: u32 = 10;
+
+/// Drawable amplifier status
+pub struct StatusWidget<C> {
+    status: Status,
+    bounds: Rectangle,
+    fg: C,
+    bg: C,
+}
+
+impl<C: PixelColor> StatusWidget<C> {
+    /// Creates a widget drawing `status` in the area given by `top_left` and `size`
+    pub fn new(status: Status, top_left: Point, size: Size, fg: C, bg: C) -> Self {
+        StatusWidget {
+            status,
+            bounds: Rectangle::new(top_left, size),
+            fg,
+            bg,
+        }
+    }
+}
+
+impl<C: PixelColor> Drawable for StatusWidget<C> {
+    type Color = C;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let origin = self.bounds.top_left;
+        let width = self.bounds.size.width;
+        let text = MonoTextStyle::new(&FONT_6X10, self.fg);
+        let inverted = MonoTextStyle::new(&FONT_6X10, self.bg);
+
+        self.bounds
+            .into_styled(PrimitiveStyle::with_fill(self.bg))
+            .draw(target)?;
+
+        // Preset name
+        let name = self.status.preset.map_or("Custom", |p| p.name());
+        Text::with_baseline(name, origin, text, Baseline::Top).draw(target)?;
+
+        // Fault icons, right aligned
+        let mut x = origin.x + width as i32;
+        for &fault in Fault::ALL.iter().rev() {
+            if !fault.is_set(&self.status.faults) {
+                continue;
+            }
+            x -= ICON_WIDTH as i32;
+            let icon = Point::new(x, origin.y);
+            Rectangle::new(icon, Size::new(ICON_WIDTH, LINE_HEIGHT))
+                .into_styled(PrimitiveStyle::with_fill(self.fg))
+                .draw(target)?;
+            let letter = match fault {
+                Fault::ShortCircuitLeft => "L",
+                Fault::ShortCircuitRight => "R",
+                Fault::Thermal => "T",
+            };
+            Text::with_baseline(letter, icon + Point::new(1, 0), inverted, Baseline::Top)
+                .draw(target)?;
+            x -= 1;
+        }
+
+        // Gain bar
+        let bar = Point::new(origin.x, origin.y + LINE_HEIGHT as i32 + 2);
+        if self.status.muted() {
+            Text::with_baseline("MUTE", bar, text, Baseline::Top).draw(target)?;
+        } else {
+            Rectangle::new(bar, Size::new(width, BAR_HEIGHT))
+                .into_styled(PrimitiveStyle::with_stroke(self.fg, 1))
+                .draw(target)?;
+            // -28 dB is an empty bar, 30 dB a full one
+            let level = (self.status.fixed_gain_db as i32 + 28) as u32;
+            let filled = (width - 2) * level / 58;
+            Rectangle::new(bar + Point::new(1, 1), Size::new(filled, BAR_HEIGHT - 2))
+                .into_styled(PrimitiveStyle::with_fill(self.fg))
+                .draw(target)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AgcPreset, Faults};
+    use embedded_graphics::{mock_display::MockDisplay, pixelcolor::BinaryColor};
+
+    fn status() -> Status {
+        Status {
+            speaker_left: true,
+            speaker_right: true,
+            shutdown: false,
+            faults: Faults::default(),
+            fixed_gain_db: 30,
+            preset: Some(AgcPreset::Jazz),
+        }
+    }
+
+    fn draw(status: Status) -> MockDisplay<BinaryColor> {
+        let mut display = MockDisplay::new();
+        display.set_allow_overdraw(true);
+        StatusWidget::new(
+            status,
+            Point::zero(),
+            Size::new(64, 24),
+            BinaryColor::On,
+            BinaryColor::Off,
+        )
+        .draw(&mut display)
+        .unwrap();
+        display
+    }
+
+    #[test]
+    fn full_gain_bar() {
+        let display = draw(status());
+        assert_eq!(display.get_pixel(Point::new(62, 16)), Some(BinaryColor::On));
+    }
+
+    #[test]
+    fn muted() {
+        let mut status = status();
+        status.speaker_left = false;
+        status.speaker_right = false;
+        let display = draw(status);
+        assert_eq!(
+            display.get_pixel(Point::new(62, 16)),
+            Some(BinaryColor::Off)
+        );
+    }
+
+    #[test]
+    fn fault_icon() {
+        let mut status = status();
+        status.faults.thermal = true;
+        let display = draw(status);
+        assert_eq!(display.get_pixel(Point::new(63, 0)), Some(BinaryColor::On));
+    }
+}
@@ -0,0 +1,95 @@
+//! The exact I2C transactions of the driver, checked against embedded-hal-mock
+//!
+//! The device is at address 0x58. Registers are written as the register
+//! address followed by the values, consecutive registers in one transaction
+//! using the auto-increment of the device. Registers are read one at a time,
+//! writing the address followed by a repeated start read.
+
+#![cfg(feature = "hal")]
+
+use embedded_hal_mock::eh0::i2c::{Mock, Transaction};
+use tpa2016d2::{AgcPreset, Config, Error, QuietReconfig, Tpa2016d2};
+
+const ADDR: u8 = 0x58;
+
+fn write(bytes: &[u8]) -> Transaction {
+    Transaction::write(ADDR, bytes.to_vec())
+}
+
+fn read(reg: u8, value: u8) -> Transaction {
+    Transaction::write_read(ADDR, vec![reg], vec![value])
+}
+
+/// Run `f` on a driver and check that it performed exactly `expected`
+fn check(expected: &[Transaction], f: impl FnOnce(&mut Tpa2016d2<Mock>)) {
+    let mut amp = Tpa2016d2::new(Mock::new(expected));
+    f(&mut amp);
+    amp.release().done();
+}
+
+#[test]
+fn sync_reads_every_register() {
+    let expected = [Transaction::write_read(
+        ADDR,
+        vec![1],
+        vec![0xC3, 0x05, 0x0B, 0x00, 0x06, 0x3A, 0xC2],
+    )];
+    check(&expected, |amp| {
+        amp.sync().unwrap();
+        assert_eq!(amp.config(), Config::DEFAULT);
+    });
+}
+
+#[test]
+fn single_register_setters() {
+    let expected = [write(&[5, 12]), write(&[5, 0x3F]), write(&[1, 0x83])];
+    check(&expected, |amp| {
+        amp.gain(12).unwrap();
+        amp.gain_db(-1).unwrap();
+        amp.speaker_enable(false, true).unwrap();
+    });
+}
+
+#[test]
+fn invalid_values_are_not_written() {
+    check(&[], |amp| {
+        assert_eq!(amp.gain(31), Err(Error::InvalidValue));
+        assert_eq!(amp.set_attack_time(64), Err(Error::InvalidValue));
+    });
+}
+
+#[test]
+fn preset_is_one_burst() {
+    let expected = [write(&[2, 0x06, 0x14, 0x00, 0x06, 0x3D, 0xC1])];
+    check(&expected, |amp| {
+        amp.set_agc_preset(AgcPreset::Jazz).unwrap()
+    });
+}
+
+#[test]
+fn apply_config_writes_control_last() {
+    // The muting write to register 1 joins the burst of registers 2 to 7
+    let expected = [
+        write(&[1, 0x03, 0x05, 0x0B, 0x00, 0x06, 0x3A, 0xC2]),
+        write(&[1, 0xC3]),
+    ];
+    check(&expected, |amp| {
+        amp.set_quiet_reconfig(QuietReconfig::Mute);
+        amp.apply_config(&Config::DEFAULT).unwrap();
+    });
+}
+
+#[test]
+fn faults_read_control_register() {
+    let expected = [read(1, 0xC3 | 1 << 2)];
+    check(&expected, |amp| assert!(amp.get_faults().unwrap().thermal));
+}
+
+#[test]
+fn write_verify_reads_back() {
+    let expected = [write(&[5, 12]), read(5, 12)];
+    check(&expected, |amp| {
+        amp.set_write_verify(true);
+        amp.gain(12).unwrap();
+    });
+}